@@ -16,6 +16,28 @@ pub struct VPos {
 
 vulkano::impl_vertex!(VPos, position);
 
+/// The per-instance vertex data of an instanced primitive: the first three rows of its affine
+/// transform this frame and last frame.
+#[derive(Default, Debug, Clone)]
+pub struct InstanceTransform {
+    pub instance_model_0: [f32; 4],
+    pub instance_model_1: [f32; 4],
+    pub instance_model_2: [f32; 4],
+    pub instance_prev_model_0: [f32; 4],
+    pub instance_prev_model_1: [f32; 4],
+    pub instance_prev_model_2: [f32; 4],
+}
+
+vulkano::impl_vertex!(
+    InstanceTransform,
+    instance_model_0,
+    instance_model_1,
+    instance_model_2,
+    instance_prev_model_0,
+    instance_prev_model_1,
+    instance_prev_model_2
+);
+
 #[derive(Debug, Clone)]
 pub struct Transform {
     pub translation: Matrix4<f32>,
@@ -0,0 +1,137 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: Point3::origin(),
+            max: Point3::origin(),
+        }
+    }
+}
+
+impl Aabb {
+    /// The smallest box containing all of the points, or an empty box at the origin if there are
+    /// none.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a [f32; 3]>) -> Self {
+        let mut points = points.into_iter().map(|&p| Point3::from(p));
+
+        let first = match points.next() {
+            Some(first) => first,
+            None => return Self::default(),
+        };
+
+        points.fold(Self { min: first, max: first }, |aabb, p| Self {
+            min: Point3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+            max: Point3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z)),
+        })
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Half the size of the box along each axis.
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    /// The box containing this box after it's transformed.
+    /// See Arvo, "Transforming Axis-Aligned Bounding Boxes".
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let center = transform.transform_point(self.center());
+        let extents = self.extents();
+
+        // Each axis of the new box is extended by the absolute projection of the old extents.
+        let extent = |row: usize| {
+            transform.x[row].abs() * extents.x + transform.y[row].abs() * extents.y + transform.z[row].abs() * extents.z
+        };
+        let extents = Vector3::new(extent(0), extent(1), extent(2));
+
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// A bounding sphere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        Self {
+            center: Point3::origin(),
+            radius: 0.0,
+        }
+    }
+}
+
+impl BoundingSphere {
+    /// A sphere around the centre of the bounding box of the points, containing all of them.
+    /// Tighter than the sphere around the box itself, though not the smallest possible sphere.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a [f32; 3]> + Clone) -> Self {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points
+            .into_iter()
+            .map(|&p| (Point3::from(p) - center).magnitude2())
+            .fold(0.0, f32::max)
+            .sqrt();
+
+        Self { center, radius }
+    }
+
+    /// The smallest sphere containing both spheres.
+    pub fn union(&self, other: &BoundingSphere) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        Self {
+            center: self.center + offset * ((radius - self.radius) / distance),
+            radius,
+        }
+    }
+
+    /// The sphere containing this sphere after it's transformed. Non-uniform scales grow the
+    /// radius by the largest scale.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let scale = transform
+            .x
+            .truncate()
+            .magnitude2()
+            .max(transform.y.truncate().magnitude2())
+            .max(transform.z.truncate().magnitude2())
+            .sqrt();
+
+        Self {
+            center: transform.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
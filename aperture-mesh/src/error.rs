@@ -1,8 +1,35 @@
 use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
 
 #[derive(Debug)]
 pub enum Error {
     MismatchedVerticesNormals,
     NoSuchFile(OsString),
+    /// The file exists, but isn't valid glTF.
+    Gltf(gltf::Error),
+    /// The file exists, but isn't valid OBJ.
+    Obj(tobj::LoadError),
     NoVerticesFound,
+    UnsupportedInstanceData,
+    /// The file isn't glTF or OBJ, going by its extension.
+    UnsupportedFormat(OsString),
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MismatchedVerticesNormals => write!(f, "A primitive has a different number of vertices and normals"),
+            Self::NoSuchFile(path) => write!(f, "No such file: {}", Path::new(path).display()),
+            Self::Gltf(err) => write!(f, "Invalid glTF: {}", err),
+            Self::Obj(err) => write!(f, "Invalid OBJ: {}", err),
+            Self::NoVerticesFound => write!(f, "A primitive has no vertex positions"),
+            Self::UnsupportedInstanceData => write!(f, "The instancing data isn't supported"),
+            Self::UnsupportedFormat(path) => {
+                write!(f, "Unsupported model format: {}", Path::new(path).display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,6 +1,6 @@
 use crate::{
-    material::{ImageFormat, Texture, TextureSet},
-    Error, Material, Mesh, Primitive,
+    material::{AlphaMode, ImageFormat, Texture, TextureSet},
+    Aabb, BoundingSphere, Error, Material, Mesh, Primitive,
 };
 
 use aperture_common::{Transform, VPosNormTex};
@@ -8,23 +8,45 @@ use gltf::mesh::util::ReadTexCoords;
 
 use cgmath::{Matrix4, Quaternion};
 
-use std::{collections::HashSet, fmt::Debug, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
+#[allow(clippy::type_complexity)]
 pub fn load<P>(path: P) -> Result<(Vec<Mesh>, Vec<Material>, Vec<Texture<u8>>), Error>
 where
     P: AsRef<Path> + Clone + Debug,
 {
-    let (document, buffers, images) = gltf::import(path.clone())
-        .map_err(|_| Error::NoSuchFile(path.as_ref().as_os_str().to_owned()))?;
+    if !path.as_ref().is_file() {
+        return Err(Error::NoSuchFile(path.as_ref().as_os_str().to_owned()));
+    }
+    let (document, buffers, images) = gltf::import(path.clone()).map_err(Error::Gltf)?;
 
     let textures = load_textures(&document, &images);
-    let materials = load_materials(&document, &textures);
-    let meshes = load_nodes(&document, &buffers, &materials)?.collect::<Vec<_>>();
+    let mut materials = load_materials(&document, &textures);
+
+    // The renderer needs a material for every primitive, so those without one are given a default
+    // material, after the document's.
+    if document.meshes().flat_map(|m| m.primitives()).any(|p| p.material().index().is_none()) {
+        let names = materials.iter().map(|m| m.name.clone()).collect();
+        let mut material = Material::default();
+        material.name = unique_name(&names, &material.name);
+        materials.push(material);
+    }
+
+    let instancing = load_instancing(path, &document, &buffers)?;
+    let meshes = load_meshes(&document, &buffers, &materials, &instancing)?;
 
     Ok((meshes, materials, textures))
 }
 
 fn load_materials(gltf: &gltf::Document, textures: &[Texture<u8>]) -> Vec<Material> {
+    let mut material_names = HashSet::new();
+
     gltf.materials()
         .map(|m| {
             let mut material = Material::default();
@@ -38,6 +60,15 @@ fn load_materials(gltf: &gltf::Document, textures: &[Texture<u8>]) -> Vec<Materi
             material.metallic_factor = pbr.metallic_factor();
             material.roughness_factor = pbr.roughness_factor();
             material.emissive_factor = m.emissive_factor().into();
+            material.alpha_mode = match m.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            };
+
+            if let Some(alpha_cutoff) = m.alpha_cutoff() {
+                material.alpha_cutoff = alpha_cutoff;
+            }
 
             let mut texture_set = TextureSet::default();
 
@@ -63,7 +94,17 @@ fn load_materials(gltf: &gltf::Document, textures: &[Texture<u8>]) -> Vec<Materi
                 texture_set.ao.replace(textures[i].name.clone());
             }
 
+            if let Some(t) = m.emissive_texture() {
+                let i = t.texture().index();
+                texture_set.emissive.replace(textures[i].name.clone());
+            }
+
             material.textures = texture_set;
+
+            // Primitives refer to their material by name, so unnamed materials are told apart.
+            material.name = unique_name(&material_names, &material.name);
+            material_names.insert(material.name.clone());
+
             material
         })
         .collect()
@@ -104,17 +145,8 @@ fn load_textures(gltf: &gltf::Document, images: &[gltf::image::Data]) -> Vec<Tex
 
         texture.pixels = pixels_u8;
 
-        // FIXME unoptimised
-        let mut count = 1;
-        let mut name = texture.name.clone();
-
-        while texture_names.contains(&name) {
-            name = format!("{}_{}", &texture.name, count.to_string());
-            count += 1;
-        }
-
-        texture_names.insert(name.clone());
-        texture.name = name;
+        texture.name = unique_name(&texture_names, &texture.name);
+        texture_names.insert(texture.name.clone());
 
         textures.push(texture);
     }
@@ -122,6 +154,20 @@ fn load_textures(gltf: &gltf::Document, images: &[gltf::image::Data]) -> Vec<Tex
     textures
 }
 
+/// The name, or the name with the first number suffix not already taken.
+fn unique_name(names: &HashSet<String>, name: &str) -> String {
+    // FIXME unoptimised
+    let mut count = 1;
+    let mut unique = name.to_string();
+
+    while names.contains(&unique) {
+        unique = format!("{}_{}", name, count);
+        count += 1;
+    }
+
+    unique
+}
+
 fn format(f: gltf::image::Format) -> ImageFormat {
     match f {
         gltf::image::Format::R8 => ImageFormat::R8,
@@ -137,34 +183,15 @@ fn format(f: gltf::image::Format) -> ImageFormat {
     }
 }
 
-fn load_nodes<'a>(
-    gltf: &'a gltf::Document,
+fn load_meshes(
+    gltf: &gltf::Document,
     buffers: &[gltf::buffer::Data],
     materials: &[Material],
-) -> Result<impl Iterator<Item = Mesh> + 'a, Error> {
-    Ok(gltf
-        .nodes()
-        .filter(|n| n.mesh().is_some())
-        .map(|n| {
-            let m = n.mesh().unwrap();
-
-            let n_transform = n.transform().decomposed();
-            let transform = Transform {
-                translation: Matrix4::from_translation(n_transform.0.into()),
-                // GLTF quaternions are (x, y, z, w), but cgmath quaternions are (w, x, y, z).
-                rotation: Matrix4::from(Quaternion::new(
-                    n_transform.1[3],
-                    n_transform.1[0],
-                    n_transform.1[1],
-                    n_transform.1[2],
-                )),
-                scale: Matrix4::from_nonuniform_scale(
-                    n_transform.2[0],
-                    n_transform.2[1],
-                    n_transform.2[2],
-                ),
-            };
-
+    instancing: &HashMap<usize, Vec<Transform>>,
+) -> Result<Vec<Mesh>, Error> {
+    let mut meshes = gltf
+        .meshes()
+        .map(|m| {
             let primitives = m
                 .primitives()
                 .map(|p| {
@@ -194,7 +221,7 @@ fn load_nodes<'a>(
                             ReadTexCoords::F32(uv) => uv.collect::<Vec<_>>(),
                         }
                     } else {
-                        vec![]
+                        vec![[0.0, 0.0]; positions.len()]
                     };
 
                     let vertices = positions
@@ -210,24 +237,26 @@ fn load_nodes<'a>(
 
                     let indices = reader
                         .read_indices()
-                        .take()
                         .map_or(vec![], |i| i.into_u32().collect());
 
-                    let mut primitive = Primitive::default();
-                    primitive.vertices = vertices;
-                    primitive.indices = indices;
-
-                    if let Some(index) = p.material().index() {
-                        primitive.material_name = Some(materials[index].name.clone());
-                    }
-
-                    primitive.set_transform(transform.clone());
-
-                    Ok(primitive)
+                    // Primitives without a material use the default one, which is last.
+                    let material = p.material().index().unwrap_or(materials.len() - 1);
+
+                    Ok(Primitive {
+                        material_name: Some(materials[material].name.clone()),
+                        vertices,
+                        indices,
+                        aabb: Aabb::from_points(&positions),
+                        bounding_sphere: BoundingSphere::from_points(&positions),
+                        ..Default::default()
+                    })
                 })
                 .collect::<Result<Vec<_>, Error>>()?;
 
-            let mut mesh = Mesh::default();
+            let mut mesh = Mesh {
+                instances: vec![],
+                ..Default::default()
+            };
             if let Some(name) = m.name() {
                 mesh.name = name.to_string();
             }
@@ -238,6 +267,189 @@ fn load_nodes<'a>(
 
             Ok(mesh)
         })
-        .collect::<Result<Vec<_>, Error>>()?
-        .into_iter())
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // Every node using a mesh adds an instance of it, or one for each of the node's instances
+    // with EXT_mesh_gpu_instancing.
+    for n in gltf.nodes() {
+        let m = match n.mesh() {
+            Some(m) => m,
+            None => continue,
+        };
+
+        let (translation, rotation, scale) = n.transform().decomposed();
+        let node_transform = transform(translation, rotation, scale);
+
+        let instances = match instancing.get(&n.index()) {
+            Some(instances) => instances
+                .iter()
+                .map(|instance| Transform {
+                    // The instance transforms are relative to the node, so the node's transform
+                    // is applied after the instance's translation.
+                    translation: node_transform.compose() * instance.translation,
+                    ..instance.clone()
+                })
+                .collect(),
+            None => vec![node_transform],
+        };
+
+        meshes[m.index()]
+            .instances
+            .extend(instances.into_iter().map(|instance| Arc::new(Mutex::new(instance))));
+    }
+
+    // Meshes no node uses aren't part of the scene.
+    meshes.retain(|mesh| !mesh.instances.is_empty());
+
+    Ok(meshes)
+}
+
+/// Reads the instance transforms of the nodes using EXT_mesh_gpu_instancing, by node index.
+/// The gltf crate doesn't parse the extension, so it's read from the JSON itself.
+fn load_instancing<P>(
+    path: P,
+    gltf: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> Result<HashMap<usize, Vec<Transform>>, Error>
+where
+    P: AsRef<Path>,
+{
+    let bytes = fs::read(path.as_ref()).map_err(|_| Error::NoSuchFile(path.as_ref().as_os_str().to_owned()))?;
+    let json = match gltf::Glb::from_slice(&bytes) {
+        Ok(glb) => serde_json::from_slice::<serde_json::Value>(&glb.json),
+        Err(_) => serde_json::from_slice::<serde_json::Value>(&bytes),
+    };
+    let json = match json {
+        Ok(json) => json,
+        // The document has already been parsed, so this can't happen for a valid file.
+        Err(_) => return Ok(HashMap::new()),
+    };
+
+    let nodes = match json["nodes"].as_array() {
+        Some(nodes) => nodes,
+        None => return Ok(HashMap::new()),
+    };
+
+    let mut instancing = HashMap::new();
+
+    for (index, node) in nodes.iter().enumerate() {
+        let attributes = &node["extensions"]["EXT_mesh_gpu_instancing"]["attributes"];
+        if !attributes.is_object() {
+            continue;
+        }
+
+        let translations = read_instance_attribute::<[f32; 3]>(gltf, buffers, &attributes["TRANSLATION"])?;
+        let rotations = read_instance_attribute::<[f32; 4]>(gltf, buffers, &attributes["ROTATION"])?;
+        let scales = read_instance_attribute::<[f32; 3]>(gltf, buffers, &attributes["SCALE"])?;
+
+        // Every attribute must have a value for each instance.
+        let counts = [
+            translations.as_ref().map(Vec::len),
+            rotations.as_ref().map(Vec::len),
+            scales.as_ref().map(Vec::len),
+        ];
+        let count = counts.iter().flatten().copied().max().unwrap_or(0);
+        if counts.iter().flatten().any(|&c| c != count) {
+            return Err(Error::UnsupportedInstanceData);
+        }
+
+        let instances = (0..count)
+            .map(|i| {
+                let translation = translations.as_ref().map_or([0.0; 3], |t| t[i]);
+                let rotation = rotations.as_ref().map_or([0.0, 0.0, 0.0, 1.0], |r| r[i]);
+                let scale = scales.as_ref().map_or([1.0; 3], |s| s[i]);
+
+                transform(translation, rotation, scale)
+            })
+            .collect();
+
+        instancing.insert(index, instances);
+    }
+
+    Ok(instancing)
+}
+
+/// Reads the values of an instance attribute, given the index of its accessor, if it's present.
+/// Only float attributes are supported.
+fn read_instance_attribute<T: gltf::accessor::Item>(
+    gltf: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    accessor_index: &serde_json::Value,
+) -> Result<Option<Vec<T>>, Error> {
+    let accessor_index = match accessor_index.as_u64() {
+        Some(accessor_index) => accessor_index as usize,
+        None => return Ok(None),
+    };
+
+    let accessor = gltf.accessors().nth(accessor_index).ok_or(Error::UnsupportedInstanceData)?;
+    if accessor.data_type() != gltf::accessor::DataType::F32 {
+        return Err(Error::UnsupportedInstanceData);
+    }
+
+    let iter = gltf::accessor::Iter::<T>::new(accessor, |buffer| Some(buffers[buffer.index()].0.as_slice()))
+        .ok_or(Error::UnsupportedInstanceData)?;
+
+    Ok(Some(iter.collect()))
+}
+
+/// A transform from a glTF translation, rotation and scale.
+fn transform(translation: [f32; 3], rotation: [f32; 4], scale: [f32; 3]) -> Transform {
+    Transform {
+        translation: Matrix4::from_translation(translation.into()),
+        // GLTF quaternions are (x, y, z, w), but cgmath quaternions are (w, x, y, z).
+        rotation: Matrix4::from(Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2])),
+        scale: Matrix4::from_nonuniform_scale(scale[0], scale[1], scale[2]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A triangle drawn by two primitives: the first with one of two unnamed materials, and the
+    /// second with none.
+    const DOCUMENT: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "accessors": [{
+            "bufferView": 0,
+            "componentType": 5126,
+            "count": 3,
+            "type": "VEC3",
+            "min": [0, 0, 0],
+            "max": [1, 1, 0]
+        }],
+        "materials": [{}, { "emissiveFactor": [1, 1, 1] }],
+        "meshes": [{
+            "primitives": [
+                { "attributes": { "POSITION": 0 }, "material": 0 },
+                { "attributes": { "POSITION": 0 } }
+            ]
+        }],
+        "nodes": [{ "mesh": 0 }],
+        "scenes": [{ "nodes": [0] }]
+    }"#;
+
+    #[test]
+    fn primitives_without_a_material_use_a_default_one() {
+        let path = std::env::temp_dir().join(format!("aperture-mesh-{}.gltf", std::process::id()));
+        fs::write(&path, DOCUMENT).unwrap();
+        let loaded = load(path.clone());
+        fs::remove_file(&path).unwrap();
+        let (meshes, materials, _) = loaded.unwrap();
+
+        let names = materials.iter().map(|m| m.name.as_str()).collect::<HashSet<_>>();
+        assert_eq!(names.len(), 3, "the names aren't unique: {:?}", names);
+
+        let primitive_material = |index: usize| {
+            let name = meshes[0].primitives[index].material_name.as_ref().expect("a primitive has no material");
+            materials.iter().find(|m| &m.name == name).unwrap()
+        };
+        assert_eq!(primitive_material(0).emissive_factor, Material::default().emissive_factor);
+        assert_eq!(primitive_material(1).name, materials.last().unwrap().name);
+    }
 }
@@ -3,19 +3,24 @@ use aperture_common::{Transform, VPosNormTex};
 use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
 
+mod bounds;
 mod error;
 mod material;
-mod obj;
 
 pub mod gltf;
+pub mod obj;
 
+pub use bounds::{Aabb, BoundingSphere};
 pub use error::Error;
-pub use material::{ImageFormat, Material, Texture, TextureSet};
+pub use material::{AlphaMode, ImageFormat, Material, Texture, TextureSet};
 
 #[derive(Debug)]
 pub struct Mesh {
     pub name: String,
     pub primitives: Vec<Primitive>,
+    /// Where each copy of the mesh is placed. Every primitive is drawn once per instance, after
+    /// its own transform is applied.
+    pub instances: Vec<Arc<Mutex<Transform>>>,
 }
 
 impl Default for Mesh {
@@ -23,6 +28,7 @@ impl Default for Mesh {
         Self {
             name: "Unnamed".to_string(),
             primitives: vec![],
+            instances: vec![Arc::new(Mutex::new(Transform::identity()))],
         }
     }
 }
@@ -37,9 +43,14 @@ impl Mesh {
 #[derive(Debug, Default)]
 pub struct Primitive {
     pub index: usize,
+    /// The loaders give every primitive a material, adding a default one if they need it.
     pub material_name: Option<String>,
     pub vertices: Vec<VPosNormTex>,
     pub indices: Vec<u32>,
+    /// The bounds of the vertices, in model space.
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    /// The transform of the primitive within its mesh.
     pub transform: Arc<Mutex<Transform>>,
 }
 
@@ -8,6 +8,9 @@ pub struct Material {
     pub roughness_factor: f32,
     pub reflectance: f32,
     pub emissive_factor: Vector3<f32>,
+    pub alpha_mode: AlphaMode,
+    /// The alpha below which fragments are discarded, when the alpha mode is `Mask`.
+    pub alpha_cutoff: f32,
     pub textures: TextureSet,
 }
 
@@ -20,17 +23,33 @@ impl Default for Material {
             roughness_factor: 0.4,
             reflectance: 0.5,
             emissive_factor: Vector3::new(0.0, 0.0, 0.0),
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
             textures: TextureSet::default(),
         }
     }
 }
 
+/// How the alpha of the base colour is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// The alpha is ignored, and the material is fully opaque.
+    Opaque,
+    /// The material is either fully opaque or fully transparent, depending on whether the alpha
+    /// is above the cutoff.
+    Mask,
+    /// The material is blended with whatever is behind it.
+    Blend,
+}
+
 #[derive(Debug, Default)]
 pub struct TextureSet {
     pub base_color: Option<String>,
     pub normal: Option<String>,
     pub metallic_roughness: Option<String>,
     pub ao: Option<String>,
+    /// Multiplied by the emissive factor, as in glTF.
+    pub emissive: Option<String>,
 }
 
 #[derive(Debug)]
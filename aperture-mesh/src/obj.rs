@@ -1,79 +1,225 @@
-// use crate::{Error, Mesh};
-
-// use renderer_common::VPosNorm;
-
-// use cgmath::{InnerSpace, Vector3};
-
-// use std::{fmt::Debug, path::Path};
-
-// pub fn load<P>(path: P) -> Result<Mesh, Error>
-// where
-//     P: AsRef<Path> + Clone + Debug,
-// {
-//     let model = tobj::load_obj(
-//         path.clone(),
-//         &tobj::LoadOptions {
-//             single_index: true,
-//             triangulate: true,
-//             ..Default::default()
-//         },
-//     );
-
-//     let (models, _) = match model {
-//         Ok(result) => result,
-//         Err(_) => Err(Error::LoadError(path.as_ref().as_os_str().to_owned()))?,
-//     };
-
-//     let mesh = &models.first().unwrap().mesh;
-
-//     let positions = (0..mesh.positions.len() / 3)
-//         .map(|i| {
-//             [
-//                 mesh.positions[i * 3],
-//                 mesh.positions[i * 3 + 1],
-//                 mesh.positions[i * 3 + 2],
-//             ]
-//         })
-//         .collect::<Vec<_>>();
-
-//     let mut normals = vec![[0f32; 3]; positions.len()];
-//     if !mesh.normals.is_empty() {
-//         for i in 0..mesh.normals.len() / 3 {
-//             let n = [
-//                 mesh.normals[i * 3],
-//                 mesh.normals[i * 3 + 1],
-//                 mesh.normals[i * 3 + 2],
-//             ];
-
-//             normals[i] = n;
-//         }
-//     } else {
-//         for i in 0..mesh.indices.len() / 3 {
-//             let a = positions[mesh.indices[i * 3] as usize];
-//             let b = positions[mesh.indices[i * 3 + 1] as usize];
-//             let c = positions[mesh.indices[i * 3 + 2] as usize];
-
-//             let v_a = Vector3::new(a[0], a[1], a[2]);
-//             let v_b = Vector3::new(b[0], b[1], b[2]);
-//             let v_c = Vector3::new(c[0], c[1], c[2]);
-
-//             let n = (v_b - v_a).cross(v_c - v_a).normalize();
-
-//             normals[mesh.indices[i * 3] as usize] = [n.x, n.y, n.z];
-//             normals[mesh.indices[i * 3 + 1] as usize] = [n.x, n.y, n.z];
-//             normals[mesh.indices[i * 3 + 2] as usize] = [n.x, n.y, n.z];
-//         }
-//     }
-
-//     let vertices = (0..positions.len())
-//         .map(|i| VPosNorm {
-//             position: positions[i],
-//             normal: normals[i],
-//         })
-//         .collect::<Vec<_>>();
-
-//     Ok(Mesh {
-//         vertices,
-//         indices: mesh.indices.clone(),
-//     })
-// }
+use crate::{
+    material::{AlphaMode, ImageFormat, Texture},
+    Aabb, BoundingSphere, Error, Material, Mesh, Primitive,
+};
+
+use aperture_common::VPosNormTex;
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use std::{collections::HashMap, fmt::Debug, path::Path};
+
+/// Loads a Wavefront OBJ model and the materials of its MTL library. Each object, or group of
+/// faces sharing a material, becomes a mesh with a single primitive and instance.
+///
+/// OBJ has no physically based materials, so the MTL diffuse colour and dissolve become the base
+/// colour and alpha, and the specular exponent the roughness. Only diffuse textures are used, and
+/// those that can't be read are skipped, as are the materials if the library can't be.
+#[allow(clippy::type_complexity)]
+pub fn load<P>(path: P) -> Result<(Vec<Mesh>, Vec<Material>, Vec<Texture<u8>>), Error>
+where
+    P: AsRef<Path> + Clone + Debug,
+{
+    if !path.as_ref().is_file() {
+        return Err(Error::NoSuchFile(path.as_ref().as_os_str().to_owned()));
+    }
+
+    let (models, obj_materials) = tobj::load_obj(
+        path.as_ref(),
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+        },
+    )
+    .map_err(Error::Obj)?;
+    let obj_materials = obj_materials.unwrap_or_default();
+
+    // Texture paths are relative to the model.
+    let directory = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+    let textures = load_textures(&obj_materials, directory);
+    let mut materials = load_materials(&obj_materials, &textures);
+
+    // The renderer needs a material for every primitive.
+    if models.iter().any(|model| model.mesh.material_id.is_none_or(|id| id >= materials.len())) {
+        materials.push(Material::default());
+    }
+    let default_material = materials.last().map(|material| material.name.clone());
+
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let primitive = load_primitive(&model.mesh)?;
+
+            let mut mesh = Mesh {
+                name: model.name,
+                ..Default::default()
+            };
+            mesh.add_primitive(Primitive {
+                material_name: match model.mesh.material_id {
+                    Some(id) if id < obj_materials.len() => Some(materials[id].name.clone()),
+                    _ => default_material.clone(),
+                },
+                ..primitive
+            });
+
+            Ok(mesh)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok((meshes, materials, textures.into_values().collect()))
+}
+
+/// Reads the diffuse textures of the materials, by the name they're referenced with.
+fn load_textures(materials: &[tobj::Material], directory: &Path) -> HashMap<String, Texture<u8>> {
+    let mut textures = HashMap::new();
+
+    for m in materials {
+        let name = &m.diffuse_texture;
+        if name.is_empty() || textures.contains_key(name) {
+            continue;
+        }
+
+        let image = match image::open(directory.join(name)) {
+            Ok(image) => image.to_rgba8(),
+            Err(_) => continue,
+        };
+
+        let texture = Texture {
+            name: directory.join(name).to_string_lossy().into_owned(),
+            format: ImageFormat::R8G8B8A8,
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        };
+        textures.insert(name.clone(), texture);
+    }
+
+    textures
+}
+
+fn load_materials(materials: &[tobj::Material], textures: &HashMap<String, Texture<u8>>) -> Vec<Material> {
+    materials
+        .iter()
+        .map(|m| {
+            let mut material = Material::default();
+
+            if !m.name.is_empty() {
+                material.name = m.name.clone();
+            }
+
+            let [r, g, b] = m.diffuse;
+            material.base_color_factor = Vector4::new(r, g, b, m.dissolve);
+            material.metallic_factor = 0.0;
+            material.roughness_factor = roughness(m.shininess);
+            if m.dissolve < 1.0 {
+                material.alpha_mode = AlphaMode::Blend;
+            }
+
+            // The emissive colour isn't part of the original format, but is widely written.
+            if let Some(emissive) = m.unknown_param.get("Ke").and_then(|ke| parse_color(ke)) {
+                material.emissive_factor = emissive;
+            }
+
+            material.textures.base_color = textures.get(&m.diffuse_texture).map(|texture| texture.name.clone());
+
+            material
+        })
+        .collect()
+}
+
+/// The roughness matching a Phong specular exponent, from Walter et al., "Microfacet Models for
+/// Refraction through Rough Surfaces": α = sqrt(2 / (n + 2)), with the roughness the square root
+/// of α.
+fn roughness(shininess: f32) -> f32 {
+    (2.0 / (shininess.max(0.0) + 2.0)).sqrt().sqrt()
+}
+
+fn parse_color(value: &str) -> Option<Vector3<f32>> {
+    let components = value
+        .split_whitespace()
+        .map(|component| component.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    match components.as_slice() {
+        &[r, g, b] => Some(Vector3::new(r, g, b)),
+        _ => None,
+    }
+}
+
+fn load_primitive(mesh: &tobj::Mesh) -> Result<Primitive, Error> {
+    let positions = mesh
+        .positions
+        .chunks_exact(3)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect::<Vec<_>>();
+    if positions.is_empty() {
+        return Err(Error::NoVerticesFound);
+    }
+
+    let normals = if mesh.normals.is_empty() {
+        smooth_normals(&positions, &mesh.indices)
+    } else {
+        mesh.normals.chunks_exact(3).map(|n| [n[0], n[1], n[2]]).collect()
+    };
+    if positions.len() != normals.len() {
+        return Err(Error::MismatchedVerticesNormals);
+    }
+
+    // OBJ texture coordinates start at the bottom of the image, and glTF's at the top.
+    let coords = if mesh.texcoords.is_empty() {
+        vec![[0.0, 0.0]; positions.len()]
+    } else {
+        mesh.texcoords.chunks_exact(2).map(|c| [c[0], 1.0 - c[1]]).collect()
+    };
+
+    let vertices = positions
+        .iter()
+        .zip(normals.iter())
+        .zip(coords.iter().chain(std::iter::repeat(&[0.0, 0.0])))
+        .map(|((p, n), c)| VPosNormTex {
+            position: *p,
+            normal: *n,
+            uv_coord: *c,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Primitive {
+        vertices,
+        indices: mesh.indices.clone(),
+        aabb: Aabb::from_points(&positions),
+        bounding_sphere: BoundingSphere::from_points(&positions),
+        ..Default::default()
+    })
+}
+
+/// Normals for a model without them, averaging the normals of the faces around each vertex,
+/// weighted by their area.
+fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [face[0] as usize, face[1] as usize, face[2] as usize];
+        if [a, b, c].iter().any(|&index| index >= positions.len()) {
+            continue;
+        }
+
+        let [p_a, p_b, p_c] = [positions[a], positions[b], positions[c]].map(Vector3::from);
+        let normal = (p_b - p_a).cross(p_c - p_a);
+        for index in [a, b, c] {
+            normals[index] += normal;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.magnitude2() > 0.0 {
+                n.normalize().into()
+            } else {
+                [0.0, 1.0, 0.0]
+            }
+        })
+        .collect()
+}
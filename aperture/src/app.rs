@@ -1,33 +1,194 @@
-use crate::render::Renderer;
-use crate::state::InputState;
+use crate::render::{
+    gpu_names, save_radiance, AmbientOcclusion, AntiAliasing, Camera, DepthOfField, GpuSelection, MotionBlur,
+    RenderPath, Renderer, SaveError, ScreenSpaceReflections, Screenshot, Transparency,
+};
+use crate::state::{Action, Bindings, BindingsError, Input, InputState};
+use crate::trace::{PathTracer, PathTracing};
 use crate::world::World;
 
-use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseScrollDelta, WindowEvent};
 use winit::event_loop::ControlFlow;
 
-use std::fmt::Debug;
-use std::path::Path;
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix3, Point3};
+use image::ImageError;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The longest time the camera is moved for in one update, so a stall, such as while loading a
+/// model, doesn't send it flying.
+const MAX_DELTA_TIME: f32 = 0.1;
+
+/// The number of frames rendered before a headless render is saved, so that temporal effects such
+/// as TAA have converged.
+const HEADLESS_FRAMES: u32 = 16;
 
 pub struct AppConfig {
+    /// The glTF or OBJ models loaded into the world.
+    pub models: Vec<PathBuf>,
+    /// The equirectangular Radiance HDR or OpenEXR image surrounding the world, if any.
+    pub environment: Option<PathBuf>,
+    /// Where the camera starts. By default, it frames the models.
+    pub camera: Option<CameraPlacement>,
+    /// The device to render with. By default, the most capable one.
+    pub gpu: Option<GpuSelection>,
     pub width: u32,
     pub height: u32,
     pub title: String,
+    pub anti_aliasing: AntiAliasing,
+    pub render_path: RenderPath,
+    pub transparency: Transparency,
+    pub ambient_occlusion: AmbientOcclusion,
+    pub reflections: ScreenSpaceReflections,
+    pub depth_of_field: DepthOfField,
+    pub motion_blur: MotionBlur,
+    /// Where screenshots are saved.
+    pub screenshot_dir: PathBuf,
+    /// The multiple of the window size high-resolution screenshots are rendered at.
+    pub screenshot_scale: u32,
+    /// Whether screenshots are also saved with their linear radiance, as OpenEXR files.
+    pub screenshot_radiance: bool,
+    /// How models dropped onto the window are loaded.
+    pub dropped_models: DroppedModels,
+    /// A bindings file replacing the default bindings of the actions it lists.
+    pub bindings: Option<PathBuf>,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            models: vec![PathBuf::from("data/gltf/DamagedHelmet.glb")],
+            environment: Some(PathBuf::from("data/images/desert_environment.hdr")),
+            camera: None,
+            gpu: None,
             width: 1560,
             height: 980,
             title: "Aperture Renderer".to_string(),
+            anti_aliasing: AntiAliasing::default(),
+            render_path: RenderPath::default(),
+            transparency: Transparency::default(),
+            ambient_occlusion: AmbientOcclusion::default(),
+            reflections: ScreenSpaceReflections::default(),
+            depth_of_field: DepthOfField::default(),
+            motion_blur: MotionBlur::default(),
+            screenshot_dir: PathBuf::from("screenshots"),
+            screenshot_scale: 4,
+            screenshot_radiance: false,
+            dropped_models: DroppedModels::default(),
+            bindings: None,
         }
     }
 }
 
+impl AppConfig {
+    /// Applies the rendering settings to a renderer.
+    fn configure(&self, renderer: &mut Renderer) {
+        renderer.set_render_path(self.render_path);
+        renderer.transparency = self.transparency;
+        renderer.ambient_occlusion = self.ambient_occlusion;
+        renderer.reflections = self.reflections;
+        renderer.depth_of_field = self.depth_of_field;
+        renderer.motion_blur = self.motion_blur;
+    }
+
+    /// Loads the models and environment map into a new world.
+    fn load_world(&self) -> Result<World, AppError> {
+        let mut world = World::default();
+
+        for model in &self.models {
+            world
+                .load_model(model)
+                .map_err(|err| AppError::Model(model.clone(), err))?;
+        }
+
+        if let Some(environment) = &self.environment {
+            world
+                .load_environment(environment)
+                .map_err(|err| AppError::Environment(environment.clone(), err))?;
+        }
+
+        Ok(world)
+    }
+
+    /// Places the camera as configured, or to frame the world from the default direction.
+    fn place_camera(&self, camera: &mut Camera, world: &World) {
+        place_camera(camera, self.camera, world);
+    }
+
+    fn load_bindings(&self) -> Result<Bindings, AppError> {
+        match &self.bindings {
+            Some(path) => Bindings::load(path).map_err(|err| AppError::Bindings(path.clone(), err)),
+            None => Ok(Bindings::default()),
+        }
+    }
+
+    fn headless_renderer(&self) -> Result<Renderer, AppError> {
+        let mut renderer = Renderer::headless(self.width, self.height, self.anti_aliasing, self.gpu.as_ref())
+            .ok_or_else(|| self.no_device())?;
+        self.configure(&mut renderer);
+
+        Ok(renderer)
+    }
+
+    fn no_device(&self) -> AppError {
+        match &self.gpu {
+            Some(gpu) => AppError::GpuNotFound(gpu.clone()),
+            None => AppError::NoDevice,
+        }
+    }
+}
+
+/// Places the camera at the placement, or if there's none, to frame the world from the camera's
+/// direction.
+fn place_camera(camera: &mut Camera, placement: Option<CameraPlacement>, world: &World) {
+    match (placement, world.bounds()) {
+        (Some(placement), bounds) => {
+            let look_at = placement
+                .look_at
+                .or_else(|| bounds.map(|bounds| bounds.center()))
+                .unwrap_or_else(Point3::origin);
+            camera.look_at = look_at;
+            camera.set_position(placement.eye);
+            camera.focus_distance = (look_at - placement.eye).magnitude();
+        }
+        (None, Some(bounds)) => camera.frame(&bounds),
+        (None, None) => {}
+    }
+}
+
+/// Where the camera starts, and the point it looks at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPlacement {
+    pub eye: Point3<f32>,
+    /// By default, the centre of the models.
+    pub look_at: Option<Point3<f32>>,
+}
+
+/// Whether a model dropped onto the window replaces those in the world, or is added to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DroppedModels {
+    /// The world is cleared of models first, keeping its environment map.
+    #[default]
+    Replace,
+    Add,
+}
+
 pub struct App {
     pub world: World,
     pub renderer: Renderer,
     pub input_state: InputState,
+    pub screenshot_dir: PathBuf,
+    pub screenshot_scale: u32,
+    pub screenshot_radiance: bool,
+    pub dropped_models: DroppedModels,
+    pub bindings: Bindings,
+    /// Where the camera is placed when it's reset. By default, it frames the world.
+    pub camera_placement: Option<CameraPlacement>,
+    /// When the camera was last updated, to move it by the time since.
+    pub last_update: Option<Instant>,
 }
 
 impl App {
@@ -35,16 +196,254 @@ impl App {
         self.renderer.notify_resized();
     }
 
-    pub fn load_gltf<P>(&mut self, path: P)
-    where
-        P: AsRef<Path> + Clone + Debug,
-    {
-        self.world.load_gltf(path);
-        self.renderer.load_world(&self.world);
+    /// Records the input being pressed or released, performing the actions bound to pressing it.
+    pub fn handle_input(&mut self, input: Input, state: ElementState) {
+        if !self.input_state.set_down(input, state == ElementState::Pressed) {
+            return;
+        }
+
+        let actions = self.bindings.triggered(input, self.input_state.modifiers).collect::<Vec<_>>();
+        for action in actions {
+            self.perform(action);
+        }
+    }
+
+    pub fn perform(&mut self, action: Action) {
+        match action {
+            // Held actions are read as the camera is updated.
+            Action::Orbit
+            | Action::Pan
+            | Action::Look
+            | Action::MoveForward
+            | Action::MoveBack
+            | Action::MoveLeft
+            | Action::MoveRight
+            | Action::MoveUp
+            | Action::MoveDown
+            | Action::MoveFaster
+            | Action::MoveSlower => {}
+            Action::FocusAtCursor => self.focus_at_cursor(),
+            Action::ResetCamera => self.reset_camera(),
+            Action::ToggleCameraMode => self.cycle_camera_mode(),
+            Action::ToggleWireframe => self.toggle_wireframe(),
+            Action::OpenAperture => self.adjust_f_stop(-1.0 / 3.0),
+            Action::CloseAperture => self.adjust_f_stop(1.0 / 3.0),
+            Action::Screenshot => self.take_screenshot(false),
+            Action::HighResolutionScreenshot => self.take_screenshot(true),
+            Action::CycleAntiAliasing => self.cycle_anti_aliasing(),
+            Action::ToggleAmbientOcclusion => self.toggle_ambient_occlusion(),
+            Action::ToggleDepthOfField => self.toggle_depth_of_field(),
+            Action::CycleBokeh => self.cycle_bokeh(),
+            Action::ToggleMotionBlur => self.toggle_motion_blur(),
+            Action::ToggleReflections => self.toggle_reflections(),
+            Action::CycleRenderPath => self.cycle_render_path(),
+            Action::CycleTransparency => self.cycle_transparency(),
+            Action::PrintStats => self.print_stats(),
+            Action::ToggleOcclusionCulling => self.toggle_occlusion_culling(),
+            Action::ToggleGpuDriven => self.toggle_gpu_driven(),
+        }
+    }
+
+    /// Moves the camera back to where it started, or to frame the world if it's changed since.
+    /// The aperture is kept.
+    pub fn reset_camera(&mut self) {
+        let camera = &mut self.renderer.camera;
+        *camera = Camera {
+            f_stop: camera.f_stop,
+            ..Camera::default()
+        };
+        place_camera(camera, self.camera_placement, &self.world);
+    }
+
+    pub fn cycle_camera_mode(&mut self) {
+        let camera_mode = self.renderer.camera_mode.next();
+        self.renderer.set_camera_mode(camera_mode);
+
+        println!("Camera mode: {:?}", self.renderer.camera_mode);
+    }
+
+    pub fn toggle_wireframe(&mut self) {
+        self.renderer.wireframe = !self.renderer.wireframe;
+
+        println!("Wireframe: {}", if self.renderer.wireframe { "on" } else { "off" });
+    }
+
+    pub fn cycle_anti_aliasing(&mut self) {
+        let anti_aliasing = self.renderer.anti_aliasing().next();
+        self.renderer.set_anti_aliasing(anti_aliasing);
+
+        println!("Anti-aliasing: {:?}", self.renderer.anti_aliasing());
+    }
+
+    pub fn cycle_render_path(&mut self) {
+        let render_path = self.renderer.render_path().next();
+        self.renderer.set_render_path(render_path);
+
+        println!("Render path: {:?}", self.renderer.render_path());
+    }
+
+    pub fn cycle_transparency(&mut self) {
+        let transparency = &mut self.renderer.transparency;
+        *transparency = transparency.next();
+
+        println!("Transparency: {:?}", transparency);
+    }
+
+    pub fn toggle_ambient_occlusion(&mut self) {
+        let ambient_occlusion = &mut self.renderer.ambient_occlusion;
+        ambient_occlusion.enabled = !ambient_occlusion.enabled;
+
+        println!("Ambient occlusion: {}", if ambient_occlusion.enabled { "on" } else { "off" });
+    }
+
+    pub fn toggle_depth_of_field(&mut self) {
+        let depth_of_field = &mut self.renderer.depth_of_field;
+        depth_of_field.enabled = !depth_of_field.enabled;
+
+        println!("Depth of field: {}", if depth_of_field.enabled { "on" } else { "off" });
+    }
+
+    pub fn cycle_bokeh(&mut self) {
+        let depth_of_field = &mut self.renderer.depth_of_field;
+        depth_of_field.bokeh = depth_of_field.bokeh.next();
+
+        println!("Bokeh: {:?}", depth_of_field.bokeh);
+    }
+
+    pub fn toggle_motion_blur(&mut self) {
+        let motion_blur = &mut self.renderer.motion_blur;
+        motion_blur.enabled = !motion_blur.enabled;
+
+        println!("Motion blur: {}", if motion_blur.enabled { "on" } else { "off" });
+    }
+
+    pub fn toggle_reflections(&mut self) {
+        let reflections = &mut self.renderer.reflections;
+        reflections.enabled = !reflections.enabled;
+
+        println!("Screen-space reflections: {}", if reflections.enabled { "on" } else { "off" });
+    }
+
+    pub fn toggle_occlusion_culling(&mut self) {
+        let occlusion_culling = &mut self.renderer.occlusion_culling;
+        *occlusion_culling = !*occlusion_culling;
+
+        println!("Occlusion culling: {}", if *occlusion_culling { "on" } else { "off" });
+        if *occlusion_culling && self.renderer.anti_aliasing().samples() > 1 {
+            println!("Occlusion culling isn't supported with MSAA");
+        }
+    }
+
+    pub fn toggle_gpu_driven(&mut self) {
+        self.renderer.gpu_driven = !self.renderer.gpu_driven;
+
+        println!("GPU-driven rendering: {}", if self.renderer.gpu_driven { "on" } else { "off" });
+        if self.renderer.gpu_driven && !self.renderer.gpu_driven_supported() {
+            println!("GPU-driven rendering isn't supported by the device or the deferred render path");
+        }
+    }
+
+    pub fn print_stats(&self) {
+        let stats = self.renderer.stats();
+
+        println!(
+            "Primitives: {} drawn, {} culled, {} occluded",
+            stats.drawn_primitives, stats.culled_primitives, stats.occluded_primitives,
+        );
+        println!("Frame time: {:.2} ms", stats.frame_time.as_secs_f64() * 1000.0);
+    }
+
+    /// Opens or closes the camera aperture by the given number of stops.
+    pub fn adjust_f_stop(&mut self, stops: f32) {
+        let camera = &mut self.renderer.camera;
+        camera.f_stop = (camera.f_stop * 2f32.sqrt().powf(stops)).clamp(1.0, 22.0);
+
+        println!("f/{:.1}", camera.f_stop);
+    }
+
+    /// Focuses the camera on the object under the cursor.
+    pub fn focus_at_cursor(&mut self) {
+        if let Some(position) = self.input_state.cursor_position {
+            self.renderer.focus_at(position);
+        }
+    }
+
+    /// Saves the current view to the screenshot directory, named by the time it was taken. High
+    /// resolution screenshots are rendered at the configured multiple of the window size.
+    pub fn take_screenshot(&mut self, high_resolution: bool) {
+        let screenshot = Screenshot {
+            scale: if high_resolution { self.screenshot_scale } else { 1 },
+            radiance: self.screenshot_radiance,
+        };
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let path = self.screenshot_dir.join(format!("aperture-{}.png", timestamp));
+
+        let result = fs::create_dir_all(&self.screenshot_dir)
+            .map_err(|err| SaveError::Image(err.into()))
+            .and_then(|()| self.renderer.save_screenshot(&self.world, &path, screenshot));
+        match result {
+            Ok(()) => println!("Saved screenshot to {}", path.display()),
+            Err(err) => println!("Failed to save screenshot: {}", err),
+        }
+    }
+
+    /// Loads a file dropped onto the window: a glTF or OBJ model, replacing or added to those in
+    /// the world as configured, or an HDR or OpenEXR environment map in place of the current one.
+    /// The camera is reframed to fit new models.
+    pub fn load_dropped_file(&mut self, path: &Path) {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("glb") | Some("gltf") | Some("obj") => self.load_dropped_model(path),
+            Some("hdr") | Some("exr") => self.load_dropped_environment(path),
+            _ => println!(
+                "Can't load {}: only glTF and OBJ models and HDR and OpenEXR environment maps are supported",
+                path.display()
+            ),
+        }
+    }
+
+    fn load_dropped_model(&mut self, path: &Path) {
+        let result = match self.dropped_models {
+            DroppedModels::Replace => self.world.replace_models(path),
+            DroppedModels::Add => self.world.load_model(path),
+        };
+        if let Err(err) = result {
+            println!("{}", AppError::Model(path.to_path_buf(), err));
+            return;
+        }
+
+        self.renderer.load_models(&self.world);
+        if let Some(bounds) = self.world.bounds() {
+            self.renderer.camera.frame(&bounds);
+        }
+
+        println!("Loaded {}", path.display());
+    }
+
+    fn load_dropped_environment(&mut self, path: &Path) {
+        if let Err(err) = self.world.load_environment(path) {
+            println!("{}", AppError::Environment(path.to_path_buf(), err));
+            return;
+        }
+
+        self.renderer.load_environment(&self.world);
+
+        println!("Loaded {}", path.display());
     }
 
     pub fn update(&mut self) {
-        self.renderer.update(&self.input_state);
+        let now = Instant::now();
+        let delta_time = self
+            .last_update
+            .replace(now)
+            .map_or(0.0, |last_update| (now - last_update).as_secs_f32().min(MAX_DELTA_TIME));
+
+        self.renderer.update(&self.input_state, &self.bindings, delta_time);
     }
 
     pub fn render(&mut self) {
@@ -52,19 +451,38 @@ impl App {
     }
 }
 
-pub fn run_app(config: AppConfig) {
-    let (renderer, event_loop) = Renderer::new(config.title, config.width, config.height);
-    let world = World::default();
+/// Opens a window showing the configured world, returning only if it can't be set up.
+pub fn run_app(config: AppConfig) -> Result<(), AppError> {
+    let world = config.load_world()?;
+    let bindings = config.load_bindings()?;
+
+    let (mut renderer, event_loop) = Renderer::new(
+        config.title.clone(),
+        config.width,
+        config.height,
+        config.anti_aliasing,
+        config.gpu.as_ref(),
+    )
+    .ok_or_else(|| config.no_device())?;
+    config.configure(&mut renderer);
+    renderer.load_world(&world);
+    config.place_camera(&mut renderer.camera, &world);
+
     let input_state = InputState::default();
 
     let mut app = App {
         renderer,
         world,
         input_state,
+        screenshot_dir: config.screenshot_dir,
+        screenshot_scale: config.screenshot_scale,
+        screenshot_radiance: config.screenshot_radiance,
+        dropped_models: config.dropped_models,
+        bindings,
+        camera_placement: config.camera,
+        last_update: None,
     };
 
-    app.load_gltf("data/gltf/DamagedHelmet.glb");
-
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
@@ -78,50 +496,189 @@ pub fn run_app(config: AppConfig) {
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
+            } if size.width > 0 && size.height > 0 => {
+                app.resize();
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state, button, .. },
+                ..
             } => {
-                if size.width > 0 && size.height > 0 {
-                    app.resize();
-                }
+                app.handle_input(Input::Mouse(button), state);
             }
             Event::WindowEvent {
                 event:
-                    WindowEvent::MouseInput {
-                        device_id: _,
-                        state,
-                        button,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
                         ..
                     },
                 ..
-            } => match button {
-                MouseButton::Left => {
-                    app.input_state.mouse_left_down = state == ElementState::Pressed
-                }
-                MouseButton::Right => {
-                    app.input_state.mouse_right_down = state == ElementState::Pressed
-                }
-                _ => {}
-            },
+            } => {
+                app.handle_input(Input::Key(key), state);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } => {
+                app.input_state.release_all();
+            }
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(path),
+                ..
+            } => {
+                app.load_dropped_file(&path);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(modifiers),
+                ..
+            } => {
+                app.input_state.modifiers = modifiers;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                app.input_state.cursor_position = Some([position.x as f32, position.y as f32]);
+            }
             Event::DeviceEvent {
                 event: DeviceEvent::MouseWheel { delta },
                 ..
             } => {
-                app.input_state.wheel_delta = match delta {
-                    MouseScrollDelta::LineDelta(_, delta_y) => Some(delta_y),
-                    MouseScrollDelta::PixelDelta(delta) => Some(delta.y as f32),
-                };
+                app.input_state.add_wheel_delta(match delta {
+                    MouseScrollDelta::LineDelta(_, delta_y) => delta_y,
+                    MouseScrollDelta::PixelDelta(delta) => delta.y as f32,
+                });
             }
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
             } => {
-                app.input_state.position_delta = Some([delta.0 as f32, delta.1 as f32]);
+                app.input_state.add_position_delta([delta.0 as f32, delta.1 as f32]);
             }
             Event::MainEventsCleared => {
                 app.update();
                 app.render();
+                // The motion since the last frame has been used.
                 app.input_state.tick();
             }
             _ => {}
         }
     });
 }
+
+#[derive(Debug)]
+pub enum AppError {
+    /// There's no Vulkan implementation, or no device supports graphics.
+    NoDevice,
+    /// No device matches the selected GPU, or the one that does can't render.
+    GpuNotFound(GpuSelection),
+    Model(PathBuf, aperture_mesh::Error),
+    Environment(PathBuf, ImageError),
+    Bindings(PathBuf, BindingsError),
+    Save(SaveError),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoDevice => write!(f, "No Vulkan device is available to render with"),
+            Self::GpuNotFound(gpu) => {
+                write!(f, "No Vulkan device matching \"{}\" can render. The devices are:", gpu)?;
+                for (index, name) in gpu_names().iter().enumerate() {
+                    write!(f, "\n  {}: {}", index, name)?;
+                }
+                Ok(())
+            }
+            Self::Model(path, err) => write!(f, "Failed to load the model {}: {}", path.display(), err),
+            Self::Environment(path, err) => {
+                write!(f, "Failed to load the environment map {}: {}", path.display(), err)
+            }
+            Self::Bindings(path, err) => write!(f, "Failed to load the bindings {}: {}", path.display(), err),
+            Self::Save(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for AppError {}
+
+impl From<SaveError> for AppError {
+    fn from(err: SaveError) -> Self {
+        Self::Save(err)
+    }
+}
+
+/// Renders the models without a window, at the size given by the config, and saves the frame to
+/// `output`. The format is chosen by the extension, with OpenEXR files holding the linear radiance.
+pub fn render_headless(config: AppConfig, output: &Path) -> Result<(), AppError> {
+    let world = config.load_world()?;
+    let mut renderer = config.headless_renderer()?;
+    renderer.load_world(&world);
+    config.place_camera(&mut renderer.camera, &world);
+
+    for _ in 0..HEADLESS_FRAMES {
+        renderer.render(&world);
+    }
+
+    Ok(renderer.save_frame(output)?)
+}
+
+/// Renders the models without a window as a turntable, orbiting the camera once around them over
+/// `frames` frames, and saves them to `output_dir` as a numbered PNG sequence. Every frame turns
+/// the camera by the same angle, however long it takes to render, so the motion is even.
+pub fn render_turntable(config: AppConfig, output_dir: &Path, frames: u32) -> Result<(), AppError> {
+    let world = config.load_world()?;
+    let mut renderer = config.headless_renderer()?;
+    renderer.load_world(&world);
+    config.place_camera(&mut renderer.camera, &world);
+
+    // Each position is found from the first rather than the last, so the error doesn't accumulate
+    // and the sequence loops.
+    let start = renderer.camera.eye;
+    let pivot = renderer.camera.look_at;
+    let axis = renderer.camera.up.normalize();
+
+    fs::create_dir_all(output_dir).map_err(|err| SaveError::Image(err.into()))?;
+
+    for frame in 0..frames {
+        let rotation = Matrix3::from_axis_angle(axis, Deg(360.0 * frame as f32 / frames as f32));
+        renderer.camera.set_position(pivot + rotation * (start - pivot));
+
+        // Temporal effects converge on the first frame, and then follow the camera as they would
+        // in the viewer.
+        let renders = if frame == 0 { HEADLESS_FRAMES } else { 1 };
+        for _ in 0..renders {
+            renderer.render(&world);
+        }
+
+        let path = output_dir.join(format!("frame_{:04}.png", frame));
+        renderer.save_frame(&path)?;
+    }
+
+    Ok(())
+}
+
+/// Renders the models on the CPU with the path tracer, at the size given by the config, and saves
+/// the image to `output`. The camera is placed as `render_headless` places it, so the two can be
+/// compared. OpenEXR files hold the linear radiance.
+pub fn render_path_traced(config: AppConfig, output: &Path, settings: PathTracing) -> Result<(), AppError> {
+    let world = config.load_world()?;
+
+    let mut camera = Camera::default();
+    config.place_camera(&mut camera, &world);
+
+    let tracer = PathTracer::new(&world);
+
+    let start = Instant::now();
+    let image = tracer.render(&camera, [config.width, config.height], settings);
+    println!(
+        "Path traced {} samples per pixel in {:.1} s",
+        settings.samples,
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(save_radiance(output, &image)?)
+}
@@ -0,0 +1,364 @@
+use crate::app::{AppConfig, CameraPlacement, DroppedModels};
+use crate::render::GpuSelection;
+use crate::trace::PathTracing;
+
+use cgmath::Point3;
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: aperture [OPTIONS] [MODEL]...
+
+Shows glTF models in a window, or renders them to images without one.
+
+Arguments:
+  [MODEL]...                  The glTF or OBJ models to load [default: data/gltf/DamagedHelmet.glb]
+
+Options:
+  -e, --environment <PATH>    The equirectangular Radiance HDR or OpenEXR environment map
+                              [default: data/images/desert_environment.hdr]
+      --no-environment        Render without an environment map
+  -s, --size <WIDTHxHEIGHT>   The size of the window or images [default: 1560x980 in a window,
+                              512x512 offscreen]
+      --eye <X,Y,Z>           Where the camera starts [default: framing the models]
+      --look-at <X,Y,Z>       The point the camera looks at, with --eye [default: the centre of
+                              the models]
+      --gpu <INDEX|NAME>      The device to render with, by its index in --list-gpus or part of
+                              its name [default: the most capable]
+      --list-gpus             List the devices and exit
+      --bindings <PATH>       A bindings file, replacing the default bindings of the actions it
+                              lists, as in data/bindings.cfg
+      --drop <replace|add>    Whether models dropped onto the window replace those loaded, or are
+                              added to them [default: replace]
+  -o, --output <PATH>         Render offscreen and save the image, as PNG, or as OpenEXR with the
+                              linear radiance for .exr paths
+      --turntable <FRAMES>    With --output naming a directory, orbit the camera once over the
+                              frames and save them as a numbered PNG sequence
+      --path-trace <SAMPLES>  With --output, render on the CPU with the path tracer, tracing the
+                              samples per pixel
+  -h, --help                  Print this help and exit
+";
+
+/// The size of images rendered offscreen, unless one is given.
+const OFFSCREEN_SIZE: [u32; 2] = [512, 512];
+
+/// What the command line asks for.
+pub enum Command {
+    Help,
+    ListGpus,
+    /// Show the world in a window.
+    Window(AppConfig),
+    /// Render one image to the path.
+    Image(AppConfig, PathBuf),
+    /// Render a turntable sequence of the frames to the directory.
+    Turntable(AppConfig, PathBuf, u32),
+    /// Render one image to the path with the path tracer.
+    PathTrace(AppConfig, PathBuf, PathTracing),
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    UnknownOption(String),
+    MissingValue(&'static str),
+    InvalidValue {
+        option: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+    /// The option only makes sense when rendering offscreen.
+    NeedsOutput(&'static str),
+    /// The option only makes sense when showing a window.
+    WindowOnly(&'static str),
+    Conflicting(&'static str, &'static str),
+    LookAtWithoutEye,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownOption(option) => write!(f, "Unknown option {}", option),
+            Self::MissingValue(option) => write!(f, "{} needs a value", option),
+            Self::InvalidValue { option, value, expected } => {
+                write!(f, "Invalid value \"{}\" for {}: expected {}", value, option, expected)
+            }
+            Self::NeedsOutput(option) => write!(f, "{} needs --output", option),
+            Self::WindowOnly(option) => {
+                write!(f, "{} only applies to the window, so can't be used with --output", option)
+            }
+            Self::Conflicting(a, b) => write!(f, "{} can't be used with {}", a, b),
+            Self::LookAtWithoutEye => write!(f, "--look-at needs --eye"),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+/// Parses the arguments, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, ArgsError> {
+    let mut args = args.into_iter();
+
+    let mut config = AppConfig {
+        models: vec![],
+        ..Default::default()
+    };
+    let mut size = None;
+    let mut eye = None;
+    let mut look_at = None;
+    let mut output = None;
+    let mut turntable = None;
+    let mut path_trace = None;
+    let mut dropped_models = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |option: &'static str| args.next().ok_or(ArgsError::MissingValue(option));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list-gpus" => return Ok(Command::ListGpus),
+            "-e" | "--environment" => config.environment = Some(PathBuf::from(value("--environment")?)),
+            "--no-environment" => config.environment = None,
+            "-s" | "--size" => size = Some(parse_size(&value("--size")?)?),
+            "--eye" => eye = Some(parse_point("--eye", &value("--eye")?)?),
+            "--look-at" => look_at = Some(parse_point("--look-at", &value("--look-at")?)?),
+            "--gpu" => config.gpu = Some(parse_gpu(value("--gpu")?)),
+            "--bindings" => config.bindings = Some(PathBuf::from(value("--bindings")?)),
+            "--drop" => dropped_models = Some(parse_dropped_models(&value("--drop")?)?),
+            "-o" | "--output" => output = Some(PathBuf::from(value("--output")?)),
+            "--turntable" => turntable = Some(parse_count("--turntable", &value("--turntable")?)?),
+            "--path-trace" => path_trace = Some(parse_count("--path-trace", &value("--path-trace")?)?),
+            option if option.starts_with('-') && option != "-" => {
+                return Err(ArgsError::UnknownOption(option.to_string()))
+            }
+            model => config.models.push(PathBuf::from(model)),
+        }
+    }
+
+    if config.models.is_empty() {
+        config.models = AppConfig::default().models;
+    }
+
+    if output.is_some() {
+        if config.bindings.is_some() {
+            return Err(ArgsError::WindowOnly("--bindings"));
+        }
+        if dropped_models.is_some() {
+            return Err(ArgsError::WindowOnly("--drop"));
+        }
+    }
+    config.dropped_models = dropped_models.unwrap_or_default();
+
+    // The path tracer renders on the CPU.
+    if config.gpu.is_some() && path_trace.is_some() {
+        return Err(ArgsError::Conflicting("--gpu", "--path-trace"));
+    }
+
+    config.camera = match (eye, look_at) {
+        (Some(eye), look_at) => Some(CameraPlacement { eye, look_at }),
+        (None, Some(_)) => return Err(ArgsError::LookAtWithoutEye),
+        (None, None) => None,
+    };
+
+    let [width, height] = match (size, &output) {
+        (Some(size), _) => size,
+        (None, Some(_)) => OFFSCREEN_SIZE,
+        (None, None) => [config.width, config.height],
+    };
+    config.width = width;
+    config.height = height;
+
+    match (output, turntable, path_trace) {
+        (_, Some(_), Some(_)) => Err(ArgsError::Conflicting("--turntable", "--path-trace")),
+        (None, Some(_), _) => Err(ArgsError::NeedsOutput("--turntable")),
+        (None, _, Some(_)) => Err(ArgsError::NeedsOutput("--path-trace")),
+        (None, None, None) => Ok(Command::Window(config)),
+        (Some(output), Some(frames), None) => Ok(Command::Turntable(config, output, frames)),
+        (Some(output), None, Some(samples)) => {
+            let settings = PathTracing {
+                samples,
+                ..Default::default()
+            };
+            Ok(Command::PathTrace(config, output, settings))
+        }
+        (Some(output), None, None) => Ok(Command::Image(config, output)),
+    }
+}
+
+fn parse_size(value: &str) -> Result<[u32; 2], ArgsError> {
+    let invalid = || ArgsError::InvalidValue {
+        option: "--size",
+        value: value.to_string(),
+        expected: "a size such as 1920x1080",
+    };
+
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok([width, height]),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_point(option: &'static str, value: &str) -> Result<Point3<f32>, ArgsError> {
+    let coordinates = value
+        .split(',')
+        .map(|coordinate| coordinate.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>();
+
+    match coordinates.as_deref() {
+        Ok(&[x, y, z]) if x.is_finite() && y.is_finite() && z.is_finite() => Ok(Point3::new(x, y, z)),
+        _ => Err(ArgsError::InvalidValue {
+            option,
+            value: value.to_string(),
+            expected: "three comma-separated numbers such as 0,1.5,-4",
+        }),
+    }
+}
+
+fn parse_count(option: &'static str, value: &str) -> Result<u32, ArgsError> {
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(ArgsError::InvalidValue {
+            option,
+            value: value.to_string(),
+            expected: "a positive integer",
+        }),
+    }
+}
+
+fn parse_dropped_models(value: &str) -> Result<DroppedModels, ArgsError> {
+    match value {
+        "replace" => Ok(DroppedModels::Replace),
+        "add" => Ok(DroppedModels::Add),
+        _ => Err(ArgsError::InvalidValue {
+            option: "--drop",
+            value: value.to_string(),
+            expected: "replace or add",
+        }),
+    }
+}
+
+fn parse_gpu(value: String) -> GpuSelection {
+    match value.parse() {
+        Ok(index) => GpuSelection::Index(index),
+        Err(_) => GpuSelection::Name(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, ArgsError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse_error(args: &[&str]) -> ArgsError {
+        match parse_args(args) {
+            Ok(_) => panic!("{:?} parsed", args),
+            Err(err) => err,
+        }
+    }
+
+    fn parse_window(args: &[&str]) -> AppConfig {
+        match parse_args(args) {
+            Ok(Command::Window(config)) => config,
+            Ok(_) => panic!("{:?} didn't parse to a window", args),
+            Err(err) => panic!("{:?} didn't parse: {}", args, err),
+        }
+    }
+
+    #[test]
+    fn without_arguments_the_default_model_is_shown() {
+        let config = parse_window(&[]);
+
+        assert_eq!(config.models, AppConfig::default().models);
+        assert_eq!([config.width, config.height], [AppConfig::default().width, AppConfig::default().height]);
+    }
+
+    #[test]
+    fn every_model_is_loaded_in_order() {
+        let config = parse_window(&["a.glb", "-s", "640x480", "b.obj", "c.gltf"]);
+
+        assert_eq!(config.models, [PathBuf::from("a.glb"), PathBuf::from("b.obj"), PathBuf::from("c.gltf")]);
+        assert_eq!([config.width, config.height], [640, 480]);
+    }
+
+    #[test]
+    fn offscreen_renders_default_to_a_smaller_size() {
+        match parse_args(&["-o", "out.png"]) {
+            Ok(Command::Image(config, output)) => {
+                assert_eq!([config.width, config.height], OFFSCREEN_SIZE);
+                assert_eq!(output, PathBuf::from("out.png"));
+            }
+            _ => panic!("didn't parse to an image"),
+        }
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(matches!(parse_error(&["--frobnicate"]), ArgsError::UnknownOption(option) if option == "--frobnicate"));
+        assert!(matches!(parse_error(&["model.glb", "-x"]), ArgsError::UnknownOption(option) if option == "-x"));
+    }
+
+    #[test]
+    fn options_without_their_value_are_rejected() {
+        assert!(matches!(parse_error(&["--size"]), ArgsError::MissingValue("--size")));
+        assert!(matches!(parse_error(&["model.glb", "-o"]), ArgsError::MissingValue("--output")));
+    }
+
+    #[test]
+    fn malformed_sizes_are_rejected() {
+        for size in ["1920", "1920x", "x1080", "0x1080", "1920x-1", "wide"] {
+            assert!(
+                matches!(parse_error(&["-s", size]), ArgsError::InvalidValue { option: "--size", .. }),
+                "{} was accepted",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_points_are_rejected() {
+        for point in ["1,2", "1,2,3,4", "1,2,z", "1,2,inf", ""] {
+            assert!(
+                matches!(parse_error(&["--eye", point]), ArgsError::InvalidValue { option: "--eye", .. }),
+                "{} was accepted",
+                point
+            );
+        }
+    }
+
+    #[test]
+    fn look_at_needs_an_eye() {
+        assert!(matches!(parse_error(&["--look-at", "0,0,0"]), ArgsError::LookAtWithoutEye));
+
+        let config = parse_window(&["--eye", "0,1.5,-4", "--look-at", "0,0,0"]);
+        let camera = config.camera.unwrap();
+        assert_eq!(camera.eye, Point3::new(0.0, 1.5, -4.0));
+        assert_eq!(camera.look_at, Some(Point3::new(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn offscreen_options_need_an_output() {
+        assert!(matches!(parse_error(&["--turntable", "8"]), ArgsError::NeedsOutput("--turntable")));
+        assert!(matches!(parse_error(&["--path-trace", "64"]), ArgsError::NeedsOutput("--path-trace")));
+        assert!(matches!(
+            parse_error(&["-o", "out", "--turntable", "8", "--path-trace", "64"]),
+            ArgsError::Conflicting("--turntable", "--path-trace")
+        ));
+    }
+
+    #[test]
+    fn options_that_would_be_ignored_are_rejected() {
+        assert!(matches!(
+            parse_error(&["-o", "out.png", "--path-trace", "64", "--gpu", "0"]),
+            ArgsError::Conflicting("--gpu", "--path-trace")
+        ));
+        assert!(matches!(
+            parse_error(&["-o", "out.png", "--bindings", "keys.cfg"]),
+            ArgsError::WindowOnly("--bindings")
+        ));
+        assert!(matches!(parse_error(&["-o", "out.png", "--drop", "add"]), ArgsError::WindowOnly("--drop")));
+    }
+}
@@ -0,0 +1,228 @@
+//! Golden-image tests, rendering the bundled models offscreen with a fixed camera and lighting,
+//! and comparing them against reference images by their structural similarity (SSIM).
+//!
+//! The reference images haven't been generated yet, so `data/golden` doesn't exist and the render
+//! tests fail until it does. They're meant to be rendered with lavapipe, by running the tests with
+//! `APERTURE_UPDATE_GOLDEN=1`, which also updates them. When a render doesn't match its reference,
+//! it's written to `target/golden` alongside an image of the difference.
+//!
+//! The render tests need a Vulkan device, so are ignored unless run with
+//! `cargo test -- --ignored`. They fail when no device is available.
+
+use crate::render::{AntiAliasing, Renderer};
+use crate::world::World;
+
+use image::{Rgba, RgbaImage};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The size of the rendered images.
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+
+/// The number of frames rendered before the image is compared.
+const FRAMES: u32 = 4;
+
+/// The lowest mean SSIM between a render and its reference that passes.
+const SSIM_THRESHOLD: f64 = 0.98;
+
+/// The size of the square windows the SSIM is computed over, which overlap by half.
+const SSIM_WINDOW: u32 = 8;
+
+/// How much the differences are scaled by in the diff images, to make small ones visible.
+const DIFF_SCALE: u8 = 4;
+
+/// The directories of the references and of the output of failed tests, relative to the
+/// workspace root.
+const REFERENCE_DIR: &str = "data/golden";
+const OUTPUT_DIR: &str = "target/golden";
+
+// The renders are run one at a time, rather than competing for the device.
+static RENDER_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn box_model() {
+    check_golden("box", "data/gltf/Box.glb");
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn damaged_helmet() {
+    check_golden("damaged_helmet", "data/gltf/DamagedHelmet.glb");
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn metal_rough_spheres() {
+    check_golden("metal_rough_spheres", "data/gltf/MetalRoughSpheresNoTextures.glb");
+}
+
+#[test]
+fn ssim_of_identical_images_is_one() {
+    let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]));
+
+    assert!((ssim(&image, &image) - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn ssim_falls_as_images_differ() {
+    let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]));
+    let noisy = RgbaImage::from_fn(32, 32, |x, y| {
+        let pixel = image.get_pixel(x, y);
+        let noise = if (x + y) % 2 == 0 { 8 } else { 0 };
+        Rgba([pixel[0].saturating_add(noise), pixel[1], pixel[2], 255])
+    });
+    let inverted = RgbaImage::from_fn(32, 32, |x, y| {
+        let pixel = image.get_pixel(x, y);
+        Rgba([255 - pixel[0], 255 - pixel[1], pixel[2], 255])
+    });
+
+    let noisy_score = ssim(&image, &noisy);
+    assert!(noisy_score < 1.0);
+    assert!(ssim(&image, &inverted) < noisy_score);
+}
+
+/// Renders the model and compares it with the reference image `name`.
+fn check_golden(name: &str, model: &str) {
+    let _lock = RENDER_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+
+    let renderer = Renderer::headless(WIDTH, HEIGHT, AntiAliasing::None, None)
+        .unwrap_or_else(|| panic!("No Vulkan device is available to render {}", name));
+
+    let actual = render(renderer, &workspace_path(model));
+    let reference_dir = workspace_path(REFERENCE_DIR);
+    let reference_path = reference_dir.join(format!("{}.png", name));
+
+    if env::var_os("APERTURE_UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(&reference_dir).unwrap();
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.to_rgba8(),
+        Err(err) => {
+            let actual_path = save_output(name, "actual", &actual);
+            panic!(
+                "Failed to open {}: {}. The render was written to {}, and the references can be created \
+                 with APERTURE_UPDATE_GOLDEN=1",
+                reference_path.display(),
+                err,
+                actual_path.display(),
+            );
+        }
+    };
+
+    if reference.dimensions() != actual.dimensions() {
+        let actual_path = save_output(name, "actual", &actual);
+        panic!(
+            "{} is {:?}, but the render is {:?}. The render was written to {}",
+            reference_path.display(),
+            reference.dimensions(),
+            actual.dimensions(),
+            actual_path.display(),
+        );
+    }
+
+    let score = ssim(&actual, &reference);
+    if score < SSIM_THRESHOLD {
+        let actual_path = save_output(name, "actual", &actual);
+        let diff_path = save_output(name, "diff", &diff(&actual, &reference));
+        panic!(
+            "{} doesn't match its reference, with an SSIM of {:.4} below {}. The render was written to {}, and \
+             the difference to {}",
+            name,
+            score,
+            SSIM_THRESHOLD,
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+/// Renders the model with the default lights and no environment map, framed from the default
+/// camera direction. Effects that depend on the camera's motion or the cursor are disabled.
+fn render(mut renderer: Renderer, model: &Path) -> RgbaImage {
+    renderer.depth_of_field.enabled = false;
+    renderer.motion_blur.enabled = false;
+
+    let mut world = World::default();
+    world.load_model(model).unwrap();
+    renderer.load_world(&world);
+    renderer.camera.frame(&world.bounds().unwrap());
+
+    for _ in 0..FRAMES {
+        renderer.render(&world);
+    }
+
+    renderer.read_image().unwrap()
+}
+
+/// Writes an image for a failed test to the output directory, returning its path.
+fn save_output(name: &str, kind: &str, image: &RgbaImage) -> PathBuf {
+    let output_dir = workspace_path(OUTPUT_DIR);
+    let path = output_dir.join(format!("{}.{}.png", name, kind));
+    fs::create_dir_all(&output_dir).unwrap();
+    image.save(&path).unwrap();
+    path
+}
+
+/// The absolute path of a path relative to the workspace root, whatever the working directory.
+fn workspace_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().join(path)
+}
+
+/// The absolute difference of each channel, scaled up.
+fn diff(a: &RgbaImage, b: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(a.width(), a.height(), |x, y| {
+        let (a, b) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        let channel = |c: usize| a[c].abs_diff(b[c]).saturating_mul(DIFF_SCALE);
+        Rgba([channel(0), channel(1), channel(2), 255])
+    })
+}
+
+/// The mean structural similarity of the luma of two images of the same size, over overlapping
+/// windows. See Wang et al., "Image Quality Assessment: From Error Visibility to Structural
+/// Similarity".
+fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let luma = |pixel: &Rgba<u8>| 0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64;
+
+    let (width, height) = a.dimensions();
+    let window_size = SSIM_WINDOW.min(width).min(height);
+    let stride = (window_size / 2).max(1);
+    let count = (window_size * window_size) as f64;
+
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for y in (0..=height - window_size).step_by(stride as usize) {
+        for x in (0..=width - window_size).step_by(stride as usize) {
+            let pixels = (y..y + window_size)
+                .flat_map(|y| (x..x + window_size).map(move |x| (x, y)))
+                .map(|(x, y)| (luma(a.get_pixel(x, y)), luma(b.get_pixel(x, y))))
+                .collect::<Vec<_>>();
+
+            let mean_a = pixels.iter().map(|(a, _)| a).sum::<f64>() / count;
+            let mean_b = pixels.iter().map(|(_, b)| b).sum::<f64>() / count;
+            let (variance_a, variance_b, covariance) =
+                pixels.iter().fold((0.0, 0.0, 0.0), |(var_a, var_b, cov), (a, b)| {
+                    let (da, db) = (a - mean_a, b - mean_b);
+                    (var_a + da * da, var_b + db * db, cov + da * db)
+                });
+            let (variance_a, variance_b, covariance) = (variance_a / count, variance_b / count, covariance / count);
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
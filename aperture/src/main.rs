@@ -1,16 +1,57 @@
 mod app;
+mod cli;
 mod render;
 mod state;
 mod vulkan;
+mod trace;
 mod world;
 
+#[cfg(test)]
+mod golden;
+
 use app::*;
+use cli::Command;
+use render::gpu_names;
+
+use std::env;
+use std::process;
 
 #[tokio::main]
 async fn main() {
-    run_app(AppConfig {
-        title: "Aperture Renderer".to_string(),
-        width: 1560,
-        height: 980,
-    });
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n\nRun `aperture --help` for usage.", err);
+            process::exit(2);
+        }
+    };
+
+    let result = match command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Command::ListGpus => {
+            let names = gpu_names();
+            if names.is_empty() {
+                println!("No Vulkan devices are available");
+            }
+            for (index, name) in names.iter().enumerate() {
+                println!("{}: {}", index, name);
+            }
+            return;
+        }
+        Command::Window(config) => run_app(AppConfig {
+            title: "Aperture Renderer".to_string(),
+            ..config
+        }),
+        Command::Image(config, output) => render_headless(config, &output),
+        Command::Turntable(config, output_dir, frames) => render_turntable(config, &output_dir, frames),
+        Command::PathTrace(config, output, settings) => render_path_traced(config, &output, settings),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,50 @@
+use crate::vulkan::Pipeline;
+
+/// The anti-aliasing technique used when rendering the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    /// Multisampled rendering with the given number of samples per pixel.
+    /// The scene is resolved to a single sample at the end of the scene render pass.
+    Msaa(u32),
+    /// Fast approximate anti-aliasing, applied as a post-process when presenting the scene.
+    Fxaa,
+    /// Temporal anti-aliasing. The projection is jittered by a sub-pixel offset each frame, and
+    /// the result is blended with the reprojected history of previous frames.
+    Taa,
+}
+
+impl Default for AntiAliasing {
+    fn default() -> Self {
+        Self::Msaa(4)
+    }
+}
+
+impl AntiAliasing {
+    /// The number of samples per pixel of the scene render pass attachments.
+    pub fn samples(&self) -> u32 {
+        match self {
+            Self::Msaa(samples) => *samples,
+            _ => 1,
+        }
+    }
+
+    /// The next technique in the cycle used to switch between them at runtime.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::None => Self::Fxaa,
+            Self::Fxaa => Self::Taa,
+            Self::Taa => Self::Msaa(2),
+            Self::Msaa(samples) if *samples < 8 => Self::Msaa(samples * 2),
+            Self::Msaa(_) => Self::None,
+        }
+    }
+
+    /// The pipeline used to draw the resolved scene to the swapchain.
+    pub fn present_pipeline(&self) -> Pipeline {
+        match self {
+            Self::Fxaa => Pipeline::Fxaa,
+            _ => Pipeline::Present,
+        }
+    }
+}
@@ -1,49 +1,170 @@
+use crate::render::anti_aliasing::AntiAliasing;
+use crate::render::deferred::{DeferredLighting, GBuffer, ALBEDO_FORMAT, EMISSIVE_FORMAT, MATERIAL_FORMAT, NORMAL_FORMAT};
+use crate::render::dof::DepthOfFieldPass;
+use crate::render::gpu_driven;
+use crate::render::hiz::HiZ;
+use crate::render::motion_blur::MotionBlurPass;
+use crate::render::occlusion::OcclusionCullingPass;
+use crate::render::oit::WeightedBlendedPass;
+use crate::render::render_path::RenderPath;
 use crate::render::shaders::*;
+use crate::render::ssao::AmbientOcclusionPass;
+use crate::render::ssr::ReflectionPass;
+use crate::render::taa::TemporalAntiAliasing;
 use crate::vulkan::Pipeline;
 
+use aperture_common::VPos;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
 use vulkano::device::{Device, DeviceExtensions, Features, Queue};
-use vulkano::format::Format;
+use vulkano::format::{ClearValue, Format};
 use vulkano::image::view::ImageView;
-use vulkano::image::{AttachmentImage, ImageUsage, SwapchainImage};
-use vulkano::instance::{Instance, PhysicalDevice, PhysicalDeviceType};
+use vulkano::image::{AttachmentImage, ImageUsage, ImageViewAbstract, SampleCount, SampleCounts, SwapchainImage};
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice, PhysicalDeviceType};
 use vulkano::pipeline::GraphicsPipelineAbstract;
 use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
-use vulkano::swapchain::{
-    self, Surface, Swapchain, SwapchainAcquireFuture, SwapchainCreationError,
-};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::swapchain::{self, Surface, Swapchain, SwapchainCreationError};
+use vulkano::sync::{self, GpuFuture};
 use vulkano::Version;
 use vulkano_win::VkSurfaceBuild;
 use winit::dpi::PhysicalSize;
 use winit::event_loop::EventLoop;
 use winit::window::{Window, WindowBuilder};
 
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
 use std::sync::Arc;
 
+/// The format of the image the scene is rendered into, before being presented to the swapchain.
+pub const SCENE_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The format of the image frames are presented to when rendering offscreen. Like a window's
+/// swapchain, the scene is encoded to sRGB when it's drawn to it.
+pub const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8Srgb;
+
+/// The format of the per-pixel screen-space motion written alongside the scene.
+pub const VELOCITY_FORMAT: Format = Format::R16G16Sfloat;
+
+/// The format of the view space normals and linear depth written alongside the scene.
+pub const NORMAL_DEPTH_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The format of the specular reflection weight and roughness written alongside the scene.
+pub const SPECULAR_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// A single triangle covering the whole screen, used to draw post-processing passes.
+pub const FULLSCREEN_TRIANGLE: [VPos; 3] = [
+    VPos { position: [-1.0, -1.0, 0.0] },
+    VPos { position: [ 3.0, -1.0, 0.0] },
+    VPos { position: [-1.0,  3.0, 0.0] },
+];
+
+/// What the frames are presented to.
+pub enum RenderTarget {
+    /// The swapchain of a window.
+    Window {
+        surface: Arc<Surface<Window>>,
+        swapchain: Arc<Swapchain<Window>>,
+        images: Vec<Arc<SwapchainImage<Window>>>,
+    },
+    /// An image which can be copied back to the CPU, for rendering without a display.
+    Offscreen { image: Arc<AttachmentImage> },
+}
+
+impl RenderTarget {
+    /// Creates an offscreen image of the given size to render into. Returns `None` if the device
+    /// doesn't support images that large.
+    pub fn offscreen(device: Arc<Device>, dimensions: [u32; 2]) -> Option<Self> {
+        let usage = ImageUsage {
+            color_attachment: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        let image = AttachmentImage::with_usage(device, dimensions, OFFSCREEN_FORMAT, usage).ok()?;
+
+        Some(Self::Offscreen { image })
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            Self::Window { swapchain, .. } => swapchain.format(),
+            Self::Offscreen { .. } => OFFSCREEN_FORMAT,
+        }
+    }
+
+    /// The size of the images presented to.
+    fn image_dimensions(&self) -> [u32; 2] {
+        match self {
+            Self::Window { images, .. } => images[0].dimensions(),
+            Self::Offscreen { image } => image.dimensions(),
+        }
+    }
+
+    fn views(&self) -> Vec<Arc<dyn ImageViewAbstract + Send + Sync>> {
+        match self {
+            Self::Window { images, .. } => images
+                .iter()
+                .map(|image| ImageView::new(image.clone()).unwrap() as Arc<dyn ImageViewAbstract + Send + Sync>)
+                .collect(),
+            Self::Offscreen { image } => vec![ImageView::new(image.clone()).unwrap()],
+        }
+    }
+}
+
 pub struct VulkanBase {
     // Vulkan
-    pub instance: Arc<Instance>,
-    pub surface: Arc<Surface<Window>>,
+    pub target: RenderTarget,
     pub device: Arc<Device>,
-    pub swapchain: Arc<Swapchain<Window>>,
-    pub swapchain_images: Vec<Arc<SwapchainImage<Window>>>,
     pub queue: Arc<Queue>,
     pub render_pass: Arc<RenderPass>,
+    pub present_render_pass: Arc<RenderPass>,
+    pub post_render_pass: Arc<RenderPass>,
     // TODO do we need pre-load all pipelines?
     pub pipeline_type: Pipeline,
     pub pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     pub environment_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub transparent_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// Draws the primitives as `pipeline` does, but only their edges.
+    pub wireframe_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// The pipeline of the GPU-driven path, if the device supports it with the forward path.
+    pub gpu_driven_pipeline: Option<Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    pub present_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub present_set: Arc<dyn DescriptorSet + Send + Sync>,
+    pub fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+    pub scene_images: SceneImages,
+    pub scene_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
     pub framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    pub ambient_occlusion: AmbientOcclusionPass,
+    pub hiz: HiZ,
+    pub occlusion_culling: Option<OcclusionCullingPass>,
+    pub reflections: ReflectionPass,
+    pub depth_of_field: DepthOfFieldPass,
+    pub motion_blur: MotionBlurPass,
+    pub taa: Option<TemporalAntiAliasing>,
+    pub deferred_lighting: Option<DeferredLighting>,
+    pub weighted_blended: WeightedBlendedPass,
 
     pub shaders: Shaders,
+    pub anti_aliasing: AntiAliasing,
+    pub render_path: RenderPath,
 
     pub recreate_swapchain: bool,
 }
 
 impl VulkanBase {
-    pub fn new(title: String, width: u32, height: u32) -> (Self, EventLoop<()>) {
+    /// Creates a window, and the device and swapchain to present to it. Returns `None` if there's
+    /// no Vulkan implementation, or no device, or not the selected one, can present to the window.
+    pub fn new(
+        title: String,
+        width: u32,
+        height: u32,
+        anti_aliasing: AntiAliasing,
+        gpu: Option<&GpuSelection>,
+    ) -> Option<(Self, EventLoop<()>)> {
         let instance = {
             let extensions = vulkano_win::required_extensions();
-            Instance::new(None, Version::V1_1, &extensions, None).unwrap()
+            Instance::new(None, Version::V1_1, &extensions, None).ok()?
         };
 
         let event_loop = EventLoop::new();
@@ -54,69 +175,14 @@ impl VulkanBase {
             .build_vk_surface(&event_loop, instance.clone())
             .unwrap();
 
-        // We need a `Swapchain` for rendering to a surface.
-        let device_extensions = DeviceExtensions {
-            khr_swapchain: true,
-            ..DeviceExtensions::none()
-        };
-
-        // Choose which physical device to use.
-        let (physical_device, queue_family) = PhysicalDevice::enumerate(&instance)
-            .filter(|&p| {
-                // Ensure device list supports our extensions.
-                DeviceExtensions::supported_by_device(p).intersection(&device_extensions)
-                    == device_extensions
-            })
-            .filter_map(|p| {
-                // Select a queue family that supports graphics operations, and surface rendering.
-                p.queue_families()
-                    .find(|&q| q.supports_graphics() && surface.is_supported(q).unwrap_or(false))
-                    .map(|q| (p, q))
-            })
-            // Assign the devices that pass the filters a score, and pick the lowest.
-            .min_by_key(|(p, _)| match p.properties().device_type.unwrap() {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-            })
-            .unwrap();
-
-        println!(
-            "Using device: {} (type: {:?})",
-            physical_device.properties().device_name.as_ref().unwrap(),
-            physical_device.properties().device_type.unwrap(),
-        );
-
-        // Initialise the device. To do this we need to pass:
-        //
-        // - The physical device to connect to
-        // - A list of optional features and extensions that we need.
-        // - The list of queues that we are going to use.
-        //
-        // This then returns the device and a list of creates queues.
-        let (device, mut queues) = Device::new(
-            physical_device,
-            &Features {
-                fill_mode_non_solid: true,
-                ..Features::none()
-            },
-            // Add any extensions that are required by the device to the extensions we want to enable.
-            &DeviceExtensions::required_extensions(physical_device).union(&device_extensions),
-            [(queue_family, 0.5)].iter().cloned(),
-        )
-        .unwrap();
-
-        // We are only using one queue, so get the first element of the `queues` iterator.
-        let queue = queues.next().unwrap();
+        let (device, queue) = create_device(&instance, Some(&surface), gpu)?;
 
         // Create the swapchain.
         // The swapchain allocates the color buffers that will contain the image visible on the screen.
         // These images are then returned alongside the swapchain.
         let (swapchain, images) = {
             // Get the surface capabilities.
-            let caps = surface.capabilities(physical_device).unwrap();
+            let caps = surface.capabilities(device.physical_device()).unwrap();
 
             // Get the alpha mode.
             // Here, the window is opaque.
@@ -137,34 +203,73 @@ impl VulkanBase {
                 .unwrap()
         };
 
-        // Create a render pass.
-        let render_pass = Arc::new(
+        let target = RenderTarget::Window {
+            surface,
+            swapchain,
+            images,
+        };
+
+        Some((Self::with_target(device, queue, target, anti_aliasing), event_loop))
+    }
+
+    /// Creates a device without a window, rendering into an offscreen image of the given size.
+    /// Works with any device that supports graphics, including software implementations such as
+    /// lavapipe. Returns `None` if there's no Vulkan implementation, or no device, or not the
+    /// selected one, supports graphics.
+    pub fn headless(width: u32, height: u32, anti_aliasing: AntiAliasing, gpu: Option<&GpuSelection>) -> Option<Self> {
+        let instance = Instance::new(None, Version::V1_1, &InstanceExtensions::none(), None).ok()?;
+        let (device, queue) = create_device(&instance, None, gpu)?;
+        let target = RenderTarget::offscreen(device.clone(), [width, height])?;
+
+        Some(Self::with_target(device, queue, target, anti_aliasing))
+    }
+
+    fn with_target(device: Arc<Device>, queue: Arc<Queue>, target: RenderTarget, anti_aliasing: AntiAliasing) -> Self {
+        let physical_device = device.physical_device();
+
+        // Create the render passes.
+        //
+        // The scene is rendered into an intermediate HDR image, which is then drawn to the
+        // swapchain image by a second render pass. This gives post-processing passes such as FXAA
+        // access to the rendered scene. Passes in between, such as the TAA resolve, draw from one
+        // HDR image to another with the post-processing render pass.
+        let render_path = RenderPath::default();
+        let anti_aliasing = supported_anti_aliasing(physical_device, render_path, anti_aliasing);
+        let render_pass = create_scene_render_pass(device.clone(), render_path, anti_aliasing.samples());
+
+        let present_render_pass = create_present_render_pass(device.clone(), target.format());
+
+        let post_render_pass = Arc::new(
             vulkano::single_pass_renderpass!(
                 device.clone(),
                 attachments: {
                     color: {
-                        load: Clear,
+                        load: DontCare,
                         store: Store,
-                        format: swapchain.format(),
-                        samples: 1,
-                    },
-                    depth: {
-                        load: Clear,
-                        store: DontCare,
-                        format: Format::D16Unorm,
+                        format: SCENE_FORMAT,
                         samples: 1,
                     }
                 },
                 pass: {
                     color: [color],
-                    depth_stencil: {depth}
+                    depth_stencil: {}
                 }
             )
             .unwrap(),
         );
 
-        // Load the shaders.
-        let shaders = Shaders::new(device.clone());
+        let fullscreen_vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::vertex_buffer(),
+            false,
+            FULLSCREEN_TRIANGLE.iter().cloned(),
+        )
+        .unwrap();
+
+        // Load the shaders, catching push constant blocks that no longer match them before
+        // drawing with them.
+        let shaders =
+            Shaders::new(device.clone()).unwrap_or_else(|err| panic!("Invalid material push constants: {}", err));
 
         // Initialise the render pipeline and framebuffer for the current window size.
         //
@@ -176,131 +281,985 @@ impl VulkanBase {
         //
         // The framebuffer is the render target.
         let pipeline_type = Pipeline::Shaded;
-        let (pipeline, environment_pipeline, framebuffers) = window_size_dependent_setup(
+        let setup = window_size_dependent_setup(
             device.clone(),
             &shaders,
-            &images,
+            &target,
             render_pass.clone(),
+            present_render_pass.clone(),
+            post_render_pass.clone(),
             pipeline_type,
+            render_path,
+            anti_aliasing,
         )
         .unwrap();
 
-        (
-            Self {
-                instance,
-                surface,
-                device,
-                swapchain,
-                swapchain_images: images,
-                render_pass,
-                queue,
-                pipeline_type,
-                pipeline,
-                environment_pipeline,
-                framebuffers,
-                shaders,
-                recreate_swapchain: false,
-            },
-            event_loop,
-        )
+        Self {
+            target,
+            device,
+            render_pass,
+            present_render_pass,
+            post_render_pass,
+            queue,
+            pipeline_type,
+            pipeline: setup.pipeline,
+            environment_pipeline: setup.environment_pipeline,
+            transparent_pipeline: setup.transparent_pipeline,
+            wireframe_pipeline: setup.wireframe_pipeline,
+            gpu_driven_pipeline: setup.gpu_driven_pipeline,
+            present_pipeline: setup.present_pipeline,
+            present_set: setup.present_set,
+            fullscreen_vertex_buffer,
+            scene_images: setup.scene_images,
+            scene_framebuffer: setup.scene_framebuffer,
+            framebuffers: setup.framebuffers,
+            ambient_occlusion: setup.ambient_occlusion,
+            hiz: setup.hiz,
+            occlusion_culling: setup.occlusion_culling,
+            reflections: setup.reflections,
+            depth_of_field: setup.depth_of_field,
+            motion_blur: setup.motion_blur,
+            taa: setup.taa,
+            deferred_lighting: setup.deferred_lighting,
+            weighted_blended: setup.weighted_blended,
+            shaders,
+            anti_aliasing,
+            render_path,
+            recreate_swapchain: false,
+        }
     }
 
-    pub fn acquire_next_swapchain_image(
-        &mut self,
-    ) -> Option<(usize, SwapchainAcquireFuture<Window>)> {
-        let (image_num, suboptimal, acquire_future) =
-            match swapchain::acquire_next_image(self.swapchain.clone(), None) {
-                Ok(r) => r,
-                Err(swapchain::AcquireError::OutOfDate) => {
-                    self.recreate_swapchain = true;
-                    return None;
-                }
-                Err(e) => panic!("Failed to acquire next image: {:?}", e),
-            };
+    /// Acquires the next image to present to, returning its index and the future to wait on
+    /// before drawing to it. Returns `None` if the swapchain needs to be recreated first.
+    pub fn acquire_next_image(&mut self) -> Option<(usize, Box<dyn GpuFuture + Send + Sync>)> {
+        let swapchain = match &self.target {
+            RenderTarget::Window { swapchain, .. } => swapchain.clone(),
+            RenderTarget::Offscreen { .. } => return Some((0, Box::new(sync::now(self.device.clone())))),
+        };
+
+        let (image_num, suboptimal, acquire_future) = match swapchain::acquire_next_image(swapchain, None) {
+            Ok(r) => r,
+            Err(swapchain::AcquireError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                return None;
+            }
+            Err(e) => panic!("Failed to acquire next image: {:?}", e),
+        };
 
         if suboptimal {
             self.recreate_swapchain = true;
         }
 
-        Some((image_num, acquire_future))
+        Some((image_num, Box::new(acquire_future)))
     }
 
+    /// Presents the image at `image_num` after `future`, if rendering to a window.
+    pub fn present<F>(&self, future: F, image_num: usize) -> Box<dyn GpuFuture + Send + Sync>
+    where
+        F: GpuFuture + Send + Sync + 'static,
+    {
+        match &self.target {
+            RenderTarget::Window { swapchain, .. } => {
+                Box::new(future.then_swapchain_present(self.queue.clone(), swapchain.clone(), image_num))
+            }
+            RenderTarget::Offscreen { .. } => Box::new(future),
+        }
+    }
+
+    /// Recreates the swapchain and the resources that depend on the size of the images presented
+    /// to. The offscreen image keeps its size.
     pub fn resize_setup(&mut self) {
-        let (new_swapchain, new_swapchain_images) = match self
-            .swapchain
-            .recreate()
-            .dimensions(self.dimensions())
-            .build()
-        {
-            Ok(r) => r,
-            Err(SwapchainCreationError::UnsupportedDimensions) => return,
-            Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
-        };
+        let dimensions = self.dimensions();
+        if let RenderTarget::Window { swapchain, images, .. } = &mut self.target {
+            let (new_swapchain, new_images) = match swapchain.recreate().dimensions(dimensions).build() {
+                Ok(r) => r,
+                Err(SwapchainCreationError::UnsupportedDimensions) => return,
+                Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+            };
 
-        self.swapchain = new_swapchain;
+            *swapchain = new_swapchain;
+            *images = new_images;
+        }
 
-        if let Some((new_pipeline, new_environment_pipeline, new_framebuffers)) = window_size_dependent_setup(
+        if let Some(setup) = window_size_dependent_setup(
             self.device.clone(),
             &self.shaders,
-            &new_swapchain_images,
+            &self.target,
             self.render_pass.clone(),
+            self.present_render_pass.clone(),
+            self.post_render_pass.clone(),
             self.pipeline_type,
+            self.render_path,
+            self.anti_aliasing,
         ) {
-            self.pipeline = new_pipeline;
-            self.environment_pipeline = new_environment_pipeline;
-            self.framebuffers = new_framebuffers;
+            self.pipeline = setup.pipeline;
+            self.environment_pipeline = setup.environment_pipeline;
+            self.transparent_pipeline = setup.transparent_pipeline;
+            self.wireframe_pipeline = setup.wireframe_pipeline;
+            self.gpu_driven_pipeline = setup.gpu_driven_pipeline;
+            self.present_pipeline = setup.present_pipeline;
+            self.present_set = setup.present_set;
+            self.scene_images = setup.scene_images;
+            self.scene_framebuffer = setup.scene_framebuffer;
+            self.framebuffers = setup.framebuffers;
+            self.ambient_occlusion = setup.ambient_occlusion;
+            self.hiz = setup.hiz;
+            self.occlusion_culling = setup.occlusion_culling;
+            self.reflections = setup.reflections;
+            self.depth_of_field = setup.depth_of_field;
+            self.motion_blur = setup.motion_blur;
+            self.taa = setup.taa;
+            self.deferred_lighting = setup.deferred_lighting;
+            self.weighted_blended = setup.weighted_blended;
             self.recreate_swapchain = false;
-        } else {
+        }
+    }
+
+    /// Presents to another target, returning the previous one. The resources that depend on the
+    /// target are recreated before the next frame is drawn.
+    pub fn replace_target(&mut self, target: RenderTarget) -> RenderTarget {
+        if target.format() != self.target.format() {
+            self.present_render_pass = create_present_render_pass(self.device.clone(), target.format());
+        }
+
+        self.recreate_swapchain = true;
+        mem::replace(&mut self.target, target)
+    }
+
+    /// Switches the anti-aliasing technique. The render targets and pipelines are recreated
+    /// before the next frame is drawn.
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        let anti_aliasing = supported_anti_aliasing(self.device.physical_device(), self.render_path, anti_aliasing);
+
+        if anti_aliasing.samples() != self.anti_aliasing.samples() {
+            self.render_pass = create_scene_render_pass(self.device.clone(), self.render_path, anti_aliasing.samples());
+        }
+
+        self.anti_aliasing = anti_aliasing;
+        self.recreate_swapchain = true;
+    }
+
+    /// Switches between forward and deferred shading. The render targets and pipelines are
+    /// recreated before the next frame is drawn.
+    pub fn set_render_path(&mut self, render_path: RenderPath) {
+        if render_path == self.render_path {
             return;
         }
+
+        self.render_path = render_path;
+        self.anti_aliasing = supported_anti_aliasing(self.device.physical_device(), render_path, self.anti_aliasing);
+        self.render_pass = create_scene_render_pass(self.device.clone(), render_path, self.anti_aliasing.samples());
+        self.recreate_swapchain = true;
+    }
+
+    /// The clear values for the attachments of the scene render pass.
+    pub fn scene_clear_values(&self) -> Vec<ClearValue> {
+        let color = [0.1, 0.1, 0.1, 1.0].into();
+        let black = || [0.0, 0.0, 0.0, 0.0].into();
+
+        if self.render_path == RenderPath::Deferred {
+            // The colour, ambient and specular are written for every pixel by the lighting pass.
+            // The background colour is cleared into the emissive attachment instead, which the
+            // lighting pass copies to the colour where nothing was drawn.
+            return vec![
+                ClearValue::None,
+                black(),
+                ClearValue::None,
+                black(),
+                ClearValue::None,
+                1f32.into(),
+                color,
+                ClearValue::None,
+                ClearValue::None,
+                ClearValue::None,
+            ];
+        }
+
+        if self.anti_aliasing.samples() > 1 {
+            vec![
+                color,
+                black(),
+                black(),
+                black(),
+                black(),
+                1f32.into(),
+                ClearValue::None,
+                ClearValue::None,
+                ClearValue::None,
+                ClearValue::None,
+                ClearValue::None,
+            ]
+        } else {
+            vec![color, black(), black(), black(), black(), 1f32.into()]
+        }
     }
 
     pub fn dimensions(&self) -> [u32; 2] {
-        let size = self.surface.window().inner_size();
-        [size.width, size.height]
+        match &self.target {
+            RenderTarget::Window { surface, .. } => {
+                let size = surface.window().inner_size();
+                [size.width, size.height]
+            }
+            RenderTarget::Offscreen { image } => image.dimensions(),
+        }
+    }
+
+    /// The HDR image drawn to the target by the last frame, holding the linear radiance before
+    /// it's encoded for display.
+    pub fn presented_image(&self) -> Arc<AttachmentImage> {
+        match &self.taa {
+            Some(taa) => taa.presented_image(),
+            None => ImageView::image(&self.scene_images.color).clone(),
+        }
+    }
+}
+
+/// Creates the render pass the scene is drawn to the target with.
+fn create_present_render_pass(device: Arc<Device>, format: Format) -> Arc<RenderPass> {
+    Arc::new(
+        vulkano::single_pass_renderpass!(
+            device,
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: format,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )
+        .unwrap(),
+    )
+}
+
+/// Which physical device to render with, rather than the most capable one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GpuSelection {
+    /// The device at the index in the order Vulkan lists them, as `gpu_names` does.
+    Index(usize),
+    /// The first device whose name contains the text, ignoring case.
+    Name(String),
+}
+
+impl GpuSelection {
+    fn matches(&self, index: usize, device: &PhysicalDevice) -> bool {
+        match self {
+            Self::Index(selected) => index == *selected,
+            Self::Name(name) => device_name(device).to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for GpuSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{}", index),
+            Self::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The names of the Vulkan devices, in the order `GpuSelection::Index` selects them. Empty if
+/// there's no Vulkan implementation.
+pub fn gpu_names() -> Vec<String> {
+    match Instance::new(None, Version::V1_1, &InstanceExtensions::none(), None) {
+        Ok(instance) => PhysicalDevice::enumerate(&instance).map(|p| device_name(&p)).collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn device_name(device: &PhysicalDevice) -> String {
+    device.properties().device_name.clone().unwrap_or_default()
+}
+
+/// Chooses a physical device, preferring discrete GPUs, and creates the device and its graphics
+/// queue. With a surface, the device and queue must also be able to present to it. Only the
+/// selected device is considered, if there is one. Returns `None` if no device is suitable.
+fn create_device(
+    instance: &Arc<Instance>,
+    surface: Option<&Arc<Surface<Window>>>,
+    gpu: Option<&GpuSelection>,
+) -> Option<(Arc<Device>, Arc<Queue>)> {
+    // We need a `Swapchain` for rendering to a surface.
+    let device_extensions = DeviceExtensions {
+        khr_swapchain: surface.is_some(),
+        ..DeviceExtensions::none()
+    };
+
+    // Choose which physical device to use.
+    let (physical_device, queue_family) = PhysicalDevice::enumerate(instance)
+        .enumerate()
+        .filter(|(index, p)| gpu.is_none_or(|gpu| gpu.matches(*index, p)))
+        .map(|(_, p)| p)
+        .filter(|&p| {
+            // Ensure device list supports our extensions.
+            DeviceExtensions::supported_by_device(p).intersection(&device_extensions)
+                == device_extensions
+        })
+        .filter_map(|p| {
+            // Select a queue family that supports graphics operations, and surface rendering.
+            p.queue_families()
+                .find(|&q| {
+                    q.supports_graphics()
+                        && surface.is_none_or(|surface| surface.is_supported(q).unwrap_or(false))
+                })
+                .map(|q| (p, q))
+        })
+        // Assign the devices that pass the filters a score, and pick the lowest.
+        .min_by_key(|(p, _)| match p.properties().device_type.unwrap() {
+            PhysicalDeviceType::DiscreteGpu => 0,
+            PhysicalDeviceType::IntegratedGpu => 1,
+            PhysicalDeviceType::VirtualGpu => 2,
+            PhysicalDeviceType::Cpu => 3,
+            PhysicalDeviceType::Other => 4,
+        })?;
+
+    println!(
+        "Using device: {} (type: {:?})",
+        physical_device.properties().device_name.as_ref().unwrap(),
+        physical_device.properties().device_type.unwrap(),
+    );
+
+    // Initialise the device. To do this we need to pass:
+    //
+    // - The physical device to connect to
+    // - A list of optional features and extensions that we need.
+    // - The list of queues that we are going to use.
+    //
+    // This then returns the device and a list of creates queues.
+    let (device, mut queues) = Device::new(
+        physical_device,
+        &Features {
+            fill_mode_non_solid: true,
+            // The GPU-driven path is only used when its features are supported.
+            ..gpu_driven::features().intersection(physical_device.supported_features())
+        },
+        // Add any extensions that are required by the device to the extensions we want to enable.
+        &DeviceExtensions::required_extensions(physical_device).union(&device_extensions),
+        [(queue_family, 0.5)].iter().cloned(),
+    )
+    .unwrap();
+
+    // We are only using one queue, so get the first element of the `queues` iterator.
+    Some((device, queues.next().unwrap()))
+}
+
+/// Clamps the MSAA sample count to the highest count supported by the device for both the scene
+/// colour and depth attachments. Deferred shading doesn't support MSAA at all.
+fn supported_anti_aliasing(
+    physical_device: PhysicalDevice,
+    render_path: RenderPath,
+    anti_aliasing: AntiAliasing,
+) -> AntiAliasing {
+    let requested = match anti_aliasing {
+        AntiAliasing::Msaa(samples) => samples,
+        _ => return anti_aliasing,
+    };
+
+    if render_path == RenderPath::Deferred {
+        println!("MSAA is not supported with deferred shading, disabling anti-aliasing");
+        return AntiAliasing::None;
+    }
+
+    let properties = physical_device.properties();
+    let color_counts = properties.framebuffer_color_sample_counts.unwrap();
+    let depth_counts = properties.framebuffer_depth_sample_counts.unwrap();
+
+    let supports = |counts: &SampleCounts, samples: u32| match samples {
+        2 => counts.sample2,
+        4 => counts.sample4,
+        8 => counts.sample8,
+        16 => counts.sample16,
+        32 => counts.sample32,
+        64 => counts.sample64,
+        _ => false,
+    };
+
+    let samples = [64, 32, 16, 8, 4, 2]
+        .iter()
+        .copied()
+        .filter(|&samples| samples <= requested)
+        .find(|&samples| supports(&color_counts, samples) && supports(&depth_counts, samples));
+
+    match samples {
+        Some(samples) => {
+            if samples != requested {
+                println!("{}x MSAA is not supported, using {}x instead", requested, samples);
+            }
+            AntiAliasing::Msaa(samples)
+        }
+        None => AntiAliasing::None,
+    }
+}
+
+/// Creates the render pass the scene is drawn with.
+///
+/// Alongside the colour, the scene pass writes:
+///
+/// - The screen-space motion of each pixel since the last frame, used to reproject the history for
+///   TAA.
+/// - The ambient lighting, added to the colour after it has been attenuated by the ambient
+///   occlusion pass.
+/// - The view space normal and linear depth, used to calculate the ambient occlusion and trace
+///   the screen-space reflections.
+/// - The weight of the specular reflections and the roughness, used to add the reflections.
+///
+/// When multisampling, these attachments are resolved into single-sampled images at the end of the
+/// pass, so the post-processing passes never have to deal with multisampled images.
+///
+/// With deferred shading, the pass has three subpasses. The first writes the G-buffer, along with
+/// the velocity and the normals and depth, the second lights it, writing the rest, and the third
+/// draws the transparent primitives, which can't be stored in the G-buffer.
+fn create_scene_render_pass(device: Arc<Device>, render_path: RenderPath, samples: u32) -> Arc<RenderPass> {
+    if render_path == RenderPath::Deferred {
+        Arc::new(
+            vulkano::ordered_passes_renderpass!(
+                device,
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Clear,
+                        store: Store,
+                        format: VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    ambient: {
+                        load: DontCare,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    },
+                    normal_depth: {
+                        load: Clear,
+                        store: Store,
+                        format: NORMAL_DEPTH_FORMAT,
+                        samples: 1,
+                    },
+                    specular: {
+                        load: DontCare,
+                        store: Store,
+                        format: SPECULAR_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    },
+                    emissive: {
+                        load: Clear,
+                        store: DontCare,
+                        format: EMISSIVE_FORMAT,
+                        samples: 1,
+                    },
+                    albedo: {
+                        load: DontCare,
+                        store: DontCare,
+                        format: ALBEDO_FORMAT,
+                        samples: 1,
+                    },
+                    material: {
+                        load: DontCare,
+                        store: DontCare,
+                        format: MATERIAL_FORMAT,
+                        samples: 1,
+                    },
+                    normal: {
+                        load: DontCare,
+                        store: DontCare,
+                        format: NORMAL_FORMAT,
+                        samples: 1,
+                    }
+                },
+                passes: [
+                    {
+                        // The locations match the skybox shader, which writes the environment
+                        // into the emissive attachment.
+                        color: [emissive, velocity, albedo, normal_depth, material, normal],
+                        depth_stencil: {depth},
+                        input: []
+                    },
+                    {
+                        color: [color, ambient, specular],
+                        depth_stencil: {},
+                        input: [emissive, albedo, material, normal, normal_depth]
+                    },
+                    {
+                        // Transparent primitives are blended over the lit scene.
+                        color: [color],
+                        depth_stencil: {depth},
+                        input: []
+                    }
+                ]
+            )
+            .unwrap(),
+        )
+    } else if samples > 1 {
+        Arc::new(
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: DontCare,
+                        format: SCENE_FORMAT,
+                        samples: samples,
+                    },
+                    velocity: {
+                        load: Clear,
+                        store: DontCare,
+                        format: VELOCITY_FORMAT,
+                        samples: samples,
+                    },
+                    ambient: {
+                        load: Clear,
+                        store: DontCare,
+                        format: SCENE_FORMAT,
+                        samples: samples,
+                    },
+                    normal_depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: NORMAL_DEPTH_FORMAT,
+                        samples: samples,
+                    },
+                    specular: {
+                        load: Clear,
+                        store: DontCare,
+                        format: SPECULAR_FORMAT,
+                        samples: samples,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::D16Unorm,
+                        samples: samples,
+                    },
+                    resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    },
+                    velocity_resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    ambient_resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    },
+                    normal_depth_resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: NORMAL_DEPTH_FORMAT,
+                        samples: 1,
+                    },
+                    specular_resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: SPECULAR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color, velocity, ambient, normal_depth, specular],
+                    depth_stencil: {depth},
+                    resolve: [resolve, velocity_resolve, ambient_resolve, normal_depth_resolve, specular_resolve]
+                }
+            )
+            .unwrap(),
+        )
+    } else {
+        Arc::new(
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Clear,
+                        store: Store,
+                        format: VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    ambient: {
+                        load: Clear,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    },
+                    normal_depth: {
+                        load: Clear,
+                        store: Store,
+                        format: NORMAL_DEPTH_FORMAT,
+                        samples: 1,
+                    },
+                    specular: {
+                        load: Clear,
+                        store: Store,
+                        format: SPECULAR_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color, velocity, ambient, normal_depth, specular],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        )
     }
 }
 
+/// The single-sampled images written by the scene render pass, read by the passes after it.
+///
+/// The images can also be copied to and from, to read back what was rendered, or to replace it
+/// with the result of a post-processing pass.
+#[derive(Clone)]
+pub struct SceneImages {
+    pub color: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub velocity: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub ambient: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub normal_depth: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub specular: Arc<ImageView<Arc<AttachmentImage>>>,
+}
+
+impl SceneImages {
+    fn new(device: Arc<Device>, dimensions: [u32; 2]) -> Option<Self> {
+        let usage = ImageUsage {
+            sampled: true,
+            input_attachment: true,
+            transfer_source: true,
+            transfer_destination: true,
+            ..ImageUsage::none()
+        };
+        let image = |format| ImageView::new(AttachmentImage::with_usage(device.clone(), dimensions, format, usage).ok()?).ok();
+
+        Some(Self {
+            color: image(SCENE_FORMAT)?,
+            velocity: image(VELOCITY_FORMAT)?,
+            ambient: image(SCENE_FORMAT)?,
+            normal_depth: image(NORMAL_DEPTH_FORMAT)?,
+            specular: image(SPECULAR_FORMAT)?,
+        })
+    }
+}
+
+/// Resources that depend on the window size, recreated whenever it changes.
+struct WindowSizeDependent {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    environment_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    transparent_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    wireframe_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    gpu_driven_pipeline: Option<Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    present_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    present_set: Arc<dyn DescriptorSet + Send + Sync>,
+    scene_images: SceneImages,
+    scene_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    ambient_occlusion: AmbientOcclusionPass,
+    hiz: HiZ,
+    occlusion_culling: Option<OcclusionCullingPass>,
+    reflections: ReflectionPass,
+    depth_of_field: DepthOfFieldPass,
+    motion_blur: MotionBlurPass,
+    taa: Option<TemporalAntiAliasing>,
+    deferred_lighting: Option<DeferredLighting>,
+    weighted_blended: WeightedBlendedPass,
+}
+
 /// Called during initialisation, and whenever the window is resized.
+#[allow(clippy::too_many_arguments)]
 fn window_size_dependent_setup(
     device: Arc<Device>,
     shaders: &Shaders,
-    images: &[Arc<SwapchainImage<Window>>],
+    target: &RenderTarget,
     render_pass: Arc<RenderPass>,
+    present_render_pass: Arc<RenderPass>,
+    post_render_pass: Arc<RenderPass>,
     pipeline: Pipeline,
-) -> Option<(
-    Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
-)> {
-    let dimensions = images[0].dimensions();
+    render_path: RenderPath,
+    anti_aliasing: AntiAliasing,
+) -> Option<WindowSizeDependent> {
+    let dimensions = target.image_dimensions();
+    let samples = SampleCount::try_from(anti_aliasing.samples()).unwrap();
 
+    // The depth is kept after the scene render pass, to test the transparent primitives
+    // accumulated for order-independent transparency against.
     let depth_buffer = ImageView::new(
-        match AttachmentImage::transient(device.clone(), dimensions, Format::D16Unorm) {
+        match AttachmentImage::multisampled(device.clone(), dimensions, samples, Format::D16Unorm) {
             Err(_) => return None,
             Ok(image) => image,
         },
     )
     .unwrap();
 
-    let framebuffers = images
-        .iter()
-        .map(|image| {
-            let view = ImageView::new(image.clone()).unwrap();
+    // The single-sampled scene images, read by the post-processing and present passes.
+    let scene_images = SceneImages::new(device.clone(), dimensions)?;
+
+    // The G-buffer attachments not shared with the passes after the scene.
+    let gbuffer = match render_path {
+        RenderPath::Deferred => Some(GBuffer::new(device.clone(), dimensions)?),
+        RenderPath::Forward => None,
+    };
+
+    let scene_framebuffer = if let Some(gbuffer) = &gbuffer {
+        Arc::new(
+            Framebuffer::start(render_pass.clone())
+                .add(scene_images.color.clone())
+                .unwrap()
+                .add(scene_images.velocity.clone())
+                .unwrap()
+                .add(scene_images.ambient.clone())
+                .unwrap()
+                .add(scene_images.normal_depth.clone())
+                .unwrap()
+                .add(scene_images.specular.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .add(gbuffer.emissive.clone())
+                .unwrap()
+                .add(gbuffer.albedo.clone())
+                .unwrap()
+                .add(gbuffer.material.clone())
+                .unwrap()
+                .add(gbuffer.normal.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ) as Arc<dyn FramebufferAbstract + Send + Sync>
+    } else if anti_aliasing.samples() > 1 {
+        let multisampled = |format| {
+            ImageView::new(AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, format).ok()?).ok()
+        };
+
+        Arc::new(
+            Framebuffer::start(render_pass.clone())
+                .add(multisampled(SCENE_FORMAT)?)
+                .unwrap()
+                .add(multisampled(VELOCITY_FORMAT)?)
+                .unwrap()
+                .add(multisampled(SCENE_FORMAT)?)
+                .unwrap()
+                .add(multisampled(NORMAL_DEPTH_FORMAT)?)
+                .unwrap()
+                .add(multisampled(SPECULAR_FORMAT)?)
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .add(scene_images.color.clone())
+                .unwrap()
+                .add(scene_images.velocity.clone())
+                .unwrap()
+                .add(scene_images.ambient.clone())
+                .unwrap()
+                .add(scene_images.normal_depth.clone())
+                .unwrap()
+                .add(scene_images.specular.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ) as Arc<dyn FramebufferAbstract + Send + Sync>
+    } else {
+        Arc::new(
+            Framebuffer::start(render_pass.clone())
+                .add(scene_images.color.clone())
+                .unwrap()
+                .add(scene_images.velocity.clone())
+                .unwrap()
+                .add(scene_images.ambient.clone())
+                .unwrap()
+                .add(scene_images.normal_depth.clone())
+                .unwrap()
+                .add(scene_images.specular.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ) as Arc<dyn FramebufferAbstract + Send + Sync>
+    };
+
+    let framebuffers = target
+        .views()
+        .into_iter()
+        .map(|view| {
             Arc::new(
-                Framebuffer::start(render_pass.clone())
+                Framebuffer::start(present_render_pass.clone())
                     .add(view)
                     .unwrap()
-                    .add(depth_buffer.clone())
-                    .unwrap()
                     .build()
                     .unwrap(),
             ) as Arc<dyn FramebufferAbstract + Send + Sync>
         })
         .collect::<Vec<_>>();
 
+    // Deferred shading draws the primitives with the G-buffer shader, which shares the material
+    // descriptor sets of the forward shader.
+    let (pipeline, wireframe_pipeline) = match render_path {
+        RenderPath::Forward => (pipeline, Pipeline::Wireframe),
+        RenderPath::Deferred => (Pipeline::GBuffer, Pipeline::GBufferWireframe),
+    };
     let pipeline = pipeline.create(device.clone(), dimensions, shaders, render_pass.clone());
+    let wireframe_pipeline = wireframe_pipeline.create(device.clone(), dimensions, shaders, render_pass.clone());
     let environment_pipeline = Pipeline::Cubemap.create(device.clone(), dimensions, shaders, render_pass.clone());
+    let transparent_pipeline = Pipeline::Transparent.create(device.clone(), dimensions, shaders, render_pass.clone());
+    let gpu_driven_pipeline = if render_path == RenderPath::Forward && gpu_driven::supported(&device) {
+        Some(Pipeline::GpuDriven.create(device.clone(), dimensions, shaders, render_pass.clone()))
+    } else {
+        None
+    };
+
+    let deferred_lighting = gbuffer.map(|gbuffer| {
+        DeferredLighting::new(device.clone(), shaders, dimensions, render_pass, &gbuffer, &scene_images)
+    });
+    let present_pipeline = anti_aliasing
+        .present_pipeline()
+        .create(device.clone(), dimensions, shaders, present_render_pass);
+
+    // Sample the scene with clamped, filtered lookups, as FXAA samples between texels.
+    let scene_sampler = Sampler::new(
+        device.clone(),
+        Filter::Linear,
+        Filter::Linear,
+        MipmapMode::Nearest,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        0.0,
+        1.0,
+        0.0,
+        0.0,
+    )
+    .unwrap();
+
+    let layout = present_pipeline.layout().descriptor_set_layout(0).unwrap();
+    let present_set = Arc::new(
+        PersistentDescriptorSet::start(layout.clone())
+            .add_sampled_image(scene_images.color.clone(), scene_sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+    );
+
+    let ambient_occlusion = AmbientOcclusionPass::new(
+        device.clone(),
+        shaders,
+        dimensions,
+        &scene_images,
+    )?;
+
+    let weighted_blended = WeightedBlendedPass::new(
+        device.clone(),
+        shaders,
+        dimensions,
+        anti_aliasing.samples(),
+        depth_buffer.clone(),
+        &scene_images,
+    )?;
+
+    let hiz = HiZ::new(device.clone(), shaders, dimensions, &scene_images)?;
+
+    // The depth pyramid is built from single-sampled depth, so occlusion culling isn't supported
+    // with MSAA.
+    let occlusion_culling = if anti_aliasing.samples() == 1 {
+        Some(OcclusionCullingPass::new(
+            device.clone(),
+            shaders,
+            dimensions,
+            depth_buffer,
+            &scene_images,
+            &hiz,
+        ))
+    } else {
+        None
+    };
+
+    let reflections = ReflectionPass::new(
+        device.clone(),
+        shaders,
+        dimensions,
+        post_render_pass.clone(),
+        &scene_images,
+        &hiz,
+    )?;
+
+    let depth_of_field = DepthOfFieldPass::new(
+        device.clone(),
+        shaders,
+        dimensions,
+        post_render_pass.clone(),
+        &scene_images,
+    )?;
+
+    let motion_blur = MotionBlurPass::new(
+        device.clone(),
+        shaders,
+        dimensions,
+        post_render_pass.clone(),
+        &scene_images,
+    )?;
+
+    let taa = if anti_aliasing == AntiAliasing::Taa {
+        Some(TemporalAntiAliasing::new(
+            device,
+            shaders,
+            dimensions,
+            post_render_pass,
+            scene_images.color.clone(),
+            scene_images.velocity.clone(),
+            present_pipeline.clone(),
+            scene_sampler,
+        )?)
+    } else {
+        None
+    };
 
-    Some((pipeline, environment_pipeline, framebuffers))
+    Some(WindowSizeDependent {
+        pipeline,
+        environment_pipeline,
+        transparent_pipeline,
+        wireframe_pipeline,
+        gpu_driven_pipeline,
+        present_pipeline,
+        present_set,
+        scene_images,
+        scene_framebuffer,
+        framebuffers,
+        ambient_occlusion,
+        hiz,
+        occlusion_culling,
+        reflections,
+        depth_of_field,
+        motion_blur,
+        taa,
+        deferred_lighting,
+        weighted_blended,
+    })
 }
@@ -1,21 +1,65 @@
-use cgmath::{EuclideanSpace, InnerSpace, Matrix3, Matrix4, One, Point3, Rad, Vector3};
+use aperture_mesh::Aabb;
+use cgmath::{Angle, Deg, EuclideanSpace, InnerSpace, Matrix3, Matrix4, MetricSpace, One, Point3, Rad, Vector3};
 
 const SPEED: f32 = 2.0;
 
+/// How the camera is moved with the mouse and keyboard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Orbits around, zooms towards and pans the point the camera looks at, with the mouse.
+    #[default]
+    Orbit,
+    /// Moves freely with the keyboard and turns in place with the mouse, as in a first-person
+    /// game. The mouse wheel changes the speed.
+    Fly,
+}
+
+impl CameraMode {
+    /// The next mode in the cycle used to switch between them at runtime.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Orbit => Self::Fly,
+            Self::Fly => Self::Orbit,
+        }
+    }
+}
+
 pub struct Camera {
     pub view_matrix: Matrix4<f32>,
     pub eye: Point3<f32>,
     pub look_at: Point3<f32>,
     pub up: Vector3<f32>,
+    /// The distance from the eye at which objects are in focus, in metres.
+    pub focus_distance: f32,
+    /// The ratio of the focal length to the diameter of the aperture.
+    pub f_stop: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new(Point3::new(2.0, 0.5, 2.0), Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0))
+    }
 }
 
 impl Camera {
+    /// The vertical field of view.
+    pub const FOV_Y: Deg<f32> = Deg(60.0);
+
+    /// The height of the simulated sensor in metres, that of a full-frame 35mm camera.
+    pub const SENSOR_HEIGHT: f32 = 0.024;
+
+    /// The distances to the near and far clip planes, in metres.
+    pub const Z_NEAR: f32 = 0.1;
+    pub const Z_FAR: f32 = 100.0;
+
     pub fn new(eye: Point3<f32>, look_at: Point3<f32>, up: Vector3<f32>) -> Self {
         let mut camera = Self {
             view_matrix: Matrix4::one(),
             eye,
             look_at,
             up,
+            focus_distance: eye.distance(look_at),
+            f_stop: 2.8,
         };
 
         camera.update_view_matrix();
@@ -67,14 +111,78 @@ impl Camera {
         self.update_view_matrix();
     }
 
+    /// The focal length in metres that gives the field of view on the simulated sensor.
+    pub fn focal_length(&self) -> f32 {
+        0.5 * Self::SENSOR_HEIGHT / (Self::FOV_Y / 2.0).tan()
+    }
+
+    /// The diameter of the aperture in metres.
+    pub fn aperture_diameter(&self) -> f32 {
+        self.focal_length() / self.f_stop
+    }
+
     pub fn view_matrix(&self) -> Matrix4<f32> {
         self.view_matrix
     }
 
+    /// The projection of the view onto an image of the aspect ratio, without the TAA jitter.
+    pub fn projection(aspect_ratio: f32) -> Matrix4<f32> {
+        cgmath::perspective(Self::FOV_Y, aspect_ratio, Self::Z_NEAR, Self::Z_FAR)
+    }
+
+    /// The transform from world space to the clip space the world is rasterised in, without the
+    /// TAA jitter. The up vector points down the image, which mirrors the view, so the vertex
+    /// shaders mirror the projected x back, as this does.
+    pub fn clip_matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0) * Self::projection(aspect_ratio) * self.view_matrix
+    }
+
     pub fn update_view_matrix(&mut self) {
         self.view_matrix = Matrix4::look_at_rh(self.eye, self.look_at, self.up);
     }
 
+    /// Looks at the centre of the bounds from the current direction, from far enough away that
+    /// they fit in the field of view, and focuses on the centre.
+    pub fn frame(&mut self, aabb: &Aabb) {
+        let view_dir = self.view_dir();
+        let center = aabb.center();
+
+        // The distance at which a sphere around the box fits in the vertical field of view.
+        let radius = aabb.extents().magnitude().max(0.01);
+        let distance = radius / (Self::FOV_Y / 2.0).sin();
+
+        self.look_at = center;
+        self.eye = center - view_dir * distance;
+        self.focus_distance = distance;
+        self.update_view_matrix();
+    }
+
+    /// Turns the camera in place, by `yaw` radians about its up vector and then `pitch` radians
+    /// about its right vector, keeping the distance to the point it looks at. The pitch stops
+    /// short of looking along the up vector.
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        let up = self.up.normalize();
+        let distance = self.eye.distance(self.look_at);
+
+        let direction = Matrix3::from_axis_angle(up, Rad(yaw)) * self.view_dir();
+
+        // A positive pitch turns towards the up vector.
+        let angle_from_up = direction.dot(up).clamp(-1.0, 1.0).acos();
+        let pitch = angle_from_up - (angle_from_up - pitch).clamp(0.01, std::f32::consts::PI - 0.01);
+        let right = direction.cross(up).normalize();
+        let direction = Matrix3::from_axis_angle(right, Rad(pitch)) * direction;
+
+        self.look_at = self.eye + direction * distance;
+        self.update_view_matrix();
+    }
+
+    /// Moves the camera and the point it looks at.
+    pub fn fly(&mut self, displacement: Vector3<f32>) {
+        self.eye += displacement;
+        self.look_at += displacement;
+        self.update_view_matrix();
+    }
+
     pub fn set_position(&mut self, position: Point3<f32>) {
         self.eye = position;
         self.update_view_matrix();
@@ -0,0 +1,107 @@
+use image::{ImageBuffer, ImageError, Rgba, RgbaImage};
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// The linear radiance of a frame, before it's encoded for display.
+pub type RadianceImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+/// How a screenshot of the viewer is taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Screenshot {
+    /// The multiple of the window size the screenshot is rendered at. Above one, the view is
+    /// rendered with more samples per displayed pixel, giving a high-resolution image.
+    pub scale: u32,
+    /// Whether the linear radiance is also saved, as an OpenEXR file next to the image.
+    pub radiance: bool,
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            radiance: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The frame as displayed was presented to a window, so can't be read back.
+    NotReadable,
+    /// The device can't render images of the size.
+    UnsupportedSize([u32; 2]),
+    Image(ImageError),
+    Exr(exr::error::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotReadable => write!(f, "Only frames rendered offscreen can be read back as displayed"),
+            Self::UnsupportedSize([width, height]) => write!(f, "The device can't render {}x{} images", width, height),
+            Self::Image(err) => write!(f, "Failed to write image: {}", err),
+            Self::Exr(err) => write!(f, "Failed to write EXR: {}", err),
+        }
+    }
+}
+
+impl Error for SaveError {}
+
+impl From<ImageError> for SaveError {
+    fn from(err: ImageError) -> Self {
+        Self::Image(err)
+    }
+}
+
+impl From<exr::error::Error> for SaveError {
+    fn from(err: exr::error::Error) -> Self {
+        Self::Exr(err)
+    }
+}
+
+/// Whether the path is of an OpenEXR file, which is written with the linear radiance.
+pub fn is_exr(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"))
+}
+
+/// Writes the radiance to an OpenEXR file. The alpha isn't meaningful, so only the colour is kept.
+pub fn write_exr(path: &Path, image: &RadianceImage) -> Result<(), SaveError> {
+    exr::prelude::write_rgb_file(path, image.width() as usize, image.height() as usize, |x, y| {
+        let pixel = image.get_pixel(x as u32, y as u32);
+        (pixel[0], pixel[1], pixel[2])
+    })?;
+
+    Ok(())
+}
+
+/// Saves radiance rendered on the CPU the way the renderer's frames are saved: as OpenEXR for
+/// `.exr` paths, and otherwise encoded as sRGB like the displayed image, without tonemapping.
+pub fn save_radiance(path: &Path, image: &RadianceImage) -> Result<(), SaveError> {
+    if is_exr(path) {
+        return write_exr(path, image);
+    }
+
+    let encoded = RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        Rgba([encode_srgb(pixel[0]), encode_srgb(pixel[1]), encode_srgb(pixel[2]), 255])
+    });
+    encoded.save(path)?;
+
+    Ok(())
+}
+
+/// Encodes a linear value as an 8-bit sRGB value, as the swapchain's sRGB format does.
+fn encode_srgb(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let encoded = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+
+    (encoded * 255.0).round() as u8
+}
@@ -0,0 +1,158 @@
+use crate::render::base::SceneImages;
+use crate::render::frames::FrameRing;
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use cgmath::Matrix4;
+use vulkano::buffer::{BufferAccess, BufferUsage, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::RenderPass;
+
+use std::sync::Arc;
+
+/// The format of the emissive colour, which also holds the environment behind the scene.
+pub const EMISSIVE_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The format of the base colour.
+pub const ALBEDO_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// The format of the metalness, roughness, ambient occlusion and reflectance.
+pub const MATERIAL_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// The format of the world space normals.
+pub const NORMAL_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The G-buffer attachments that only live for the duration of the deferred scene render pass.
+///
+/// The velocity and the view space normals and depth are also part of the G-buffer, but are
+/// written to the scene images, as the passes after the scene read them.
+pub struct GBuffer {
+    pub emissive: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub albedo: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub material: Arc<ImageView<Arc<AttachmentImage>>>,
+    pub normal: Arc<ImageView<Arc<AttachmentImage>>>,
+}
+
+impl GBuffer {
+    pub fn new(device: Arc<Device>, dimensions: [u32; 2]) -> Option<Self> {
+        let image = |format| {
+            ImageView::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, format).ok()?).ok()
+        };
+
+        Some(Self {
+            emissive: image(EMISSIVE_FORMAT)?,
+            albedo: image(ALBEDO_FORMAT)?,
+            material: image(MATERIAL_FORMAT)?,
+            normal: image(NORMAL_FORMAT)?,
+        })
+    }
+}
+
+/// Lights the G-buffer in the second subpass of the deferred scene render pass, writing the same
+/// scene images as the forward shader.
+pub struct DeferredLighting {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // The uniforms of each frame in flight, with the sets reading them.
+    sets: FrameRing<Arc<dyn DescriptorSet + Send + Sync>>,
+    uniform_buffers: FrameRing<Arc<DeviceLocalBuffer<deferred_lighting_frag::ty::Data>>>,
+}
+
+impl DeferredLighting {
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        render_pass: Arc<RenderPass>,
+        gbuffer: &GBuffer,
+        scene_images: &SceneImages,
+    ) -> Self {
+        let pipeline = Pipeline::DeferredLighting.create(device.clone(), dimensions, shaders, render_pass);
+
+        let uniform_buffers = FrameRing::new(|_| {
+            DeviceLocalBuffer::<deferred_lighting_frag::ty::Data>::new(
+                device.clone(),
+                BufferUsage::uniform_buffer_transfer_destination(),
+                device.active_queue_families(),
+            )
+            .unwrap()
+        });
+
+        let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
+        let sets = FrameRing::new(|frame| {
+            Arc::new(
+                PersistentDescriptorSet::start(layout.clone())
+                    .add_image(gbuffer.emissive.clone())
+                    .unwrap()
+                    .add_image(gbuffer.albedo.clone())
+                    .unwrap()
+                    .add_image(gbuffer.material.clone())
+                    .unwrap()
+                    .add_image(gbuffer.normal.clone())
+                    .unwrap()
+                    .add_image(scene_images.normal_depth.clone())
+                    .unwrap()
+                    .add_buffer(uniform_buffers.get(frame).clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn DescriptorSet + Send + Sync>
+        });
+
+        Self {
+            pipeline,
+            sets,
+            uniform_buffers,
+        }
+    }
+
+    /// Records the update of the lighting uniforms of the frame slot `frame`. Must be recorded
+    /// outside of a render pass.
+    pub fn update(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        frame: usize,
+        data: deferred_lighting_frag::ty::Data,
+    ) {
+        builder
+            .update_buffer(self.uniform_buffers.get(frame).clone(), Arc::new(data))
+            .unwrap();
+    }
+
+    /// Records the lighting pass with the uniforms of the frame slot `frame`. The builder must be in
+    /// the lighting subpass.
+    ///
+    /// `view` and `proj` are the unjittered matrices the scene was drawn with, used to
+    /// reconstruct positions from depth.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+        point_light_count: u32,
+        frame: usize,
+    ) {
+        let push_constants = deferred_lighting_frag::ty::LightingPushConstants {
+            view: view.into(),
+            projection_scale: [proj.x.x, proj.y.y],
+            point_light_count,
+        };
+
+        builder
+            .draw(
+                self.pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                self.sets.get(frame).clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use crate::render::base::{SceneImages, SCENE_FORMAT};
+use crate::render::camera::Camera;
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::ImageView;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+/// The format of the near field circle of confusion tiles.
+const TILE_FORMAT: Format = Format::R16Sfloat;
+
+/// The width and height in pixels of the tiles the near field circle of confusion is gathered in.
+const TILE_SIZE: u32 = 16;
+
+/// The shape of the out of focus highlights, determined by the shape of the camera's aperture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bokeh {
+    Circle,
+    Hexagon,
+}
+
+impl Bokeh {
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Circle => Self::Hexagon,
+            Self::Hexagon => Self::Circle,
+        }
+    }
+}
+
+/// Settings for the depth of field effect. The amount of blur is determined by the camera's focus
+/// distance and f-stop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthOfField {
+    pub enabled: bool,
+    pub bokeh: Bokeh,
+    /// The largest circle of confusion, in pixels.
+    pub max_coc: f32,
+}
+
+impl Default for DepthOfField {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bokeh: Bokeh::Circle,
+            max_coc: 24.0,
+        }
+    }
+}
+
+/// Blurs the scene colour according to the circle of confusion of each pixel.
+///
+/// The circle of confusion is calculated from the depth and packed alongside the colour, then the
+/// largest near field circle of confusion is gathered per tile, to find how far blurred foreground
+/// objects spread. Finally, the blurred scene is gathered back into the scene colour image.
+pub struct DepthOfFieldPass {
+    coc_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    coc_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    coc_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    tile_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    tile_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    tile_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    gather_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    gather_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    gather_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    dimensions: [u32; 2],
+}
+
+impl DepthOfFieldPass {
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        post_render_pass: Arc<RenderPass>,
+        scene_images: &SceneImages,
+    ) -> Option<Self> {
+        let tile_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    tiles: {
+                        load: DontCare,
+                        store: Store,
+                        format: TILE_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [tiles],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let tile_dimensions = [
+            dimensions[0].div_ceil(TILE_SIZE),
+            dimensions[1].div_ceil(TILE_SIZE),
+        ];
+
+        let color_coc_view = ImageView::new(AttachmentImage::sampled(device.clone(), dimensions, SCENE_FORMAT).ok()?).unwrap();
+        let tile_view = ImageView::new(AttachmentImage::sampled(device.clone(), tile_dimensions, TILE_FORMAT).ok()?).unwrap();
+
+        let framebuffer = |render_pass: &Arc<RenderPass>, view| {
+            Arc::new(
+                Framebuffer::start(render_pass.clone())
+                    .add(view)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        };
+
+        let sampler = |filter| {
+            Sampler::new(
+                device.clone(),
+                filter,
+                filter,
+                MipmapMode::Nearest,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            .unwrap()
+        };
+        let linear_sampler = sampler(Filter::Linear);
+        let nearest_sampler = sampler(Filter::Nearest);
+
+        let coc_pipeline = Pipeline::DofCoc.create(device.clone(), dimensions, shaders, post_render_pass.clone());
+        let tile_pipeline = Pipeline::DofTiles.create(device.clone(), tile_dimensions, shaders, tile_render_pass.clone());
+        let gather_pipeline = Pipeline::Dof.create(device.clone(), dimensions, shaders, post_render_pass.clone());
+
+        let layout = coc_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let coc_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(scene_images.color.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.normal_depth.clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = tile_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let tile_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(color_coc_view.clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = gather_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let gather_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(color_coc_view.clone(), linear_sampler)
+                .unwrap()
+                .add_sampled_image(tile_view.clone(), nearest_sampler)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Some(Self {
+            coc_pipeline,
+            coc_framebuffer: framebuffer(&post_render_pass, color_coc_view),
+            coc_set,
+            tile_pipeline,
+            tile_framebuffer: framebuffer(&tile_render_pass, tile_view),
+            tile_set,
+            gather_pipeline,
+            gather_framebuffer: framebuffer(&post_render_pass, scene_images.color.clone()),
+            gather_set,
+            dimensions,
+        })
+    }
+
+    /// Records the depth of field passes, replacing the scene colour with the blurred result.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+        settings: &DepthOfField,
+        camera: &Camera,
+    ) {
+        let coc_push_constants = dof_coc_frag::ty::CocPushConstants {
+            focus_distance: camera.focus_distance,
+            focal_length: camera.focal_length(),
+            aperture_diameter: camera.aperture_diameter(),
+            pixels_per_metre: self.dimensions[1] as f32 / Camera::SENSOR_HEIGHT,
+            max_coc: settings.max_coc,
+        };
+
+        let tile_push_constants = dof_tiles_frag::ty::TilePushConstants {
+            tile_size: TILE_SIZE,
+        };
+
+        let gather_push_constants = dof_frag::ty::DofPushConstants {
+            bokeh_shape: match settings.bokeh {
+                Bokeh::Circle => 0,
+                Bokeh::Hexagon => 1,
+            },
+        };
+
+        builder
+            .begin_render_pass(
+                self.coc_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.coc_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer.clone()],
+                self.coc_set.clone(),
+                coc_push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap()
+            .begin_render_pass(
+                self.tile_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.tile_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer.clone()],
+                self.tile_set.clone(),
+                tile_push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap()
+            .begin_render_pass(
+                self.gather_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.gather_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                self.gather_set.clone(),
+                gather_push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
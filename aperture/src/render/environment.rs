@@ -1,7 +1,9 @@
+use crate::render::frames::FrameRing;
 use crate::render::shaders::*;
 use crate::world::cube::Cube;
 
 use aperture_common::VPos;
+use aperture_mesh::Texture;
 use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess};
 use vulkano::descriptor::descriptor::ShaderStages;
 use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
@@ -15,26 +17,27 @@ use vulkano::pipeline::shader::EntryPointAbstract;
 use vulkano::pipeline::vertex::SingleBufferDefinition;
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass, Subpass};
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, Subpass};
 use vulkano::sampler::{Compare, Sampler};
 
 use std::iter;
 use std::sync::Arc;
 
 pub struct Environment {
-    pub cube: Cube,
-    
     pub cubemap_image: Arc<dyn ImageAccess + Send + Sync>,
+    pub cubemap_view: Arc<ImageView<Arc<StorageImage>>>,
     pub skybox_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
-    pub skybox_uniform_buffer: Arc<dyn TypedBufferAccess<Content = cube_vert::ty::Data> + Send + Sync>,
-    pub skybox_set: Arc<dyn DescriptorSet + Send + Sync>,
+    /// The camera uniforms of each frame in flight, with the sets reading them.
+    pub skybox_uniform_buffers: FrameRing<Arc<dyn TypedBufferAccess<Content = cube_vert::ty::Data> + Send + Sync>>,
+    pub skybox_sets: FrameRing<Arc<dyn DescriptorSet + Send + Sync>>,
+    /// The diffuse irradiance of the environment, as 9 spherical harmonic coefficients.
+    pub irradiance: [[f32; 4]; 9],
 
     pub framebuffer_image: Arc<dyn ImageAccess + Send + Sync>,
     pub offscreen_cube_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     pub offscreen_cube_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
     pub offscreen_cube_uniform_buffer: Arc<dyn TypedBufferAccess<Content = offscreen_cube_vert::ty::Data> + Send + Sync>,  
     pub offscreen_cube_set: Arc<dyn DescriptorSet + Send + Sync>,
-    pub offscreen_render_pass: Arc<RenderPass>,
     pub offscreen_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
 }
 
@@ -44,12 +47,13 @@ impl Environment {
     pub const CUBE_IMAGE_LAYERS: u32 = 6;
 
     pub fn new(
+        cube: &Cube,
         skybox_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         shaders: &Shaders,
         device: Arc<Device>,
         queue: Arc<Queue>,
     ) -> Self {
-        let cube = Cube::textured();
+        let irradiance = irradiance_coefficients(&cube.texture);
 
         let (image, _) = ImmutableImage::from_iter(
             cube.texture.pixels.iter().cloned(),
@@ -75,12 +79,14 @@ impl Environment {
         )
         .unwrap();
 
-        let skybox_uniform_buffer = DeviceLocalBuffer::<cube_vert::ty::Data>::new(
-            device.clone(),
-            BufferUsage::uniform_buffer_transfer_destination(),
-            device.active_queue_families(),
-        )
-        .unwrap();
+        let skybox_uniform_buffers = FrameRing::new(|_| {
+            DeviceLocalBuffer::<cube_vert::ty::Data>::new(
+                device.clone(),
+                BufferUsage::uniform_buffer_transfer_destination(),
+                device.active_queue_families(),
+            )
+            .unwrap() as Arc<dyn TypedBufferAccess<Content = cube_vert::ty::Data> + Send + Sync>
+        });
 
         let offscreen_render_pass = Arc::new(
             vulkano::single_pass_renderpass!(
@@ -249,28 +255,84 @@ impl Environment {
         );
 
         let layout = skybox_pipeline.layout().descriptor_set_layout(0).unwrap();
-        let skybox_set = Arc::new(PersistentDescriptorSet::start(layout.clone())
-            .add_buffer(skybox_uniform_buffer.clone())
-            .unwrap()
-            .add_sampled_image(cubemap_image_view.clone(), cube_texture_sampler.clone())
-            .unwrap()
-            .build()
-            .unwrap()
-        );
+        let skybox_sets = FrameRing::new(|frame| {
+            Arc::new(PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(skybox_uniform_buffers.get(frame).clone())
+                .unwrap()
+                .add_sampled_image(cubemap_image_view.clone(), cube_texture_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap()
+            ) as Arc<dyn DescriptorSet + Send + Sync>
+        });
 
         Self {
-            cube,
             cubemap_image,
+            cubemap_view: cubemap_image_view,
             skybox_vertex_buffer,
-            skybox_uniform_buffer,
-            skybox_set,
+            skybox_uniform_buffers,
+            skybox_sets,
+            irradiance,
             framebuffer_image,
             offscreen_cube_pipeline,
             offscreen_cube_vertex_buffer,
             offscreen_cube_uniform_buffer,
             offscreen_cube_set,
-            offscreen_render_pass,
             offscreen_framebuffer,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Projects an equirectangular HDR image onto the first 9 spherical harmonics, for evaluating the
+/// diffuse irradiance of the environment in the shaders.
+fn irradiance_coefficients(texture: &Texture<f32>) -> [[f32; 4]; 9] {
+    use std::f32::consts::PI;
+
+    // There's no need to visit every texel of a large image for such a low frequency signal.
+    const MAX_SAMPLES: u32 = 512;
+    let step_x = (texture.width / MAX_SAMPLES).max(1);
+    let step_y = (texture.height / (MAX_SAMPLES / 2)).max(1);
+
+    let mut coefficients = [[0.0f32; 4]; 9];
+
+    for y in (0..texture.height).step_by(step_y as usize) {
+        // The inverse of the mapping in `offscreen_hdri.frag`.
+        let latitude = -((y as f32 + 0.5) / texture.height as f32 - 0.5) * PI;
+
+        // The solid angle covered by each sample shrinks towards the poles.
+        let solid_angle = latitude.cos()
+            * (2.0 * PI * step_x as f32 / texture.width as f32)
+            * (PI * step_y as f32 / texture.height as f32);
+
+        for x in (0..texture.width).step_by(step_x as usize) {
+            let longitude = ((x as f32 + 0.5) / texture.width as f32 - 0.5) * 2.0 * PI;
+
+            let dx = latitude.cos() * longitude.cos();
+            let dy = latitude.sin();
+            let dz = latitude.cos() * longitude.sin();
+
+            let basis = [
+                0.282095,
+                0.488603 * dy,
+                0.488603 * dz,
+                0.488603 * dx,
+                1.092548 * dx * dy,
+                1.092548 * dy * dz,
+                0.315392 * (3.0 * dz * dz - 1.0),
+                1.092548 * dx * dz,
+                0.546274 * (dx * dx - dy * dy),
+            ];
+
+            let index = ((y * texture.width + x) * 4) as usize;
+            let color = &texture.pixels[index..index + 3];
+
+            for (coefficient, basis) in coefficients.iter_mut().zip(basis.iter()) {
+                for channel in 0..3 {
+                    coefficient[channel] += color[channel] * basis * solid_angle;
+                }
+            }
+        }
+    }
+
+    coefficients
+}
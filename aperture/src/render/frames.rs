@@ -0,0 +1,123 @@
+use vulkano::device::Device;
+use vulkano::sync::{self, FenceSignalFuture, GpuFuture};
+
+use std::sync::Arc;
+
+/// The number of frames the CPU can record while the GPU renders the ones before.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
+/// The fence signalled when the GPU finishes a frame.
+pub type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>>;
+
+/// A copy of a resource for each frame in flight. The resources a frame writes on the CPU side
+/// each frame, such as uniforms, are taken from the frame's slot, so they aren't overwritten
+/// while the GPU is still reading them for an earlier frame.
+pub struct FrameRing<T> {
+    items: Vec<T>,
+}
+
+impl<T> FrameRing<T> {
+    /// Creates the resource of each slot.
+    pub fn new(create: impl FnMut(usize) -> T) -> Self {
+        Self {
+            items: (0..FRAMES_IN_FLIGHT).map(create).collect(),
+        }
+    }
+
+    /// The resource of the frame slot `frame`.
+    pub fn get(&self, frame: usize) -> &T {
+        &self.items[frame % FRAMES_IN_FLIGHT]
+    }
+}
+
+/// The frames submitted to the GPU, with a fence for each slot of the frame rings.
+///
+/// Before a frame writes the resources of its slot, it waits for the fence of the last frame
+/// that used the slot. Each frame's work is submitted after the previous frame's, so the GPU
+/// still renders the frames in order.
+pub struct FramesInFlight {
+    fences: Vec<Option<FrameFence>>,
+    // The uploads the next frame's work is submitted after.
+    uploads: Option<Box<dyn GpuFuture + Send + Sync>>,
+    // The number of frames submitted.
+    frame: usize,
+}
+
+impl FramesInFlight {
+    pub fn new() -> Self {
+        Self {
+            fences: vec![None; FRAMES_IN_FLIGHT],
+            uploads: None,
+            frame: 0,
+        }
+    }
+
+    /// The slot of the frame being recorded.
+    pub fn index(&self) -> usize {
+        self.frame % FRAMES_IN_FLIGHT
+    }
+
+    /// Releases the resources of the frames the GPU has finished.
+    pub fn cleanup_finished(&mut self) {
+        for fence in self.fences.iter_mut().flatten() {
+            // Only the renderer holds the fences, so they can be borrowed mutably.
+            if let Some(fence) = Arc::get_mut(fence) {
+                fence.cleanup_finished();
+            }
+        }
+    }
+
+    /// Blocks until the GPU has finished the last frame that used the current slot.
+    pub fn wait_for_slot(&mut self) {
+        let index = self.index();
+        if let Some(fence) = self.fences[index].take() {
+            if let Err(e) = fence.wait(None) {
+                println!("failed to wait for frame: {:?}", e);
+            }
+        }
+    }
+
+    /// Has the next work submitted wait for the upload, rather than blocking the CPU until it
+    /// finishes.
+    pub fn after_upload(&mut self, upload: Box<dyn GpuFuture + Send + Sync>) {
+        self.uploads = Some(match self.uploads.take() {
+            Some(uploads) => Box::new(uploads.join(upload)),
+            None => upload,
+        });
+    }
+
+    /// The future the current frame's work is submitted after, which is the previous frame's and
+    /// any uploads since.
+    pub fn previous_frame_end(&mut self, device: Arc<Device>) -> Box<dyn GpuFuture + Send + Sync> {
+        let last_frame_end = self.last_frame_end(device);
+
+        match self.uploads.take() {
+            Some(uploads) => Box::new(last_frame_end.join(uploads)),
+            None => last_frame_end,
+        }
+    }
+
+    /// The future of the last frame submitted finishing.
+    pub fn last_frame_end(&self, device: Arc<Device>) -> Box<dyn GpuFuture + Send + Sync> {
+        let previous = (self.frame + FRAMES_IN_FLIGHT - 1) % FRAMES_IN_FLIGHT;
+
+        match &self.fences[previous] {
+            Some(fence) => Box::new(fence.clone()),
+            None => Box::new(sync::now(device)),
+        }
+    }
+
+    /// Keeps the fence of the submitted frame, or `None` if it failed, and moves on to the next
+    /// slot.
+    pub fn submitted(&mut self, fence: Option<FrameFence>) {
+        let index = self.index();
+        self.fences[index] = fence;
+        self.frame += 1;
+    }
+}
+
+impl Default for FramesInFlight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
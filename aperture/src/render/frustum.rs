@@ -0,0 +1,61 @@
+use aperture_mesh::{Aabb, BoundingSphere};
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Vector4};
+
+/// The six planes bounding the volume a camera can see, used to cull primitives outside it.
+pub struct Frustum {
+    // Each plane is stored as its normal, pointing into the frustum, and its distance from the
+    // origin.
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a combined projection and view matrix.
+    /// See Gribb and Hartmann, "Fast Extraction of Viewing Frustum Planes from the
+    /// World-View-Projection Matrix".
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let rows = [view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3)];
+
+        let normalize = |plane: Vector4<f32>| plane / plane.truncate().magnitude();
+
+        Self {
+            planes: [
+                normalize(rows[3] + rows[0]),
+                normalize(rows[3] - rows[0]),
+                normalize(rows[3] + rows[1]),
+                normalize(rows[3] - rows[1]),
+                normalize(rows[3] + rows[2]),
+                normalize(rows[3] - rows[2]),
+            ],
+        }
+    }
+
+    /// The planes, in the layout of the culling shaders' push constants.
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        let mut planes = [[0.0; 4]; 6];
+        for (plane, frustum_plane) in planes.iter_mut().zip(&self.planes) {
+            *plane = (*frustum_plane).into();
+        }
+        planes
+    }
+
+    /// Whether any part of the sphere may be inside the frustum.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center.to_vec()) + plane.w >= -sphere.radius)
+    }
+
+    /// Whether any part of the box may be inside the frustum. Boxes outside the frustum near its
+    /// corners can still pass.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner of the box furthest along the plane's normal.
+            let x = if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x };
+            let y = if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y };
+            let z = if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z };
+
+            plane.x * x + plane.y * y + plane.z * z + plane.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,438 @@
+use crate::render::frames::FrameRing;
+use crate::render::frustum::Frustum;
+use crate::render::shaders::*;
+use crate::render::world_render::{PrimitiveInfo, WorldRender};
+use crate::vulkan::{BindlessSet, Binding, ImageView};
+use crate::world::World;
+
+use aperture_common::VPosNormTex;
+use aperture_mesh::{AlphaMode, Material};
+
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuBufferPool, DeviceLocalBuffer, ImmutableBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DynamicState, PrimaryAutoCommandBuffer};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::{Device, Features, Queue};
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract, GraphicsPipelineAbstract};
+use vulkano::sampler::Sampler;
+
+use std::mem;
+use std::sync::Arc;
+
+/// The size of the fragment shader's array of textures.
+const MAX_TEXTURES: usize = 128;
+
+/// The number of objects each workgroup of the culling shader tests.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The textures used in place of those a material doesn't have, in the order of the material's
+/// texture indices.
+const DUMMY_TEXTURES: [&str; 5] = ["DUMMY_COLOR", "DUMMY_NORMAL", "DUMMY_METAL_ROUGH", "DUMMY_AO", "DUMMY_EMISSIVE"];
+
+/// The device features the GPU-driven path needs, requested when they're supported.
+pub fn features() -> Features {
+    Features {
+        multi_draw_indirect: true,
+        draw_indirect_first_instance: true,
+        shader_sampled_image_array_dynamic_indexing: true,
+        ..Features::none()
+    }
+}
+
+/// Whether the device was created with the features of the GPU-driven path.
+pub fn supported(device: &Device) -> bool {
+    device.enabled_features().superset_of(&features())
+}
+
+/// The opaque and masked primitives of the world, drawn with a single indirect multi-draw.
+///
+/// Every primitive is an object with its bounds and the range of the shared vertex and index
+/// buffers it's drawn from. A compute shader culls the objects against the view frustum and writes
+/// a draw command for each, with no instances for those outside it. The transforms of each draw
+/// and instance, and every material, are read from storage buffers, with the textures indexed
+/// from one array, so nothing is bound between draws.
+///
+/// Blended and non-indexed primitives, and those whose geometry isn't in the shared buffers, are
+/// left to the per-primitive path.
+pub struct GpuDrivenScene {
+    // The indices of the drawn primitives in the world render's primitives, in draw order.
+    primitives: Vec<usize>,
+    material_indices: Vec<u32>,
+    instance_count: usize,
+
+    vertex_buffer: Arc<ImmutableBuffer<[VPosNormTex]>>,
+    index_buffer: Arc<ImmutableBuffer<[u32]>>,
+    frames: FrameRing<FrameBuffers>,
+
+    cull_pipeline: Arc<ComputePipeline>,
+    object_pool: CpuBufferPool<gpu_driven_cull_comp::ty::Object>,
+    instance_pool: CpuBufferPool<gpu_driven_vert::ty::Instance>,
+    draw_pool: CpuBufferPool<gpu_driven_vert::ty::Draw>,
+}
+
+/// The buffers written each frame, with the sets reading them.
+struct FrameBuffers {
+    objects: Arc<DeviceLocalBuffer<[gpu_driven_cull_comp::ty::Object]>>,
+    instances: Arc<DeviceLocalBuffer<[gpu_driven_vert::ty::Instance]>>,
+    draws: Arc<DeviceLocalBuffer<[gpu_driven_vert::ty::Draw]>>,
+    commands: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+    // The uniforms have the same layout as those of the per-primitive shaders.
+    vertex_uniform_buffer: Arc<DeviceLocalBuffer<vert::ty::Data>>,
+    fragment_uniform_buffer: Arc<DeviceLocalBuffer<frag::ty::Data>>,
+    set: Arc<BindlessSet>,
+    cull_set: Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+impl FrameBuffers {
+    fn new(
+        device: Arc<Device>,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        cull_pipeline: &Arc<ComputePipeline>,
+        object_count: usize,
+        instance_count: usize,
+        materials: Arc<dyn BufferAccess + Send + Sync>,
+        textures: Vec<(ImageView, Arc<Sampler>)>,
+    ) -> Self {
+        let usage = BufferUsage {
+            storage_buffer: true,
+            transfer_destination: true,
+            ..BufferUsage::none()
+        };
+        let command_usage = BufferUsage {
+            indirect_buffer: true,
+            ..usage
+        };
+
+        let objects = DeviceLocalBuffer::array(device.clone(), object_count, usage, device.active_queue_families()).unwrap();
+        let instances = DeviceLocalBuffer::array(device.clone(), instance_count, usage, device.active_queue_families()).unwrap();
+        let draws = DeviceLocalBuffer::array(device.clone(), object_count, usage, device.active_queue_families()).unwrap();
+        let commands =
+            DeviceLocalBuffer::array(device.clone(), object_count, command_usage, device.active_queue_families()).unwrap();
+        let vertex_uniform_buffer = DeviceLocalBuffer::new(
+            device.clone(),
+            BufferUsage::uniform_buffer_transfer_destination(),
+            device.active_queue_families(),
+        )
+        .unwrap();
+        let fragment_uniform_buffer = DeviceLocalBuffer::new(
+            device.clone(),
+            BufferUsage::uniform_buffer_transfer_destination(),
+            device.active_queue_families(),
+        )
+        .unwrap();
+
+        let set = Arc::new(BindlessSet::new(
+            pipeline.layout().descriptor_set_layout(0).unwrap().clone(),
+            vec![
+                Binding::Buffer(vertex_uniform_buffer.clone()),
+                Binding::Buffer(instances.clone()),
+                Binding::Buffer(draws.clone()),
+                Binding::Buffer(materials),
+                Binding::SampledImages(textures),
+                Binding::Buffer(fragment_uniform_buffer.clone()),
+            ],
+        ));
+
+        let cull_layout = cull_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let cull_set = Arc::new(
+            PersistentDescriptorSet::start(cull_layout.clone())
+                .add_buffer(objects.clone())
+                .unwrap()
+                .add_buffer(commands.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Self {
+            objects,
+            instances,
+            draws,
+            commands,
+            vertex_uniform_buffer,
+            fragment_uniform_buffer,
+            set,
+            cull_set,
+        }
+    }
+}
+
+impl GpuDrivenScene {
+    /// Gathers the primitives of the world that can be drawn on the GPU-driven path. Returns
+    /// `None` if there are none, or the materials use more textures than the shader can index.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        shaders: &Shaders,
+        pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        world_render: &WorldRender,
+        world: &World,
+    ) -> Option<Self> {
+        let primitive_info = &world_render.primitive_info;
+        let first = primitive_info.iter().find(|draw_info| draw_info.has_indices())?;
+        let vertex_buffer = first.vertex_buffer.buffer().clone();
+        let index_buffer = first.index_buffer.as_ref()?.buffer().clone();
+
+        let primitives = primitive_info
+            .iter()
+            .enumerate()
+            .filter(|(_, draw_info)| {
+                draw_info.material(world).alpha_mode != AlphaMode::Blend
+                    && Arc::ptr_eq(draw_info.vertex_buffer.buffer(), &vertex_buffer)
+                    && draw_info
+                        .index_buffer
+                        .as_ref()
+                        .is_some_and(|indices| Arc::ptr_eq(indices.buffer(), &index_buffer))
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        if primitives.is_empty() {
+            return None;
+        }
+
+        // The dummy textures come first, so a material's missing textures index them.
+        let mut texture_names = DUMMY_TEXTURES.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        // The default material is first, followed by the world's.
+        let material_names = world.materials.keys().collect::<Vec<_>>();
+        let materials = std::iter::once(&world.default_material)
+            .chain(material_names.iter().map(|name| &world.materials[name.as_str()]))
+            .collect::<Vec<_>>();
+        for material in &materials {
+            for name in material_textures(material).iter().flatten() {
+                if !texture_names.contains(name) {
+                    texture_names.push(name.clone());
+                }
+            }
+        }
+        if texture_names.len() > MAX_TEXTURES {
+            println!(
+                "The materials use {} textures, more than the {} of the GPU-driven path",
+                texture_names.len(),
+                MAX_TEXTURES
+            );
+            return None;
+        }
+
+        let texture_index = |name: &Option<String>, dummy: u32| {
+            name.as_ref()
+                .and_then(|name| texture_names.iter().position(|texture| texture == name))
+                .map_or(dummy, |index| index as u32)
+        };
+
+        let material_data = materials.iter().map(|material| {
+            let [base_color, normal, metal_rough, ao, emissive] = material_textures(material);
+            let alpha_cutoff = match material.alpha_mode {
+                AlphaMode::Mask => material.alpha_cutoff,
+                AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+            };
+
+            gpu_driven_frag::ty::Material {
+                base_color: material.base_color_factor.into(),
+                emissive: material.emissive_factor.into(),
+                emissive_tex: texture_index(&emissive, 4),
+                metalness: material.metallic_factor,
+                roughness: material.roughness_factor,
+                reflectance: material.reflectance,
+                alpha_cutoff,
+                base_color_tex: texture_index(&base_color, 0),
+                normal_tex: texture_index(&normal, 1),
+                metal_rough_tex: texture_index(&metal_rough, 2),
+                ao_tex: texture_index(&ao, 3),
+            }
+        });
+        let (material_buffer, _) = ImmutableBuffer::from_iter(
+            material_data.collect::<Vec<_>>().into_iter(),
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            queue,
+        )
+        .unwrap();
+
+        let material_indices = primitives
+            .iter()
+            .map(|&index| match &primitive_info[index].material_name {
+                Some(name) => material_names.iter().position(|material| *material == name).unwrap() as u32 + 1,
+                None => 0,
+            })
+            .collect();
+
+        // Unused elements of the array still need a valid image.
+        let textures = texture_names
+            .iter()
+            .chain(std::iter::repeat(&texture_names[0]))
+            .take(MAX_TEXTURES)
+            .map(|name| {
+                let data = &world_render.image_samplers[name.as_str()];
+                (data.view.clone(), data.sampler.clone())
+            })
+            .collect::<Vec<_>>();
+
+        let instance_count = primitives.iter().map(|&index| primitive_info[index].instance_count()).sum();
+
+        let cull_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shaders.gpu_driven_cull_comp.main_entry_point(), &(), None).unwrap(),
+        );
+
+        let material_buffer: Arc<dyn BufferAccess + Send + Sync> = material_buffer;
+        let frames = FrameRing::new(|_| {
+            FrameBuffers::new(
+                device.clone(),
+                &pipeline,
+                &cull_pipeline,
+                primitives.len(),
+                instance_count,
+                material_buffer.clone(),
+                textures.clone(),
+            )
+        });
+
+        Some(Self {
+            primitives,
+            material_indices,
+            instance_count,
+            vertex_buffer,
+            index_buffer,
+            frames,
+            cull_pipeline,
+            object_pool: CpuBufferPool::new(device.clone(), BufferUsage::transfer_source()),
+            instance_pool: CpuBufferPool::new(device.clone(), BufferUsage::transfer_source()),
+            draw_pool: CpuBufferPool::new(device, BufferUsage::transfer_source()),
+        })
+    }
+
+    /// Whether the primitive at `index` is drawn by the GPU-driven path.
+    pub fn contains(&self, index: usize) -> bool {
+        self.primitives.binary_search(&index).is_ok()
+    }
+
+    /// Uploads this frame's uniforms, transforms and bounds into the buffers of the frame slot
+    /// `frame`, and records the culling pass writing the draw commands. The world bounds and
+    /// previous transforms of the primitives must be up to date.
+    pub fn update(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        frame: usize,
+        primitive_info: &[PrimitiveInfo],
+        vert_data: vert::ty::Data,
+        frag_data: frag::ty::Data,
+        frustum: &Frustum,
+    ) {
+        let primitives = self.primitives.iter().map(|&index| &primitive_info[index]).collect::<Vec<_>>();
+
+        let mut first_instance = 0;
+        let mut objects = Vec::with_capacity(primitives.len());
+        for draw_info in &primitives {
+            let aabb = draw_info.world_aabb;
+            let indices = draw_info.index_buffer.as_ref().unwrap();
+
+            objects.push(gpu_driven_cull_comp::ty::Object {
+                aabb_min: aabb.min.to_homogeneous().into(),
+                aabb_max: aabb.max.to_homogeneous().into(),
+                index_count: indices.len() as u32,
+                first_index: (indices.offset() / mem::size_of::<u32>()) as u32,
+                vertex_offset: (draw_info.vertex_buffer.offset() / mem::size_of::<VPosNormTex>()) as i32,
+                first_instance,
+                instance_count: draw_info.instance_count() as u32,
+                _dummy0: [0u8; 12],
+            });
+            first_instance += draw_info.instance_count() as u32;
+        }
+
+        let instances = primitives.iter().enumerate().flat_map(|(draw_index, draw_info)| {
+            draw_info
+                .composed_instance_transforms()
+                .into_iter()
+                .zip(draw_info.previous_instance_transforms.clone())
+                .map(move |(transform, previous_transform)| gpu_driven_vert::ty::Instance {
+                    model: super::affine_rows(transform),
+                    prev_model: super::affine_rows(previous_transform),
+                    draw_index: draw_index as u32,
+                    _dummy0: [0u8; 12],
+                })
+        });
+
+        let draws = primitives
+            .iter()
+            .zip(&self.material_indices)
+            .map(|(draw_info, &material_index)| gpu_driven_vert::ty::Draw {
+                model: super::affine_rows(draw_info.composed_transform()),
+                prev_model: super::affine_rows(draw_info.previous_transform),
+                material_index,
+                _dummy0: [0u8; 12],
+            });
+
+        let push_constants = gpu_driven_cull_comp::ty::CullPushConstants {
+            planes: frustum.planes(),
+            object_count: primitives.len() as u32,
+        };
+
+        let group_count = (primitives.len() as u32).div_ceil(WORKGROUP_SIZE);
+        let buffers = self.frames.get(frame);
+
+        builder
+            .update_buffer(buffers.vertex_uniform_buffer.clone(), Arc::new(vert_data))
+            .unwrap()
+            .update_buffer(buffers.fragment_uniform_buffer.clone(), Arc::new(frag_data))
+            .unwrap()
+            .copy_buffer(self.object_pool.chunk(objects).unwrap(), buffers.objects.clone())
+            .unwrap()
+            .copy_buffer(self.draw_pool.chunk(draws).unwrap(), buffers.draws.clone())
+            .unwrap();
+
+        // Instances can be added to meshes after the scene is gathered, so only as many as there
+        // were are drawn.
+        let instances = instances.take(self.instance_count).collect::<Vec<_>>();
+        if instances.len() == self.instance_count {
+            builder
+                .copy_buffer(self.instance_pool.chunk(instances).unwrap(), buffers.instances.clone())
+                .unwrap();
+        }
+
+        builder
+            .dispatch(
+                [group_count, 1, 1],
+                self.cull_pipeline.clone(),
+                buffers.cull_set.clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap();
+    }
+
+    /// Records the draw of every primitive with the commands written for the frame slot `frame`,
+    /// within the first subpass of the scene render pass.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        frame: usize,
+        pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    ) {
+        let buffers = self.frames.get(frame);
+        let vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> = vec![self.vertex_buffer.clone()];
+
+        builder
+            .draw_indexed_indirect(
+                pipeline,
+                &DynamicState::none(),
+                vertex_buffers,
+                self.index_buffer.clone(),
+                buffers.commands.clone(),
+                buffers.set.clone(),
+                (),
+                vec![],
+            )
+            .unwrap();
+    }
+}
+
+/// The names of a material's textures, in the order of its texture indices.
+fn material_textures(material: &Material) -> [Option<String>; 5] {
+    [
+        material.textures.base_color.clone(),
+        material.textures.normal.clone(),
+        material.textures.metallic_roughness.clone(),
+        material.textures.ao.clone(),
+        material.textures.emissive.clone(),
+    ]
+}
@@ -0,0 +1,162 @@
+use crate::render::base::SceneImages;
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::ImageView;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+/// The format of the depth pyramid, holding the closest and furthest linear depth of each texel.
+const HIZ_FORMAT: Format = Format::R32G32Sfloat;
+
+/// A hierarchical depth buffer: a pyramid of the scene depth, each level half the size of the one
+/// before, holding the closest and furthest depth covered by each texel.
+///
+/// Each level is a separate image, as render targets can't have mipmaps.
+pub struct HiZ {
+    pub levels: Vec<Arc<ImageView<Arc<AttachmentImage>>>>,
+
+    pipelines: Vec<Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    sets: Vec<Arc<dyn DescriptorSet + Send + Sync>>,
+}
+
+impl HiZ {
+    /// The number of levels in the pyramid, matching the levels bound in the shaders that read it.
+    pub const LEVELS: usize = 8;
+
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        scene_images: &SceneImages,
+    ) -> Option<Self> {
+        let render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    depth: {
+                        load: DontCare,
+                        store: Store,
+                        format: HIZ_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [depth],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let mut levels = Vec::with_capacity(Self::LEVELS);
+        let mut pipelines = Vec::with_capacity(Self::LEVELS);
+        let mut framebuffers = Vec::with_capacity(Self::LEVELS);
+        let mut sets = Vec::with_capacity(Self::LEVELS);
+
+        for level in 0..Self::LEVELS {
+            // Round up, so every texel of the previous level is covered.
+            let scale = 1 << level;
+            let level_dimensions = [
+                dimensions[0].div_ceil(scale).max(1),
+                dimensions[1].div_ceil(scale).max(1),
+            ];
+
+            let view = ImageView::new(AttachmentImage::sampled(device.clone(), level_dimensions, HIZ_FORMAT).ok()?).unwrap();
+            let pipeline = Pipeline::HiZ.create(device.clone(), level_dimensions, shaders, render_pass.clone());
+
+            let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
+            let set = match levels.last() {
+                Some(previous) => Arc::new(
+                    PersistentDescriptorSet::start(layout.clone())
+                        .add_sampled_image(Arc::clone(previous), sampler.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                ) as Arc<dyn DescriptorSet + Send + Sync>,
+                None => Arc::new(
+                    PersistentDescriptorSet::start(layout.clone())
+                        .add_sampled_image(scene_images.normal_depth.clone(), sampler.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                ) as Arc<dyn DescriptorSet + Send + Sync>,
+            };
+
+            framebuffers.push(Arc::new(
+                Framebuffer::start(render_pass.clone())
+                    .add(view.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>);
+            levels.push(view);
+            pipelines.push(pipeline);
+            sets.push(set);
+        }
+
+        Some(Self {
+            levels,
+            pipelines,
+            framebuffers,
+            sets,
+        })
+    }
+
+    /// Records the passes building the pyramid from the scene depth, one level at a time.
+    pub fn build(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+    ) {
+        for level in 0..Self::LEVELS {
+            let push_constants = hiz_frag::ty::HiZPushConstants {
+                first_level: (level == 0) as u32,
+            };
+
+            builder
+                .begin_render_pass(
+                    self.framebuffers[level].clone(),
+                    SubpassContents::Inline,
+                    vec![ClearValue::None],
+                )
+                .unwrap()
+                .draw(
+                    self.pipelines[level].clone(),
+                    &DynamicState::none(),
+                    vec![fullscreen_vertex_buffer.clone()],
+                    self.sets[level].clone(),
+                    push_constants,
+                    vec![],
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap();
+        }
+    }
+}
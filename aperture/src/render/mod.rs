@@ -1,62 +1,352 @@
+mod anti_aliasing;
 mod base;
 mod camera;
+mod capture;
+mod deferred;
+mod dof;
 mod environment;
+mod frames;
+mod frustum;
+mod gpu_driven;
+mod hiz;
+mod motion_blur;
+mod occlusion;
+mod oit;
+mod render_path;
+mod ssao;
+mod ssr;
+mod stats;
+mod taa;
 mod world_render;
 
 pub mod shaders;
 
 use crate::render::environment::Environment;
-use crate::render::world_render::WorldRender; 
-use crate::state::InputState;
+use crate::render::world_render::{PrimitiveInfo, WorldRender};
+use crate::vulkan::{DescriptorSet, PushConstantLayout};
+use crate::state::{Action, Bindings, InputState};
 use crate::world::World;
 use crate::world::light::Light;
 
-use base::VulkanBase;
-use camera::Camera;
+use aperture_common::InstanceTransform;
+use aperture_mesh::{AlphaMode, Material};
+
+pub use anti_aliasing::AntiAliasing;
+pub use base::{gpu_names, GpuSelection};
+pub use camera::{Camera, CameraMode};
+pub use capture::{save_radiance, RadianceImage, SaveError, Screenshot};
+pub use dof::DepthOfField;
+pub use motion_blur::MotionBlur;
+pub use oit::Transparency;
+pub use render_path::RenderPath;
+pub use ssao::AmbientOcclusion;
+pub use ssr::ScreenSpaceReflections;
+pub use stats::RenderStats;
+
+use base::{RenderTarget, VulkanBase};
+use frames::FramesInFlight;
+use frustum::Frustum;
+use gpu_driven::GpuDrivenScene;
+use occlusion::DrawCommands;
 use shaders::*;
+use taa::TemporalAntiAliasing;
 
-use cgmath::{Deg, Matrix4, Point3, Vector3, perspective};
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, DynamicState, SubpassContents};
+use cgmath::{Deg, InnerSpace, Matrix, Matrix4, Vector3, Zero, perspective};
+use image::RgbaImage;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::format::{ClearValue, Pixel};
+use vulkano::half::f16;
+use vulkano::pipeline::GraphicsPipelineAbstract;
 use vulkano::sync::{self, GpuFuture};
+use vulkano::image::AttachmentImage;
 use winit::event_loop::EventLoop;
 
-use std::convert::TryInto;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How much each frame contributes to the average frame time.
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+
+/// How quickly the fly mode's velocity approaches the speed of the held moves, per second.
+const FLY_ACCELERATION: f32 = 10.0;
+/// The multiples of the fly speed moved at with the faster and slower modifiers.
+const FLY_FASTER: f32 = 4.0;
+const FLY_SLOWER: f32 = 0.25;
+/// The fly mode's turn in radians per unit of mouse motion.
+const LOOK_SENSITIVITY: f32 = 0.003;
 
 pub struct Renderer {
     pub base: VulkanBase,
-    pub previous_frame_end: Option<Box<dyn GpuFuture>>,
+    frames: FramesInFlight,
 
     pub world_render: WorldRender,
     pub camera: Camera,
+    pub ambient_occlusion: AmbientOcclusion,
+    pub reflections: ScreenSpaceReflections,
+    pub depth_of_field: DepthOfField,
+    pub motion_blur: MotionBlur,
+    pub transparency: Transparency,
+    /// Whether primitives hidden behind others are culled on the GPU. Not supported with MSAA.
+    pub occlusion_culling: bool,
+    /// Whether the opaque primitives are drawn with the GPU-driven path, where the device
+    /// supports it. Only with forward shading, and without occlusion culling.
+    pub gpu_driven: bool,
+    /// Whether only the edges of the primitives are drawn. Transparent primitives are drawn as
+    /// the opaque are, and neither occlusion culling nor the GPU-driven path are used.
+    pub wireframe: bool,
+    pub camera_mode: CameraMode,
+    /// How fast the camera moves in the fly mode, in metres per second.
+    pub fly_speed: f32,
+
+    stats: RenderStats,
+    // The per-instance transforms of the primitives, uploaded each frame.
+    instance_pool: CpuBufferPool<InstanceTransform>,
+    // The primitives of the loaded world on the GPU-driven path, if it's supported.
+    gpu_driven_scene: Option<GpuDrivenScene>,
+    // The velocity of the camera in the fly mode, in metres per second.
+    fly_velocity: Vector3<f32>,
+    // When the last frame started, to time the next.
+    last_frame_start: Option<Instant>,
+    // The pixel to focus the camera on, read back from the depth of the next frame.
+    focus_request: Option<[u32; 2]>,
+    focus_readback: Option<Arc<CpuAccessibleBuffer<[f16]>>>,
+
+    // The unjittered view and projection matrices of the last frame, used to write motion vectors.
+    previous_view_proj: Option<(Matrix4<f32>, Matrix4<f32>)>,
 }
 
 impl Renderer {
-    pub fn new(title: String, width: u32, height: u32) -> (Self, EventLoop<()>) {
-        let (base, event_loop) = VulkanBase::new(title, width, height);
-        let previous_frame_end = Some(sync::now(base.device.clone()).boxed());
-
-        (
-            Self {
-                base,
-                previous_frame_end,
-                world_render: WorldRender::default(),
-                camera: Camera::new(
-                    Point3::new(2.0, 0.5, 2.0),
-                    Point3::new(0.0, 0.0, 0.0),
-                    Vector3::new(0.0, -1.0, 0.0),
-                ),
-            },
-            event_loop,
-        )
+    /// Creates a renderer presenting to a new window. Returns `None` if there's no Vulkan device,
+    /// or not the selected one, to present with.
+    pub fn new(
+        title: String,
+        width: u32,
+        height: u32,
+        anti_aliasing: AntiAliasing,
+        gpu: Option<&GpuSelection>,
+    ) -> Option<(Self, EventLoop<()>)> {
+        let (base, event_loop) = VulkanBase::new(title, width, height, anti_aliasing, gpu)?;
+
+        Some((Self::with_base(base), event_loop))
+    }
+
+    /// Creates a renderer drawing into an offscreen image of the given size rather than a window,
+    /// for rendering without a display. Frames are read back with `read_image` and `save_frame`.
+    /// Returns `None` if there's no Vulkan device, or not the selected one, to render with.
+    pub fn headless(width: u32, height: u32, anti_aliasing: AntiAliasing, gpu: Option<&GpuSelection>) -> Option<Self> {
+        VulkanBase::headless(width, height, anti_aliasing, gpu).map(Self::with_base)
+    }
+
+    fn with_base(base: VulkanBase) -> Self {
+        let instance_pool = CpuBufferPool::vertex_buffer(base.device.clone());
+
+        Self {
+            base,
+            frames: FramesInFlight::new(),
+            world_render: WorldRender::default(),
+            camera: Camera::default(),
+            ambient_occlusion: AmbientOcclusion::default(),
+            reflections: ScreenSpaceReflections::default(),
+            depth_of_field: DepthOfField::default(),
+            motion_blur: MotionBlur::default(),
+            transparency: Transparency::default(),
+            occlusion_culling: true,
+            gpu_driven: false,
+            wireframe: false,
+            camera_mode: CameraMode::default(),
+            fly_speed: 2.0,
+            stats: RenderStats::default(),
+            instance_pool,
+            gpu_driven_scene: None,
+            fly_velocity: Vector3::zero(),
+            last_frame_start: None,
+            focus_request: None,
+            focus_readback: None,
+            previous_view_proj: None,
+        }
     }
 
     pub fn notify_resized(&mut self) {
         self.base.recreate_swapchain = true;
     }
 
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.base.anti_aliasing
+    }
+
+    /// Switches the anti-aliasing technique, taking effect from the next frame.
+    /// MSAA sample counts the device doesn't support are clamped to the nearest supported count.
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.base.set_anti_aliasing(anti_aliasing);
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        self.base.render_path
+    }
+
+    /// Switches between forward and deferred shading, taking effect from the next frame.
+    /// MSAA is disabled when switching to deferred shading.
+    pub fn set_render_path(&mut self, render_path: RenderPath) {
+        self.base.set_render_path(render_path);
+    }
+
+    /// Whether the GPU-driven path can be used with the loaded world and current render path.
+    pub fn gpu_driven_supported(&self) -> bool {
+        self.gpu_driven_scene.is_some() && self.base.gpu_driven_pipeline.is_some()
+    }
+
+    /// What was drawn in the last frame.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Focuses the camera on whatever is drawn at the given pixel, once the next frame is rendered.
+    pub fn focus_at(&mut self, position: [f32; 2]) {
+        let dimensions = self.base.dimensions();
+        if position[0] < 0.0 || position[1] < 0.0 {
+            return;
+        }
+
+        let (x, y) = (position[0] as u32, position[1] as u32);
+        if x < dimensions[0] && y < dimensions[1] {
+            self.focus_request = Some([x, y]);
+        }
+    }
+
+    /// Copies the last frame back as displayed, encoded to sRGB. Frames presented to a window
+    /// can't be read back, so this returns `None` unless rendering offscreen.
+    pub fn read_image(&self) -> Option<RgbaImage> {
+        let image = match &self.base.target {
+            RenderTarget::Offscreen { image } => image.clone(),
+            RenderTarget::Window { .. } => return None,
+        };
+
+        let [width, height] = image.dimensions();
+        let data = self.read_back(image, 0u8);
+        RgbaImage::from_raw(width, height, data)
+    }
+
+    /// Copies the linear radiance of the last frame back, before it was encoded for display.
+    pub fn read_radiance(&self) -> RadianceImage {
+        let image = self.base.presented_image();
+
+        let [width, height] = image.dimensions();
+        let data = self.read_back(image, f16::from_f32(0.0));
+        RadianceImage::from_raw(width, height, data.iter().map(|value| value.to_f32()).collect()).unwrap()
+    }
+
+    /// Saves the last frame, in the format given by the extension of the path. OpenEXR files
+    /// are written with the linear radiance, and other formats with the frame as displayed.
+    pub fn save_frame(&self, path: &Path) -> Result<(), SaveError> {
+        if capture::is_exr(path) {
+            capture::write_exr(path, &self.read_radiance())
+        } else {
+            let image = self.read_image().ok_or(SaveError::NotReadable)?;
+            image.save(path)?;
+            Ok(())
+        }
+    }
+
+    /// Renders the current view again, offscreen at a multiple of the window size, and saves it
+    /// to `path`. With TAA, enough frames are rendered for the new history to converge.
+    pub fn save_screenshot(&mut self, world: &World, path: &Path, screenshot: Screenshot) -> Result<(), SaveError> {
+        let [width, height] = self.base.dimensions();
+        let dimensions = [width * screenshot.scale, height * screenshot.scale];
+        let target =
+            RenderTarget::offscreen(self.base.device.clone(), dimensions).ok_or(SaveError::UnsupportedSize(dimensions))?;
+        let window_target = self.base.replace_target(target);
+
+        let frames = match self.base.anti_aliasing {
+            AntiAliasing::Taa => TemporalAntiAliasing::JITTER_SAMPLES,
+            _ => 1,
+        };
+        for _ in 0..frames {
+            self.render(world);
+        }
+
+        let result = self.save_frame(path).and_then(|()| {
+            if screenshot.radiance {
+                capture::write_exr(&path.with_extension("exr"), &self.read_radiance())
+            } else {
+                Ok(())
+            }
+        });
+
+        self.base.replace_target(window_target);
+        result
+    }
+
+    /// Copies a four channel image written by the last frame back to the CPU, blocking until the
+    /// GPU has finished the frame and the copy.
+    fn read_back<Px>(&self, image: Arc<AttachmentImage>, zero: Px) -> Vec<Px>
+    where
+        Px: Pixel + Copy + Send + Sync + 'static,
+    {
+        let [width, height] = image.dimensions();
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.base.device.clone(),
+            BufferUsage::transfer_destination(),
+            true,
+            std::iter::repeat_n(zero, (width * height * 4) as usize),
+        )
+        .unwrap();
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.base.device.clone(),
+            self.base.queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder.copy_image_to_buffer(image, buffer.clone()).unwrap();
+        let command_buffer = builder.build().unwrap();
+
+        self.frames
+            .last_frame_end(self.base.device.clone())
+            .then_execute(self.base.queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        let data = buffer.read().unwrap().to_vec();
+        data
+    }
+
+    /// Sets the camera focus distance from the depth copied back by a previous frame, if the copy
+    /// has finished.
+    fn read_focus_distance(&mut self) {
+        let depth = match &self.focus_readback {
+            Some(buffer) => match buffer.read() {
+                Ok(normal_depth) => normal_depth[3].to_f32(),
+                // Still in use by the GPU.
+                Err(_) => return,
+            },
+            None => return,
+        };
+
+        // The background has no depth, so leave the focus where it is.
+        if depth > 0.0 {
+            self.camera.focus_distance = depth;
+            println!("Focus distance: {:.2}m", depth);
+        }
+
+        self.focus_readback = None;
+    }
+
+    /// Uploads the world's meshes, materials, textures and environment map, replacing those loaded
+    /// before.
     pub fn load_world(&mut self, world: &World) {
-        self.world_render.update(
-            world.meshes.values(),
+        self.load_models(world);
+        self.load_environment(world);
+    }
+
+    /// Uploads the world's meshes, materials and textures, replacing those loaded before.
+    pub fn load_models(&mut self, world: &World) {
+        let upload = self.world_render.update(
+            world.meshes.iter(),
             world.materials.values(),
             world.textures.values(),
             self.base.pipeline_type,
@@ -64,8 +354,25 @@ impl Renderer {
             self.base.device.clone(),
             self.base.queue.clone(),
         );
+        self.frames.after_upload(upload);
 
+        // The scene is gathered with the forward path, as it's unsupported with the deferred.
+        self.gpu_driven_scene = self.base.gpu_driven_pipeline.clone().and_then(|pipeline| {
+            GpuDrivenScene::new(
+                self.base.device.clone(),
+                self.base.queue.clone(),
+                &self.base.shaders,
+                pipeline,
+                &self.world_render,
+                world,
+            )
+        });
+    }
+
+    /// Uploads the world's environment map, replacing the one loaded before.
+    pub fn load_environment(&mut self, world: &World) {
         self.world_render.update_environment(
+            world.environment.as_ref(),
             self.base.environment_pipeline.clone(),
             &self.base.shaders,
             self.base.device.clone(),
@@ -73,9 +380,25 @@ impl Renderer {
         );
     }
 
-    pub fn update(&mut self, input_state: &InputState) {
+    /// Switches the camera mode. The camera stops, rather than carrying on at the speed it was
+    /// flying.
+    pub fn set_camera_mode(&mut self, camera_mode: CameraMode) {
+        self.camera_mode = camera_mode;
+        self.fly_velocity = Vector3::zero();
+    }
+
+    /// Moves the camera by the held actions, where `delta_time` is the time since the last update
+    /// in seconds.
+    pub fn update(&mut self, input_state: &InputState, bindings: &Bindings, delta_time: f32) {
+        match self.camera_mode {
+            CameraMode::Orbit => self.update_orbit(input_state, bindings),
+            CameraMode::Fly => self.update_fly(input_state, bindings, delta_time),
+        }
+    }
+
+    fn update_orbit(&mut self, input_state: &InputState, bindings: &Bindings) {
         if let Some(delta) = input_state.position_delta {
-            if input_state.mouse_left_down {
+            if bindings.is_held(Action::Orbit, input_state) {
                 let dimensions = self.base.dimensions();
                 let theta_x = (2.0 * std::f32::consts::PI) / dimensions[0] as f32;
                 let theta_y = std::f32::consts::PI / dimensions[1] as f32;
@@ -83,7 +406,7 @@ impl Renderer {
                 let delta_y = delta[1] * theta_y;
 
                 self.camera.orbit(delta_x, delta_y);
-            } else if input_state.mouse_right_down {
+            } else if bindings.is_held(Action::Pan, input_state) {
                 self.camera.translate(delta[0], delta[1]);
             }
         }
@@ -93,8 +416,68 @@ impl Renderer {
         }
     }
 
+    fn update_fly(&mut self, input_state: &InputState, bindings: &Bindings, delta_time: f32) {
+        if let Some(delta) = input_state.position_delta {
+            if bindings.is_held(Action::Look, input_state) {
+                self.camera.look(delta[0] * LOOK_SENSITIVITY, delta[1] * LOOK_SENSITIVITY);
+            } else if bindings.is_held(Action::Pan, input_state) {
+                self.camera.translate(delta[0], delta[1]);
+            }
+        }
+
+        if let Some(delta) = input_state.wheel_delta {
+            self.fly_speed = (self.fly_speed * 1.25f32.powf(delta)).clamp(0.01, 1000.0);
+            println!("Fly speed: {:.2} m/s", self.fly_speed);
+        }
+
+        // The camera's up vector points down the screen, as Vulkan's clip space is flipped
+        // vertically, so its right vector points left across it.
+        let held = |action| if bindings.is_held(action, input_state) { 1.0 } else { 0.0 };
+        let direction = self.camera.view_dir() * (held(Action::MoveForward) - held(Action::MoveBack))
+            - self.camera.right_vector() * (held(Action::MoveRight) - held(Action::MoveLeft))
+            - self.camera.up.normalize() * (held(Action::MoveUp) - held(Action::MoveDown));
+
+        let speed = if bindings.is_held(Action::MoveFaster, input_state) {
+            self.fly_speed * FLY_FASTER
+        } else if bindings.is_held(Action::MoveSlower, input_state) {
+            self.fly_speed * FLY_SLOWER
+        } else {
+            self.fly_speed
+        };
+        let target_velocity = if direction.magnitude2() > 0.0 {
+            direction.normalize() * speed
+        } else {
+            Vector3::zero()
+        };
+
+        // Approach the target velocity exponentially, so the acceleration is the same at any
+        // frame rate.
+        let blend = 1.0 - (-FLY_ACCELERATION * delta_time).exp();
+        self.fly_velocity += (target_velocity - self.fly_velocity) * blend;
+        if self.fly_velocity.magnitude2() < 1e-6 {
+            self.fly_velocity = Vector3::zero();
+            return;
+        }
+
+        self.camera.fly(self.fly_velocity * delta_time);
+    }
+
     pub fn render(&mut self, world: &World) {
-        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+        self.frames.cleanup_finished();
+        self.read_focus_distance();
+
+        let frame_start = Instant::now();
+        let frame_time = match self.last_frame_start.replace(frame_start) {
+            Some(last_frame_start) => {
+                let elapsed = frame_start - last_frame_start;
+                if self.stats.frame_time.is_zero() {
+                    elapsed
+                } else {
+                    self.stats.frame_time.mul_f32(1.0 - FRAME_TIME_SMOOTHING) + elapsed.mul_f32(FRAME_TIME_SMOOTHING)
+                }
+            }
+            None => Duration::ZERO,
+        };
 
         // Don't render anything if the window is minimised.
         let dimensions = self.base.dimensions();
@@ -107,16 +490,28 @@ impl Renderer {
             self.base.resize_setup();
         }
 
+        // Wait until the GPU is done with the resources of this frame's slot, which were last
+        // used FRAMES_IN_FLIGHT frames ago.
+        self.frames.wait_for_slot();
+        let frame = self.frames.index();
+
         // Retrieve the index of the next available presentable image, and its future.
         // If there are none available, break out of this iteration of the render loop.
-        let (image_num, acquire_future) = match self.base.acquire_next_swapchain_image() {
+        let (image_num, acquire_future) = match self.base.acquire_next_image() {
             Some((image_num, acquire_future)) => (image_num, acquire_future),
             None => return,
         };
 
         let aspect_ratio = dimensions[0] as f32 / dimensions[1] as f32;
-        let proj = cgmath::perspective(Deg(60.0), aspect_ratio, 0.1, 100.0);
+        let unjittered_proj = Camera::projection(aspect_ratio);
         let view = self.camera.view_matrix();
+        let (prev_view, prev_proj) = self.previous_view_proj.unwrap_or((view, unjittered_proj));
+
+        // With TAA, offset the projection by a different sub-pixel amount each frame.
+        let proj = match &self.base.taa {
+            Some(taa) => taa.jittered_projection(unjittered_proj),
+            None => unjittered_proj,
+        };
 
         // Start building the command buffer.
         let mut builder = AutoCommandBufferBuilder::primary(
@@ -150,10 +545,13 @@ impl Renderer {
         if let Some(environment) = &self.world_render.environment {
             builder
                 .update_buffer(
-                    environment.skybox_uniform_buffer.clone(),
+                    environment.skybox_uniform_buffers.get(frame).clone(),
                     std::sync::Arc::new(cube_vert::ty::Data {
                         proj: proj.into(),
                         view: view.into(),
+                        unjittered_proj: unjittered_proj.into(),
+                        prev_proj: prev_proj.into(),
+                        prev_view: prev_view.into(),
                     }),
                 )
                 .unwrap();
@@ -179,11 +577,22 @@ impl Renderer {
                 .unwrap();
         }
 
+        let irradiance = match &self.world_render.environment {
+            Some(environment) => environment.irradiance,
+            None => [[0.0; 4]; 9],
+        };
+
         // Update uniform buffers.
         for draw_info in &self.world_render.primitive_info {
-            let set = self.world_render.material_info[draw_info.material_name.as_ref().unwrap()]
-                .descriptor_set
+            let material_name = draw_info.material_name.as_ref().unwrap();
+            let set = self.world_render.material_info[material_name]
+                .descriptor_set(frame)
                 .clone();
+            let material = &world.materials[material_name.as_str()];
+            let alpha_cutoff = match material.alpha_mode {
+                AlphaMode::Mask => material.alpha_cutoff,
+                AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+            };
 
             builder
                 .update_buffer(
@@ -191,6 +600,9 @@ impl Renderer {
                     std::sync::Arc::new(vert::ty::Data {
                         proj: proj.into(),
                         view: view.into(),
+                        unjittered_proj: unjittered_proj.into(),
+                        prev_proj: prev_proj.into(),
+                        prev_view: prev_view.into(),
                     }),
                 )
                 .unwrap()
@@ -203,12 +615,33 @@ impl Renderer {
                             self.camera.eye.z,
                             0.0,
                         ],
+                        emissive: material.emissive_factor.extend(0.0).into(),
+                        alpha_cutoff,
+                        _dummy0: [0u8; 12],
+                        irradiance,
                         lights: point_lights,
                     }),
                 )
                 .unwrap();
         }
 
+        if let Some(deferred_lighting) = &self.base.deferred_lighting {
+            deferred_lighting.update(
+                &mut builder,
+                frame,
+                deferred_lighting_frag::ty::Data {
+                    view_pos: [
+                        self.camera.eye.x,
+                        self.camera.eye.y,
+                        self.camera.eye.z,
+                        0.0,
+                    ],
+                    irradiance,
+                    lights: deferred_point_lights(&point_lights),
+                },
+            );
+        }
+
         // Project the HDRI environment map to a cube.
         if let Some(environment) = &self.world_render.environment {
             for i in 0..Environment::CUBE_IMAGE_LAYERS {
@@ -255,75 +688,162 @@ impl Renderer {
             }
         }
 
+        // Skip the primitives outside the view, testing the cheaper bounding sphere first.
+        for draw_info in &mut self.world_render.primitive_info {
+            draw_info.update_world_bounds();
+            draw_info.update_instance_buffer(&self.instance_pool);
+        }
+
+        let frustum = Frustum::from_matrix(unjittered_proj * view);
+        let frustum_visible = self
+            .world_render
+            .primitive_info
+            .iter()
+            .map(|draw_info| {
+                frustum.intersects_sphere(&draw_info.world_bounding_sphere)
+                    && frustum.intersects_aabb(&draw_info.world_aabb)
+            })
+            .collect::<Vec<_>>();
+        let visible = self
+            .world_render
+            .primitive_info
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| frustum_visible[*index])
+            .collect::<Vec<_>>();
+
+        // Upload the GPU-driven scene and cull it, writing its draw commands.
+        let gpu_driven = self
+            .gpu_driven_scene
+            .as_ref()
+            .filter(|_| self.gpu_driven && !self.wireframe)
+            .zip(self.base.gpu_driven_pipeline.clone());
+
+        if let Some((gpu_driven_scene, _)) = &gpu_driven {
+            gpu_driven_scene.update(
+                &mut builder,
+                frame,
+                &self.world_render.primitive_info,
+                vert::ty::Data {
+                    proj: proj.into(),
+                    view: view.into(),
+                    unjittered_proj: unjittered_proj.into(),
+                    prev_proj: prev_proj.into(),
+                    prev_view: prev_view.into(),
+                },
+                frag::ty::Data {
+                    view_pos: [
+                        self.camera.eye.x,
+                        self.camera.eye.y,
+                        self.camera.eye.z,
+                        0.0,
+                    ],
+                    emissive: [0.0; 4],
+                    alpha_cutoff: 0.0,
+                    _dummy0: [0u8; 12],
+                    irradiance,
+                    lights: point_lights,
+                },
+                &frustum,
+            );
+        }
+
+        // Draw the opaque primitives visible last frame as occluders, and test every primitive
+        // against them, to skip those hidden behind others.
+        let occlusion_culling_enabled = self.occlusion_culling && gpu_driven.is_none() && !self.wireframe;
+        let mut occluded_primitives = 0;
+        if occlusion_culling_enabled {
+            if let Some(occlusion_culling) = &mut self.base.occlusion_culling {
+                occluded_primitives = occlusion_culling.read_occluded_count().unwrap_or(self.stats.occluded_primitives);
+                occlusion_culling.prepare(&mut builder, self.base.device.clone(), self.world_render.primitive_info.len());
+            }
+        }
+
+        let occlusion_culling = self.base.occlusion_culling.as_ref().filter(|_| occlusion_culling_enabled);
+        let draw_commands = occlusion_culling.and_then(|occlusion_culling| occlusion_culling.commands());
+
+        if let (Some(occlusion_culling), Some(draw_commands)) = (occlusion_culling, &draw_commands) {
+            occlusion_culling.begin_prepass(&mut builder);
+
+            for &(index, draw_info) in &visible {
+                let material = draw_info.material(world);
+                if material.alpha_mode != AlphaMode::Opaque {
+                    continue;
+                }
+
+                draw_primitive(
+                    &mut builder,
+                    occlusion_culling.prepass_pipeline(),
+                    &self.base.shaders.material_push_constants,
+                    draw_info,
+                    material,
+                    self.world_render.material_info[draw_info.material_name.as_ref().unwrap()].descriptor_set(frame),
+                    world.lights.len() as u32,
+                    Some((draw_commands, index)),
+                );
+            }
+
+            builder.end_render_pass().unwrap();
+
+            self.base.hiz.build(&mut builder, self.base.fullscreen_vertex_buffer.clone());
+            occlusion_culling.cull(
+                &mut builder,
+                &self.world_render.primitive_info,
+                &frustum_visible,
+                view,
+                unjittered_proj,
+            );
+        }
+
+        self.stats = RenderStats {
+            drawn_primitives: (visible.len() as u32).saturating_sub(occluded_primitives),
+            culled_primitives: (self.world_render.primitive_info.len() - visible.len()) as u32,
+            occluded_primitives,
+            frame_time,
+        };
+
         builder
             .begin_render_pass(
-                self.base.framebuffers[image_num].clone(),
+                self.base.scene_framebuffer.clone(),
                 SubpassContents::Inline,
-                vec![[0.1, 0.1, 0.1, 1.0].into(), 1f32.into()],
+                self.base.scene_clear_values(),
             )
             .unwrap();
 
-        for draw_info in &self.world_render.primitive_info {
-            let vert_push_constants = vert::ty::VertPushConstants {
-                model: draw_info.composed_transform().into(),
-            };
-
-            let material = if let Some(name) = &draw_info.material_name {
-                &world.materials[name.as_str()]
-            } else {
-                &world.default_material
-            };
+        // Draw the opaque and masked primitives, collecting the blended primitives to draw after
+        // the rest of the scene.
+        let mut transparent = Vec::new();
+        for &(index, draw_info) in &visible {
+            let material = draw_info.material(world);
 
-            let frag_push_constants = frag::ty::FragPushConstants {
-                _dummy0: [0u8; 64],
-                base_color: material.base_color_factor.into(),
-                metalness: material.metallic_factor,
-                roughness: material.roughness_factor,
-                reflectance: material.reflectance,
-                point_light_count: world.lights.len() as u32,
-            };
+            if material.alpha_mode == AlphaMode::Blend && !self.wireframe {
+                transparent.push((index, draw_info));
+                continue;
+            }
 
-            // FIXME
-            let vert_data = unsafe {
-                std::mem::transmute::<vert::ty::VertPushConstants, [u8; 64]>(vert_push_constants)
-            };
+            if gpu_driven.as_ref().is_some_and(|(gpu_driven_scene, _)| gpu_driven_scene.contains(index)) {
+                continue;
+            }
 
-            let frag_data = unsafe {
-                std::mem::transmute::<frag::ty::FragPushConstants, [u8; 96]>(frag_push_constants)
+            let pipeline = if self.wireframe {
+                self.base.wireframe_pipeline.clone()
+            } else {
+                self.base.pipeline.clone()
             };
+            draw_primitive(
+                &mut builder,
+                pipeline,
+                &self.base.shaders.material_push_constants,
+                draw_info,
+                material,
+                self.world_render.material_info[draw_info.material_name.as_ref().unwrap()].descriptor_set(frame),
+                world.lights.len() as u32,
+                draw_commands.as_ref().map(|draw_commands| (draw_commands, index)),
+            );
+        }
 
-            let mut data_vec = vert_data.to_vec();
-            data_vec.extend(frag_data.iter().skip(64));
-            let push_constants: [u8; 96] = data_vec.try_into().unwrap();
-
-            let set = self.world_render.material_info[draw_info.material_name.as_ref().unwrap()]
-                .descriptor_set
-                .clone();
-
-            if draw_info.has_indices() {
-                builder
-                    .draw_indexed(
-                        self.base.pipeline.clone(),
-                        &DynamicState::none(),
-                        vec![draw_info.vertex_buffer.clone()],
-                        draw_info.index_buffer.as_ref().unwrap().clone(),
-                        set.set.clone(),
-                        push_constants,
-                        vec![],
-                    )
-                    .unwrap();
-            } else {
-                builder
-                    .draw(
-                        self.base.pipeline.clone(),
-                        &DynamicState::none(),
-                        vec![draw_info.vertex_buffer.clone()],
-                        set.set.clone(),
-                        push_constants,
-                        vec![],
-                    )
-                    .unwrap();
-            }
+        if let Some((gpu_driven_scene, pipeline)) = &gpu_driven {
+            gpu_driven_scene.draw(&mut builder, frame, pipeline.clone());
         }
 
         // Draw the environment cube.
@@ -333,43 +853,320 @@ impl Renderer {
                     self.base.environment_pipeline.clone(),
                     &DynamicState::none(),
                     vec![environment.skybox_vertex_buffer.clone()],
-                    environment.skybox_set.clone(),
+                    environment.skybox_sets.get(frame).clone(),
                     (),
                     vec![],
                 )
                 .unwrap();
         }
 
+        // Light the G-buffer.
+        if let Some(deferred_lighting) = &self.base.deferred_lighting {
+            builder.next_subpass(SubpassContents::Inline).unwrap();
+
+            deferred_lighting.draw(
+                &mut builder,
+                self.base.fullscreen_vertex_buffer.clone(),
+                view,
+                unjittered_proj,
+                world.lights.len() as u32,
+                frame,
+            );
+
+            builder.next_subpass(SubpassContents::Inline).unwrap();
+        }
+
+        // Blend the transparent primitives over the scene, from back to front. Each is sorted by
+        // the view space depth of its centre.
+        if self.transparency == Transparency::Sorted {
+            let view_depth = |draw_info: &PrimitiveInfo| {
+                (view * draw_info.world_bounding_sphere.center.to_homogeneous()).z
+            };
+            transparent.sort_by(|(_, a), (_, b)| {
+                view_depth(a)
+                    .partial_cmp(&view_depth(b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+            for &(index, draw_info) in &transparent {
+                draw_primitive(
+                    &mut builder,
+                    self.base.transparent_pipeline.clone(),
+                    &self.base.shaders.material_push_constants,
+                    draw_info,
+                    draw_info.material(world),
+                    self.world_render.material_info[draw_info.material_name.as_ref().unwrap()].descriptor_set(frame),
+                    world.lights.len() as u32,
+                    draw_commands.as_ref().map(|draw_commands| (draw_commands, index)),
+                );
+            }
+        }
+
         builder.end_render_pass().unwrap();
 
+        // Accumulate the transparent primitives in any order, and blend their weighted average
+        // over the scene.
+        if self.transparency == Transparency::WeightedBlended && !transparent.is_empty() {
+            let weighted_blended = &self.base.weighted_blended;
+            weighted_blended.begin(&mut builder);
+
+            for &(index, draw_info) in &transparent {
+                draw_primitive(
+                    &mut builder,
+                    weighted_blended.pipeline.clone(),
+                    &self.base.shaders.material_push_constants,
+                    draw_info,
+                    draw_info.material(world),
+                    self.world_render.material_info[draw_info.material_name.as_ref().unwrap()].descriptor_set(frame),
+                    world.lights.len() as u32,
+                    draw_commands.as_ref().map(|draw_commands| (draw_commands, index)),
+                );
+            }
+
+            weighted_blended.composite(&mut builder, self.base.fullscreen_vertex_buffer.clone());
+        }
+
+        for draw_info in &mut self.world_render.primitive_info {
+            draw_info.save_previous_transforms();
+        }
+        self.previous_view_proj = Some((view, unjittered_proj));
+
+        // Add the ambient lighting to the scene, attenuated by the ambient occlusion.
+        self.base.ambient_occlusion.draw(
+            &mut builder,
+            self.base.fullscreen_vertex_buffer.clone(),
+            &self.ambient_occlusion,
+            unjittered_proj,
+        );
+
+        // Add the specular reflections, traced through the depth pyramid where enabled.
+        if let Some(environment) = &self.world_render.environment {
+            if self.reflections.enabled {
+                self.base.hiz.build(&mut builder, self.base.fullscreen_vertex_buffer.clone());
+            }
+
+            self.base.reflections.draw(
+                &mut builder,
+                self.base.fullscreen_vertex_buffer.clone(),
+                &self.reflections,
+                environment,
+                view,
+                unjittered_proj,
+            );
+        }
+
+        // Copy the depth under the cursor back, to focus on it.
+        if let Some([x, y]) = self.focus_request.take() {
+            let buffer = CpuAccessibleBuffer::from_iter(
+                self.base.device.clone(),
+                BufferUsage::transfer_destination(),
+                true,
+                [f16::from_f32(0.0); 4].iter().cloned(),
+            )
+            .unwrap();
+
+            builder
+                .copy_image_to_buffer_dimensions(
+                    self.base.scene_images.normal_depth.image().clone(),
+                    buffer.clone(),
+                    [x, y, 0],
+                    [1, 1, 1],
+                    0,
+                    1,
+                    0,
+                )
+                .unwrap();
+
+            self.focus_readback = Some(buffer);
+        }
+
+        if self.depth_of_field.enabled {
+            self.base.depth_of_field.draw(
+                &mut builder,
+                self.base.fullscreen_vertex_buffer.clone(),
+                &self.depth_of_field,
+                &self.camera,
+            );
+        }
+
+        if self.motion_blur.enabled {
+            self.base.motion_blur.draw(
+                &mut builder,
+                self.base.fullscreen_vertex_buffer.clone(),
+                &self.motion_blur,
+            );
+        }
+
+        // Blend the scene into the TAA history, which is then presented in place of the scene.
+        let present_set = match &mut self.base.taa {
+            Some(taa) => {
+                taa.resolve(&mut builder, self.base.fullscreen_vertex_buffer.clone());
+                taa.present_set()
+            }
+            None => self.base.present_set.clone(),
+        };
+
+        // Draw the resolved scene to the swapchain image, applying FXAA if enabled.
+        builder
+            .begin_render_pass(
+                self.base.framebuffers[image_num].clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.base.present_pipeline.clone(),
+                &DynamicState::none(),
+                vec![self.base.fullscreen_vertex_buffer.clone()],
+                present_set,
+                (),
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+
         let command_buffer = builder.build().unwrap();
 
         let future = self
-            .previous_frame_end
-            .take()
-            .unwrap()
+            .frames
+            .previous_frame_end(self.base.device.clone())
             .join(acquire_future)
             .then_execute(self.base.queue.clone(), command_buffer)
-            .unwrap()
-            .then_swapchain_present(
-                self.base.queue.clone(),
-                self.base.swapchain.clone(),
-                image_num,
-            )
-            .then_signal_fence_and_flush();
+            .unwrap();
+        let future = self.base.present(future, image_num).then_signal_fence_and_flush();
 
         match future {
             Ok(future) => {
-                self.previous_frame_end = Some(future.boxed());
+                self.frames.submitted(Some(Arc::new(future)));
             }
             Err(sync::FlushError::OutOfDate) => {
                 self.base.recreate_swapchain = true;
-                self.previous_frame_end = Some(sync::now(self.base.device.clone()).boxed());
+                self.frames.submitted(None);
             }
             Err(e) => {
                 println!("failed to flush future: {:?}", e);
-                self.previous_frame_end = Some(sync::now(self.base.device.clone()).boxed());
+                self.frames.submitted(None);
             }
         }
     }
 }
+
+/// Records the draw of a primitive with its material, pushed in the layout of the material push
+/// constants. With occlusion culling, the primitive is drawn with its indirect command, given with
+/// the primitive's index.
+#[allow(clippy::too_many_arguments)]
+fn draw_primitive(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    push_constant_layout: &PushConstantLayout,
+    draw_info: &PrimitiveInfo,
+    material: &Material,
+    set: &DescriptorSet,
+    point_light_count: u32,
+    indirect: Option<(&DrawCommands, usize)>,
+) {
+    // The instances are drawn with the per-instance transforms uploaded this frame.
+    let vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> = vec![
+        draw_info.vertex_buffer.clone(),
+        draw_info.instance_buffer.clone().expect("instance buffer not uploaded"),
+    ];
+
+    let mut push_constants = MaterialPushConstants::new(push_constant_layout);
+    push_constants
+        .write(
+            push_constant_layout,
+            &vert::ty::VertPushConstants {
+                model: affine_rows(draw_info.composed_transform()),
+                prev_model: affine_rows(draw_info.previous_transform),
+            },
+        )
+        .write(
+            push_constant_layout,
+            &frag::ty::FragPushConstants {
+                base_color: material.base_color_factor.into(),
+                metalness: material.metallic_factor,
+                roughness: material.roughness_factor,
+                reflectance: material.reflectance,
+                point_light_count,
+                ..Default::default()
+            },
+        );
+
+    if let Some((draw_commands, index)) = indirect {
+        if draw_info.has_indices() {
+            builder
+                .draw_indexed_indirect(
+                    pipeline,
+                    &DynamicState::none(),
+                    vertex_buffers,
+                    draw_info.index_buffer.as_ref().unwrap().clone(),
+                    draw_commands.indexed(index),
+                    set.set.clone(),
+                    push_constants,
+                    vec![],
+                )
+                .unwrap();
+        } else {
+            builder
+                .draw_indirect(
+                    pipeline,
+                    &DynamicState::none(),
+                    vertex_buffers,
+                    draw_commands.non_indexed(index),
+                    set.set.clone(),
+                    push_constants,
+                    vec![],
+                )
+                .unwrap();
+        }
+    } else if draw_info.has_indices() {
+        builder
+            .draw_indexed(
+                pipeline,
+                &DynamicState::none(),
+                vertex_buffers,
+                draw_info.index_buffer.as_ref().unwrap().clone(),
+                set.set.clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap();
+    } else {
+        builder
+            .draw(
+                pipeline,
+                &DynamicState::none(),
+                vertex_buffers,
+                set.set.clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap();
+    }
+}
+
+/// The point lights in the layout of the deferred lighting shader's uniforms.
+fn deferred_point_lights(lights: &[frag::ty::PointLight; 255]) -> [deferred_lighting_frag::ty::PointLight; 255] {
+    let mut deferred_lights = [deferred_lighting_frag::ty::PointLight {
+        position: [0.0; 4],
+        color: [0.0; 4],
+        power: [0; 4],
+    }; 255];
+
+    for (deferred_light, light) in deferred_lights.iter_mut().zip(lights.iter()) {
+        *deferred_light = deferred_lighting_frag::ty::PointLight {
+            position: light.position,
+            color: light.color,
+            power: light.power,
+        };
+    }
+
+    deferred_lights
+}
+
+/// The first three rows of an affine transform, the layout model matrices are pushed to the
+/// shaders in.
+fn affine_rows(transform: Matrix4<f32>) -> [[f32; 4]; 3] {
+    [transform.row(0).into(), transform.row(1).into(), transform.row(2).into()]
+}
@@ -0,0 +1,292 @@
+use crate::render::base::{SceneImages, SCENE_FORMAT, VELOCITY_FORMAT};
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::ClearValue;
+use vulkano::image::view::ImageView;
+use vulkano::image::{AttachmentImage, ImageUsage};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+/// The width and height in pixels of the tiles the longest motion is gathered in.
+/// This is also the longest distance a pixel can be blurred.
+const TILE_SIZE: u32 = 20;
+
+/// Settings for the motion blur effect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotionBlur {
+    pub enabled: bool,
+    /// The fraction of the frame time the simulated shutter is open for, in degrees.
+    /// 360 degrees blurs over the whole motion between frames, 180 degrees over half of it.
+    pub shutter_angle: f32,
+}
+
+impl Default for MotionBlur {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shutter_angle: 180.0,
+        }
+    }
+}
+
+/// Blurs the scene colour along the motion of each pixel.
+///
+/// The longest motion is found for each tile of the screen, and then for each tile's
+/// neighbourhood. Pixels are blurred along the longest motion around them, so that fast moving
+/// objects blur over their silhouettes rather than being cut off at them.
+pub struct MotionBlurPass {
+    tile_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    tile_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    tile_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    neighbour_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    neighbour_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    neighbour_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    gather_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    gather_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    gather_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    // The blurred scene, copied back into the scene colour image.
+    blurred_image: Arc<AttachmentImage>,
+    scene_image: Arc<AttachmentImage>,
+
+    dimensions: [u32; 2],
+    frame: u32,
+}
+
+impl MotionBlurPass {
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        post_render_pass: Arc<RenderPass>,
+        scene_images: &SceneImages,
+    ) -> Option<Self> {
+        let tile_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    tiles: {
+                        load: DontCare,
+                        store: Store,
+                        format: VELOCITY_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [tiles],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let tile_dimensions = [
+            dimensions[0].div_ceil(TILE_SIZE),
+            dimensions[1].div_ceil(TILE_SIZE),
+        ];
+
+        let tile_view = ImageView::new(AttachmentImage::sampled(device.clone(), tile_dimensions, VELOCITY_FORMAT).ok()?).unwrap();
+        let neighbour_view = ImageView::new(AttachmentImage::sampled(device.clone(), tile_dimensions, VELOCITY_FORMAT).ok()?).unwrap();
+
+        let blurred_image = AttachmentImage::with_usage(
+            device.clone(),
+            dimensions,
+            SCENE_FORMAT,
+            ImageUsage {
+                transfer_source: true,
+                ..ImageUsage::none()
+            },
+        )
+        .ok()?;
+        let blurred_view = ImageView::new(blurred_image.clone()).unwrap();
+
+        let framebuffer = |render_pass: &Arc<RenderPass>, view| {
+            Arc::new(
+                Framebuffer::start(render_pass.clone())
+                    .add(view)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        };
+
+        let sampler = |filter| {
+            Sampler::new(
+                device.clone(),
+                filter,
+                filter,
+                MipmapMode::Nearest,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            .unwrap()
+        };
+        let linear_sampler = sampler(Filter::Linear);
+        let nearest_sampler = sampler(Filter::Nearest);
+
+        let tile_pipeline = Pipeline::MotionBlurTiles.create(device.clone(), tile_dimensions, shaders, tile_render_pass.clone());
+        let neighbour_pipeline = Pipeline::MotionBlurNeighbours.create(device.clone(), tile_dimensions, shaders, tile_render_pass.clone());
+        let gather_pipeline = Pipeline::MotionBlur.create(device, dimensions, shaders, post_render_pass.clone());
+
+        let layout = tile_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let tile_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(scene_images.velocity.clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = neighbour_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let neighbour_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(tile_view.clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = gather_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let gather_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(scene_images.color.clone(), linear_sampler)
+                .unwrap()
+                .add_sampled_image(scene_images.velocity.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.normal_depth.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(neighbour_view.clone(), nearest_sampler)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Some(Self {
+            tile_pipeline,
+            tile_framebuffer: framebuffer(&tile_render_pass, tile_view),
+            tile_set,
+            neighbour_pipeline,
+            neighbour_framebuffer: framebuffer(&tile_render_pass, neighbour_view),
+            neighbour_set,
+            gather_pipeline,
+            gather_framebuffer: framebuffer(&post_render_pass, blurred_view),
+            gather_set,
+            blurred_image,
+            scene_image: scene_images.color.image().clone(),
+            dimensions,
+            frame: 0,
+        })
+    }
+
+    /// Records the motion blur passes, replacing the scene colour with the blurred result.
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+        settings: &MotionBlur,
+    ) {
+        // The motion vectors cover the whole frame in texture space. The blur covers the fraction
+        // of that the shutter is open for, either side of the pixel.
+        let exposure = settings.shutter_angle / 360.0;
+        let velocity_scale = [
+            0.5 * exposure * self.dimensions[0] as f32,
+            0.5 * exposure * self.dimensions[1] as f32,
+        ];
+        let max_radius = TILE_SIZE as f32;
+
+        let tile_push_constants = motion_blur_tiles_frag::ty::TilePushConstants {
+            velocity_scale,
+            max_radius,
+            tile_size: TILE_SIZE,
+        };
+
+        let gather_push_constants = motion_blur_frag::ty::MotionBlurPushConstants {
+            velocity_scale,
+            max_radius,
+            frame: self.frame,
+        };
+
+        builder
+            .begin_render_pass(
+                self.tile_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.tile_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer.clone()],
+                self.tile_set.clone(),
+                tile_push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap()
+            .begin_render_pass(
+                self.neighbour_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.neighbour_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer.clone()],
+                self.neighbour_set.clone(),
+                (),
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap()
+            .begin_render_pass(
+                self.gather_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.gather_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                self.gather_set.clone(),
+                gather_push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap()
+            .copy_image(
+                self.blurred_image.clone(),
+                [0, 0, 0],
+                0,
+                0,
+                self.scene_image.clone(),
+                [0, 0, 0],
+                0,
+                0,
+                [self.dimensions[0], self.dimensions[1], 1],
+                1,
+            )
+            .unwrap();
+
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
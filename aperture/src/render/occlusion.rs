@@ -0,0 +1,330 @@
+use crate::render::base::{SceneImages, NORMAL_DEPTH_FORMAT};
+use crate::render::hiz::HiZ;
+use crate::render::shaders::*;
+use crate::render::world_render::PrimitiveInfo;
+use crate::vulkan::Pipeline;
+
+use cgmath::Matrix4;
+use vulkano::buffer::{
+    BufferAccess, BufferSlice, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, DeviceLocalBuffer, TypedBufferAccess,
+};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DrawIndirectCommand, PrimaryAutoCommandBuffer, SubpassContents,
+};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract, GraphicsPipelineAbstract};
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+/// The number of primitives each workgroup of the culling shader tests.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The indirect draw commands for each primitive, with an instance count of zero for primitives
+/// that are hidden. Indexed primitives are drawn with the indexed commands, and the rest with the
+/// others.
+#[derive(Clone)]
+pub struct DrawCommands {
+    indexed: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+    non_indexed: Arc<DeviceLocalBuffer<[DrawIndirectCommand]>>,
+}
+
+impl DrawCommands {
+    /// The indexed command for the primitive at `index`.
+    pub fn indexed(&self, index: usize) -> BufferSlice<[DrawIndexedIndirectCommand], Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>> {
+        self.indexed.clone().into_buffer_slice().slice(index..index + 1).unwrap()
+    }
+
+    /// The non-indexed command for the primitive at `index`.
+    pub fn non_indexed(&self, index: usize) -> BufferSlice<[DrawIndirectCommand], Arc<DeviceLocalBuffer<[DrawIndirectCommand]>>> {
+        self.non_indexed.clone().into_buffer_slice().slice(index..index + 1).unwrap()
+    }
+}
+
+/// The buffers sized for the primitives of the loaded world.
+struct CullingBuffers {
+    objects: Arc<DeviceLocalBuffer<[occlusion_cull_comp::ty::Object]>>,
+    commands: DrawCommands,
+    occluded_count: Arc<CpuAccessibleBuffer<u32>>,
+    set: Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+/// Two-phase occlusion culling against a hierarchical depth buffer.
+///
+/// In the first phase, the opaque primitives that were visible last frame are drawn into the
+/// scene depth buffer as occluders, and the depth pyramid is built from them. In the second, a
+/// compute shader tests the bounds of every primitive against the pyramid, writing the indirect
+/// draw commands the scene is drawn with. As the pyramid only holds primitives that are really
+/// visible, nothing visible is culled, and primitives that come into view are drawn straight
+/// away, becoming occluders from the next frame.
+///
+/// The commands written in the second phase are the ones drawn in the first phase of the next
+/// frame. Only supported without MSAA, as the pyramid is built from single-sampled depth.
+pub struct OcclusionCullingPass {
+    prepass_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    prepass_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+
+    cull_pipeline: Arc<ComputePipeline>,
+    object_pool: CpuBufferPool<occlusion_cull_comp::ty::Object>,
+    hiz_levels: Vec<Arc<ImageView<Arc<AttachmentImage>>>>,
+    sampler: Arc<Sampler>,
+
+    // The buffers are created when the culling pass is first prepared, and recreated whenever the
+    // number of primitives changes.
+    buffers: Option<CullingBuffers>,
+}
+
+impl OcclusionCullingPass {
+    /// `depth_buffer` is the single-sampled depth attachment of the scene render pass. The first
+    /// phase draws into it, and into the scene normals and depth, before the scene pass clears
+    /// them.
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        depth_buffer: Arc<ImageView<Arc<AttachmentImage>>>,
+        scene_images: &SceneImages,
+        hiz: &HiZ,
+    ) -> Self {
+        let prepass_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    normal_depth: {
+                        load: Clear,
+                        store: Store,
+                        format: NORMAL_DEPTH_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [normal_depth],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+
+        let prepass_framebuffer = Arc::new(
+            Framebuffer::start(prepass_render_pass.clone())
+                .add(scene_images.normal_depth.clone())
+                .unwrap()
+                .add(depth_buffer)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let prepass_pipeline = Pipeline::DepthPrepass.create(device.clone(), dimensions, shaders, prepass_render_pass);
+
+        let cull_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shaders.occlusion_cull_comp.main_entry_point(), &(), None).unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        Self {
+            prepass_pipeline,
+            prepass_framebuffer,
+            cull_pipeline,
+            object_pool: CpuBufferPool::new(device, BufferUsage::transfer_source()),
+            hiz_levels: hiz.levels.clone(),
+            sampler,
+            buffers: None,
+        }
+    }
+
+    /// The pipeline the occluders are drawn with in the first phase.
+    pub fn prepass_pipeline(&self) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        self.prepass_pipeline.clone()
+    }
+
+    /// The draw commands written by the last culling pass.
+    pub fn commands(&self) -> Option<DrawCommands> {
+        self.buffers.as_ref().map(|buffers| buffers.commands.clone())
+    }
+
+    /// The number of primitives hidden by others in the last frame the GPU has finished, if any.
+    pub fn read_occluded_count(&self) -> Option<u32> {
+        let buffers = self.buffers.as_ref()?;
+
+        // Fails while the buffer is still in use by the GPU.
+        buffers.occluded_count.read().ok().map(|count| *count)
+    }
+
+    /// Creates the buffers for the given number of primitives, if it has changed. New commands are
+    /// cleared, so nothing is drawn in the first phase until a culling pass has run.
+    pub fn prepare(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        device: Arc<Device>,
+        primitive_count: usize,
+    ) {
+        if primitive_count == 0 {
+            return;
+        }
+        if let Some(buffers) = &self.buffers {
+            if buffers.objects.len() == primitive_count {
+                return;
+            }
+        }
+
+        let usage = BufferUsage {
+            storage_buffer: true,
+            transfer_destination: true,
+            ..BufferUsage::none()
+        };
+        let command_usage = BufferUsage {
+            indirect_buffer: true,
+            ..usage
+        };
+
+        let objects = DeviceLocalBuffer::array(device.clone(), primitive_count, usage, device.active_queue_families()).unwrap();
+        let commands = DrawCommands {
+            indexed: DeviceLocalBuffer::array(device.clone(), primitive_count, command_usage, device.active_queue_families())
+                .unwrap(),
+            non_indexed: DeviceLocalBuffer::array(device.clone(), primitive_count, command_usage, device.active_queue_families())
+                .unwrap(),
+        };
+        let occluded_count = CpuAccessibleBuffer::from_data(device, usage, true, 0u32).unwrap();
+
+        builder
+            .fill_buffer(commands.indexed.clone(), 0)
+            .unwrap()
+            .fill_buffer(commands.non_indexed.clone(), 0)
+            .unwrap();
+
+        let layout = self.cull_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(objects.clone())
+                .unwrap()
+                .add_buffer(commands.indexed.clone())
+                .unwrap()
+                .add_buffer(commands.non_indexed.clone())
+                .unwrap()
+                .add_buffer(occluded_count.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[0].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[1].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[2].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[3].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[4].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[5].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[6].clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(self.hiz_levels[7].clone(), self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        self.buffers = Some(CullingBuffers {
+            objects,
+            commands,
+            occluded_count,
+            set,
+        });
+    }
+
+    /// Begins the first phase. The occluders are then drawn with the prepass pipeline and the
+    /// current commands, before ending the render pass.
+    pub fn begin_prepass(&self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        builder
+            .begin_render_pass(
+                self.prepass_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![[0.0, 0.0, 0.0, 0.0].into(), 1f32.into()],
+            )
+            .unwrap();
+    }
+
+    /// Records the second phase, testing every primitive against the depth pyramid, which must
+    /// already have been built from the occluders. `frustum_visible` is whether each primitive
+    /// passed frustum culling; the rest are hidden without being tested.
+    ///
+    /// `view` and `proj` are the unjittered matrices the scene is drawn with.
+    pub fn cull(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        primitives: &[PrimitiveInfo],
+        frustum_visible: &[bool],
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+    ) {
+        let buffers = match &self.buffers {
+            Some(buffers) if !primitives.is_empty() => buffers,
+            _ => return,
+        };
+
+        let objects = primitives.iter().zip(frustum_visible).map(|(draw_info, &visible)| {
+            let aabb = draw_info.world_aabb;
+            let count = match &draw_info.index_buffer {
+                Some(index_buffer) => index_buffer.len(),
+                None => draw_info.vertex_buffer.len(),
+            };
+
+            occlusion_cull_comp::ty::Object {
+                aabb_min: aabb.min.to_homogeneous().into(),
+                aabb_max: aabb.max.to_homogeneous().into(),
+                count: count as u32,
+                indexed: draw_info.has_indices() as u32,
+                frustum_visible: visible as u32,
+                instance_count: draw_info.instance_count() as u32,
+            }
+        });
+        let chunk = self.object_pool.chunk(objects).unwrap();
+
+        let push_constants = occlusion_cull_comp::ty::CullPushConstants {
+            view: view.into(),
+            projection_scale: [proj.x.x, proj.y.y],
+            object_count: primitives.len() as u32,
+        };
+
+        let group_count = (primitives.len() as u32).div_ceil(WORKGROUP_SIZE);
+
+        builder
+            .copy_buffer(chunk, buffers.objects.clone())
+            .unwrap()
+            .fill_buffer(buffers.occluded_count.clone(), 0)
+            .unwrap()
+            .dispatch(
+                [group_count, 1, 1],
+                self.cull_pipeline.clone(),
+                buffers.set.clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use crate::render::base::{SceneImages, SCENE_FORMAT};
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::ImageView;
+use vulkano::image::{AttachmentImage, SampleCount};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// The format of the sum of the weighted colours and alphas.
+const ACCUM_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The format of the product of the transmittance of the transparent surfaces.
+const REVEALAGE_FORMAT: Format = Format::R16Sfloat;
+
+/// How transparent primitives are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transparency {
+    /// Each primitive is blended over the scene in turn, sorted back to front by its centre.
+    /// Exact when primitives don't overlap in depth, but wrong where they intersect.
+    #[default]
+    Sorted,
+    /// The primitives are accumulated without sorting, and resolved to a weighted average of
+    /// their colours. Order-independent, so overlapping and intersecting primitives don't pop,
+    /// but an approximation where many surfaces with similar depths overlap.
+    WeightedBlended,
+}
+
+impl Transparency {
+    /// The next strategy in the cycle used to switch between them at runtime.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Sorted => Self::WeightedBlended,
+            Self::WeightedBlended => Self::Sorted,
+        }
+    }
+}
+
+/// Weighted blended order-independent transparency.
+/// See McGuire and Bavoil, "Weighted Blended Order-Independent Transparency".
+///
+/// The transparent primitives are accumulated after the scene render pass, tested against the
+/// scene depth, then composited over the scene colour. When multisampling, the accumulation is
+/// multisampled too, and resolved before it's composited.
+pub struct WeightedBlendedPass {
+    /// The pipeline the transparent primitives are accumulated with.
+    pub pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    accum_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    samples: u32,
+
+    composite_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    composite_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    composite_set: Arc<dyn DescriptorSet + Send + Sync>,
+}
+
+impl WeightedBlendedPass {
+    /// `depth_buffer` is the depth attachment of the scene render pass, which must be stored.
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        samples: u32,
+        depth_buffer: Arc<ImageView<Arc<AttachmentImage>>>,
+        scene_images: &SceneImages,
+    ) -> Option<Self> {
+        let accum_render_pass = create_accum_render_pass(device.clone(), samples);
+
+        // The accumulation is resolved into these, to be read by the composite.
+        let accum_view = ImageView::new(AttachmentImage::sampled(device.clone(), dimensions, ACCUM_FORMAT).ok()?).unwrap();
+        let revealage_view = ImageView::new(AttachmentImage::sampled(device.clone(), dimensions, REVEALAGE_FORMAT).ok()?).unwrap();
+
+        let accum_framebuffer = if samples > 1 {
+            let sample_count = SampleCount::try_from(samples).unwrap();
+            let multisampled = |format| {
+                ImageView::new(AttachmentImage::transient_multisampled(device.clone(), dimensions, sample_count, format).ok()?).ok()
+            };
+
+            Arc::new(
+                Framebuffer::start(accum_render_pass.clone())
+                    .add(multisampled(ACCUM_FORMAT)?)
+                    .unwrap()
+                    .add(multisampled(REVEALAGE_FORMAT)?)
+                    .unwrap()
+                    .add(depth_buffer)
+                    .unwrap()
+                    .add(accum_view.clone())
+                    .unwrap()
+                    .add(revealage_view.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        } else {
+            Arc::new(
+                Framebuffer::start(accum_render_pass.clone())
+                    .add(accum_view.clone())
+                    .unwrap()
+                    .add(revealage_view.clone())
+                    .unwrap()
+                    .add(depth_buffer)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        };
+
+        // The transparent surfaces are blended over the scene, so it must be preserved.
+        let composite_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let composite_framebuffer = Arc::new(
+            Framebuffer::start(composite_render_pass.clone())
+                .add(scene_images.color.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let pipeline = Pipeline::WeightedBlended.create(device.clone(), dimensions, shaders, accum_render_pass);
+        let composite_pipeline = Pipeline::OitComposite.create(device, dimensions, shaders, composite_render_pass);
+
+        let layout = composite_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let composite_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(accum_view, sampler.clone())
+                .unwrap()
+                .add_sampled_image(revealage_view, sampler)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Some(Self {
+            pipeline,
+            accum_framebuffer,
+            samples,
+            composite_pipeline,
+            composite_framebuffer,
+            composite_set,
+        })
+    }
+
+    /// Begins the accumulation render pass. The transparent primitives are then drawn with
+    /// `pipeline`, in any order, before calling `composite`.
+    pub fn begin(&self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        let clear_values = if self.samples > 1 {
+            vec![
+                [0.0, 0.0, 0.0, 0.0].into(),
+                [1.0, 0.0, 0.0, 0.0].into(),
+                ClearValue::None,
+                ClearValue::None,
+                ClearValue::None,
+            ]
+        } else {
+            vec![
+                [0.0, 0.0, 0.0, 0.0].into(),
+                [1.0, 0.0, 0.0, 0.0].into(),
+                ClearValue::None,
+            ]
+        };
+
+        builder
+            .begin_render_pass(self.accum_framebuffer.clone(), SubpassContents::Inline, clear_values)
+            .unwrap();
+    }
+
+    /// Ends the accumulation render pass, and blends the average of the transparent surfaces
+    /// over the scene colour.
+    pub fn composite(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+    ) {
+        builder
+            .end_render_pass()
+            .unwrap()
+            .begin_render_pass(
+                self.composite_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.composite_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                self.composite_set.clone(),
+                (),
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
+
+/// Creates the render pass the transparent primitives are accumulated in, loading the depth
+/// written by the scene render pass.
+fn create_accum_render_pass(device: Arc<Device>, samples: u32) -> Arc<RenderPass> {
+    if samples > 1 {
+        Arc::new(
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    accum: {
+                        load: Clear,
+                        store: DontCare,
+                        format: ACCUM_FORMAT,
+                        samples: samples,
+                    },
+                    revealage: {
+                        load: Clear,
+                        store: DontCare,
+                        format: REVEALAGE_FORMAT,
+                        samples: samples,
+                    },
+                    depth: {
+                        load: Load,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: samples,
+                    },
+                    accum_resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: ACCUM_FORMAT,
+                        samples: 1,
+                    },
+                    revealage_resolve: {
+                        load: DontCare,
+                        store: Store,
+                        format: REVEALAGE_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [accum, revealage],
+                    depth_stencil: {depth},
+                    resolve: [accum_resolve, revealage_resolve]
+                }
+            )
+            .unwrap(),
+        )
+    } else {
+        Arc::new(
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    accum: {
+                        load: Clear,
+                        store: Store,
+                        format: ACCUM_FORMAT,
+                        samples: 1,
+                    },
+                    revealage: {
+                        load: Clear,
+                        store: Store,
+                        format: REVEALAGE_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Load,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [accum, revealage],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        )
+    }
+}
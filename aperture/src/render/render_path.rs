@@ -0,0 +1,21 @@
+/// How the scene is lit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    /// Each primitive is lit as it's drawn.
+    #[default]
+    Forward,
+    /// The surface properties of each pixel are written to a G-buffer, which is then lit in a
+    /// single fullscreen pass, so the cost of lighting doesn't grow with overdraw.
+    /// MSAA isn't supported.
+    Deferred,
+}
+
+impl RenderPath {
+    /// The next path in the cycle used to switch between them at runtime.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Forward => Self::Deferred,
+            Self::Deferred => Self::Forward,
+        }
+    }
+}
@@ -1,7 +1,30 @@
+use crate::vulkan::{Pipeline, PushConstantBlock, PushConstantError, PushConstantLayout, PushConstants};
+
 use vulkano::device::Device;
+use vulkano::pipeline::shader::EntryPointAbstract;
 
+use std::mem;
 use std::sync::Arc;
 
+// The forward shader, for materials blended over the scene.
+pub mod transparent_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/pbr.frag",
+        define: [("TRANSPARENT", "1")]
+    }
+}
+
+// The forward shader, accumulating transparent materials for weighted blended order-independent
+// transparency.
+pub mod weighted_blended_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/pbr.frag",
+        define: [("TRANSPARENT", "1"), ("WEIGHTED_BLENDED", "1")]
+    }
+}
+
 pub mod cube_vert {
     vulkano_shaders::shader! {
         ty: "vertex",
@@ -40,17 +63,190 @@ pub mod vert {
 pub mod frag {
     vulkano_shaders::shader! {
         ty: "fragment",
-        path: "../data/shaders/pbr.frag"
+        path: "../data/shaders/pbr.frag",
+        // The material block is zeroed over the vertex stage's range, which it isn't pushed with.
+        types_meta: {
+            #[derive(Clone, Copy, Default)]
+        }
+    }
+}
+
+pub mod fullscreen_vert {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "../data/shaders/fullscreen.vert"
     }
 }
 
-pub mod depth {
+pub mod present_frag {
     vulkano_shaders::shader! {
         ty: "fragment",
-        path: "../data/shaders/depth.glsl"
+        path: "../data/shaders/present.frag"
     }
 }
 
+pub mod fxaa_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/fxaa.frag"
+    }
+}
+
+pub mod taa_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/taa.frag"
+    }
+}
+
+pub mod ssao_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/ssao.frag"
+    }
+}
+
+pub mod ssao_blur_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/ssao_blur.frag"
+    }
+}
+
+pub mod ambient_composite_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/ambient_composite.frag"
+    }
+}
+
+pub mod dof_coc_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/dof_coc.frag"
+    }
+}
+
+pub mod dof_tiles_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/dof_tiles.frag"
+    }
+}
+
+pub mod dof_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/dof.frag"
+    }
+}
+
+pub mod motion_blur_tiles_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/motion_blur_tiles.frag"
+    }
+}
+
+pub mod motion_blur_neighbours_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/motion_blur_neighbours.frag"
+    }
+}
+
+pub mod motion_blur_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/motion_blur.frag"
+    }
+}
+
+pub mod gbuffer_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/gbuffer.frag"
+    }
+}
+
+pub mod deferred_lighting_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/deferred_lighting.frag"
+    }
+}
+
+pub mod hiz_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/hiz.frag"
+    }
+}
+
+pub mod ssr_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/ssr.frag"
+    }
+}
+
+pub mod ssr_composite_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/ssr_composite.frag"
+    }
+}
+
+pub mod oit_composite_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/oit_composite.frag"
+    }
+}
+
+pub mod depth_prepass_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/depth_prepass.frag"
+    }
+}
+
+pub mod occlusion_cull_comp {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "../data/shaders/occlusion_cull.comp"
+    }
+}
+
+// The GPU-driven path, reading per-draw data and materials from storage buffers.
+pub mod gpu_driven_vert {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "../data/shaders/gpu_driven.vert"
+    }
+}
+
+pub mod gpu_driven_frag {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "../data/shaders/pbr.frag",
+        define: [("GPU_DRIVEN", "1")]
+    }
+}
+
+pub mod gpu_driven_cull_comp {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "../data/shaders/gpu_driven_cull.comp"
+    }
+}
+
+// The generated blocks are `repr(C)`, with their padding as explicit fields.
+unsafe impl PushConstantBlock for vert::ty::VertPushConstants {}
+unsafe impl PushConstantBlock for frag::ty::FragPushConstants {}
+
+/// The push constants of a primitive drawn with its material, which end with the fragment block.
+pub type MaterialPushConstants = PushConstants<{ mem::size_of::<frag::ty::FragPushConstants>() }>;
 
 pub struct Shaders {
     pub cubemap_vert: cube_vert::Shader,
@@ -59,19 +255,91 @@ pub struct Shaders {
     pub offscreen_cube_frag: offscreen_cube_frag::Shader,
     pub vertex: vert::Shader,
     pub fragment: frag::Shader,
-    pub depth: depth::Shader,
+    pub transparent_fragment: transparent_frag::Shader,
+    pub weighted_blended_fragment: weighted_blended_frag::Shader,
+    pub fullscreen_vert: fullscreen_vert::Shader,
+    pub present_frag: present_frag::Shader,
+    pub fxaa_frag: fxaa_frag::Shader,
+    pub taa_frag: taa_frag::Shader,
+    pub ssao_frag: ssao_frag::Shader,
+    pub ssao_blur_frag: ssao_blur_frag::Shader,
+    pub ambient_composite_frag: ambient_composite_frag::Shader,
+    pub dof_coc_frag: dof_coc_frag::Shader,
+    pub dof_tiles_frag: dof_tiles_frag::Shader,
+    pub dof_frag: dof_frag::Shader,
+    pub motion_blur_tiles_frag: motion_blur_tiles_frag::Shader,
+    pub motion_blur_neighbours_frag: motion_blur_neighbours_frag::Shader,
+    pub motion_blur_frag: motion_blur_frag::Shader,
+    pub gbuffer_frag: gbuffer_frag::Shader,
+    pub deferred_lighting_frag: deferred_lighting_frag::Shader,
+    pub hiz_frag: hiz_frag::Shader,
+    pub ssr_frag: ssr_frag::Shader,
+    pub ssr_composite_frag: ssr_composite_frag::Shader,
+    pub oit_composite_frag: oit_composite_frag::Shader,
+    pub depth_prepass_frag: depth_prepass_frag::Shader,
+    pub occlusion_cull_comp: occlusion_cull_comp::Shader,
+    pub gpu_driven_vert: gpu_driven_vert::Shader,
+    pub gpu_driven_frag: gpu_driven_frag::Shader,
+    pub gpu_driven_cull_comp: gpu_driven_cull_comp::Shader,
+    /// The push constants of the pipelines drawing primitives with their materials.
+    pub material_push_constants: PushConstantLayout,
 }
 
 impl Shaders {
-    pub fn new(device: Arc<Device>) -> Self {
-        Self {
+    /// Loads the shaders, failing if the material push constant blocks don't match them.
+    pub fn new(device: Arc<Device>) -> Result<Self, PushConstantError> {
+        let vertex = vert::Shader::load(device.clone()).unwrap();
+        let fragment = frag::Shader::load(device.clone()).unwrap();
+        let transparent_fragment = transparent_frag::Shader::load(device.clone()).unwrap();
+        let weighted_blended_fragment = weighted_blended_frag::Shader::load(device.clone()).unwrap();
+        let gbuffer_frag = gbuffer_frag::Shader::load(device.clone()).unwrap();
+        let depth_prepass_frag = depth_prepass_frag::Shader::load(device.clone()).unwrap();
+
+        let material_push_constants = Pipeline::material_push_constants(
+            &vertex,
+            &fragment,
+            &[
+                transparent_fragment.main_entry_point().layout_desc(),
+                weighted_blended_fragment.main_entry_point().layout_desc(),
+                gbuffer_frag.main_entry_point().layout_desc(),
+                depth_prepass_frag.main_entry_point().layout_desc(),
+            ],
+        )?;
+
+        Ok(Self {
             cubemap_vert: cube_vert::Shader::load(device.clone()).unwrap(),
             cubemap_frag: cube_frag::Shader::load(device.clone()).unwrap(),
             offscreen_cube_vert: offscreen_cube_vert::Shader::load(device.clone()).unwrap(),
             offscreen_cube_frag: offscreen_cube_frag::Shader::load(device.clone()).unwrap(),
-            vertex: vert::Shader::load(device.clone()).unwrap(),
-            fragment: frag::Shader::load(device.clone()).unwrap(),
-            depth: depth::Shader::load(device).unwrap(),
-        }
+            vertex,
+            fragment,
+            transparent_fragment,
+            weighted_blended_fragment,
+            fullscreen_vert: fullscreen_vert::Shader::load(device.clone()).unwrap(),
+            present_frag: present_frag::Shader::load(device.clone()).unwrap(),
+            fxaa_frag: fxaa_frag::Shader::load(device.clone()).unwrap(),
+            taa_frag: taa_frag::Shader::load(device.clone()).unwrap(),
+            ssao_frag: ssao_frag::Shader::load(device.clone()).unwrap(),
+            ssao_blur_frag: ssao_blur_frag::Shader::load(device.clone()).unwrap(),
+            ambient_composite_frag: ambient_composite_frag::Shader::load(device.clone()).unwrap(),
+            dof_coc_frag: dof_coc_frag::Shader::load(device.clone()).unwrap(),
+            dof_tiles_frag: dof_tiles_frag::Shader::load(device.clone()).unwrap(),
+            dof_frag: dof_frag::Shader::load(device.clone()).unwrap(),
+            motion_blur_tiles_frag: motion_blur_tiles_frag::Shader::load(device.clone()).unwrap(),
+            motion_blur_neighbours_frag: motion_blur_neighbours_frag::Shader::load(device.clone()).unwrap(),
+            motion_blur_frag: motion_blur_frag::Shader::load(device.clone()).unwrap(),
+            gbuffer_frag,
+            deferred_lighting_frag: deferred_lighting_frag::Shader::load(device.clone()).unwrap(),
+            hiz_frag: hiz_frag::Shader::load(device.clone()).unwrap(),
+            ssr_frag: ssr_frag::Shader::load(device.clone()).unwrap(),
+            ssr_composite_frag: ssr_composite_frag::Shader::load(device.clone()).unwrap(),
+            oit_composite_frag: oit_composite_frag::Shader::load(device.clone()).unwrap(),
+            depth_prepass_frag,
+            occlusion_cull_comp: occlusion_cull_comp::Shader::load(device.clone()).unwrap(),
+            gpu_driven_vert: gpu_driven_vert::Shader::load(device.clone()).unwrap(),
+            gpu_driven_frag: gpu_driven_frag::Shader::load(device.clone()).unwrap(),
+            gpu_driven_cull_comp: gpu_driven_cull_comp::Shader::load(device).unwrap(),
+            material_push_constants,
+        })
     }
 }
@@ -0,0 +1,266 @@
+use crate::render::base::{SceneImages, SCENE_FORMAT};
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use cgmath::Matrix4;
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::ImageView;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+/// The format of the ambient occlusion images, holding the visibility of each pixel.
+const OCCLUSION_FORMAT: Format = Format::R8Unorm;
+
+/// Settings for the screen-space ambient occlusion applied to the ambient lighting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    pub enabled: bool,
+    /// The view space radius around each pixel in which geometry occludes it.
+    pub radius: f32,
+    /// How strongly occlusion darkens the ambient lighting. 1.0 is physically based.
+    pub intensity: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.5,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Calculates ambient occlusion from the scene normals and depth, blurs it, and adds the occluded
+/// ambient lighting to the scene.
+pub struct AmbientOcclusionPass {
+    occlusion_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    occlusion_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    occlusion_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    blur_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    blur_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    blur_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    composite_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    composite_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    composite_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    frame: u32,
+}
+
+impl AmbientOcclusionPass {
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        scene_images: &SceneImages,
+    ) -> Option<Self> {
+        let occlusion_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    occlusion: {
+                        load: DontCare,
+                        store: Store,
+                        format: OCCLUSION_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [occlusion],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        // The ambient lighting is blended on top of the lit scene, so it must be preserved.
+        let composite_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let occlusion_view = ImageView::new(AttachmentImage::sampled(device.clone(), dimensions, OCCLUSION_FORMAT).ok()?).unwrap();
+        let blurred_view = ImageView::new(AttachmentImage::sampled(device.clone(), dimensions, OCCLUSION_FORMAT).ok()?).unwrap();
+
+        let framebuffer = |render_pass: &Arc<RenderPass>, view| {
+            Arc::new(
+                Framebuffer::start(render_pass.clone())
+                    .add(view)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        };
+
+        // Normals and depth must not be interpolated across edges.
+        let nearest_sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let occlusion_pipeline = Pipeline::Ssao.create(device.clone(), dimensions, shaders, occlusion_render_pass.clone());
+        let blur_pipeline = Pipeline::SsaoBlur.create(device.clone(), dimensions, shaders, occlusion_render_pass.clone());
+        let composite_pipeline = Pipeline::AmbientComposite.create(device, dimensions, shaders, composite_render_pass.clone());
+
+        let layout = occlusion_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let occlusion_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(scene_images.normal_depth.clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = blur_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let blur_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(occlusion_view.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.normal_depth.clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = composite_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let composite_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(scene_images.ambient.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(blurred_view.clone(), nearest_sampler)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Some(Self {
+            occlusion_pipeline,
+            occlusion_framebuffer: framebuffer(&occlusion_render_pass, occlusion_view),
+            occlusion_set,
+            blur_pipeline,
+            blur_framebuffer: framebuffer(&occlusion_render_pass, blurred_view),
+            blur_set,
+            composite_pipeline,
+            composite_framebuffer: framebuffer(&composite_render_pass, scene_images.color.clone()),
+            composite_set,
+            frame: 0,
+        })
+    }
+
+    /// Records the occlusion and blur passes if enabled, then adds the ambient lighting to the
+    /// scene colour.
+    ///
+    /// `proj` is the unjittered projection the scene was drawn with, used to reconstruct view space
+    /// positions from depth.
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+        settings: &AmbientOcclusion,
+        proj: Matrix4<f32>,
+    ) {
+        if settings.enabled {
+            let push_constants = ssao_frag::ty::SsaoPushConstants {
+                proj: proj.into(),
+                radius: settings.radius,
+                intensity: settings.intensity,
+                frame: self.frame,
+            };
+
+            builder
+                .begin_render_pass(
+                    self.occlusion_framebuffer.clone(),
+                    SubpassContents::Inline,
+                    vec![ClearValue::None],
+                )
+                .unwrap()
+                .draw(
+                    self.occlusion_pipeline.clone(),
+                    &DynamicState::none(),
+                    vec![fullscreen_vertex_buffer.clone()],
+                    self.occlusion_set.clone(),
+                    push_constants,
+                    vec![],
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap()
+                .begin_render_pass(
+                    self.blur_framebuffer.clone(),
+                    SubpassContents::Inline,
+                    vec![ClearValue::None],
+                )
+                .unwrap()
+                .draw(
+                    self.blur_pipeline.clone(),
+                    &DynamicState::none(),
+                    vec![fullscreen_vertex_buffer.clone()],
+                    self.blur_set.clone(),
+                    (),
+                    vec![],
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap();
+
+            self.frame = self.frame.wrapping_add(1);
+        }
+
+        let push_constants = ambient_composite_frag::ty::CompositePushConstants {
+            occlusion_enabled: settings.enabled as u32,
+        };
+
+        builder
+            .begin_render_pass(
+                self.composite_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.composite_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                self.composite_set.clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
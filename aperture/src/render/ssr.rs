@@ -0,0 +1,269 @@
+use crate::render::base::{SceneImages, SCENE_FORMAT};
+use crate::render::environment::Environment;
+use crate::render::hiz::HiZ;
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use cgmath::Matrix4;
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::ClearValue;
+use vulkano::image::view::ImageView;
+use vulkano::image::{AttachmentImage, StorageImage};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use std::sync::Arc;
+
+/// Settings for the screen-space reflections. Where reflected rays leave the screen or miss, the
+/// environment is reflected instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenSpaceReflections {
+    pub enabled: bool,
+    /// The furthest distance a reflected ray is traced, in metres.
+    pub max_distance: f32,
+    /// How far behind a surface a ray can pass and still hit it, in metres.
+    pub thickness: f32,
+    /// The blur radius of the reflections on fully rough surfaces, in pixels.
+    pub max_blur_radius: f32,
+}
+
+impl Default for ScreenSpaceReflections {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_distance: 10.0,
+            thickness: 0.2,
+            max_blur_radius: 16.0,
+        }
+    }
+}
+
+/// Traces reflected rays through the depth pyramid, and adds the specular reflections to the
+/// scene.
+///
+/// The traced reflections are blurred by roughness as they're composited, and blended with the
+/// environment where the trace is unsure of, or missed, a hit.
+pub struct ReflectionPass {
+    trace_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    trace_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    trace_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    composite_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    composite_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    composite_set: Arc<dyn DescriptorSet + Send + Sync>,
+
+    // The environment cubemap is loaded with the world, so its set is created when first drawn,
+    // and recreated if the environment changes.
+    environment_cubemap: Option<Arc<ImageView<Arc<StorageImage>>>>,
+    environment_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+    environment_sampler: Arc<Sampler>,
+}
+
+impl ReflectionPass {
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        post_render_pass: Arc<RenderPass>,
+        scene_images: &SceneImages,
+        hiz: &HiZ,
+    ) -> Option<Self> {
+        // The reflections are blended on top of the lit scene, so it must be preserved.
+        let composite_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let reflection_view = ImageView::new(AttachmentImage::sampled(device.clone(), dimensions, SCENE_FORMAT).ok()?).unwrap();
+
+        let framebuffer = |render_pass: &Arc<RenderPass>, view| {
+            Arc::new(
+                Framebuffer::start(render_pass.clone())
+                    .add(view)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        };
+
+        let sampler = |filter| {
+            Sampler::new(
+                device.clone(),
+                filter,
+                filter,
+                MipmapMode::Nearest,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            .unwrap()
+        };
+        let linear_sampler = sampler(Filter::Linear);
+        let nearest_sampler = sampler(Filter::Nearest);
+
+        let trace_pipeline = Pipeline::Ssr.create(device.clone(), dimensions, shaders, post_render_pass.clone());
+        let composite_pipeline = Pipeline::SsrComposite.create(device, dimensions, shaders, composite_render_pass.clone());
+
+        let layout = trace_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let trace_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(scene_images.color.clone(), linear_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.normal_depth.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.specular.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[0].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[1].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[2].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[3].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[4].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[5].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[6].clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(hiz.levels[7].clone(), nearest_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let layout = composite_pipeline.layout().descriptor_set_layout(0).unwrap();
+        let composite_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(reflection_view.clone(), linear_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.normal_depth.clone(), nearest_sampler.clone())
+                .unwrap()
+                .add_sampled_image(scene_images.specular.clone(), nearest_sampler)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Some(Self {
+            trace_pipeline,
+            trace_framebuffer: framebuffer(&post_render_pass, reflection_view),
+            trace_set,
+            composite_pipeline,
+            composite_framebuffer: framebuffer(&composite_render_pass, scene_images.color.clone()),
+            composite_set,
+            environment_cubemap: None,
+            environment_set: None,
+            environment_sampler: linear_sampler,
+        })
+    }
+
+    /// Records the trace pass if enabled, then adds the reflections to the scene colour.
+    ///
+    /// The depth pyramid must already have been built from this frame's depth. `view` and `proj`
+    /// are the unjittered matrices the scene was drawn with.
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+        settings: &ScreenSpaceReflections,
+        environment: &Environment,
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+    ) {
+        let environment_changed = match &self.environment_cubemap {
+            Some(cubemap) => !Arc::ptr_eq(cubemap, &environment.cubemap_view),
+            None => true,
+        };
+
+        if environment_changed {
+            let layout = self.composite_pipeline.layout().descriptor_set_layout(1).unwrap();
+            self.environment_set = Some(Arc::new(
+                PersistentDescriptorSet::start(layout.clone())
+                    .add_sampled_image(environment.cubemap_view.clone(), self.environment_sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ));
+            self.environment_cubemap = Some(environment.cubemap_view.clone());
+        }
+
+        let environment_set = self.environment_set.clone().unwrap();
+
+        if settings.enabled {
+            let push_constants = ssr_frag::ty::SsrPushConstants {
+                proj: proj.into(),
+                max_distance: settings.max_distance,
+                thickness: settings.thickness,
+            };
+
+            builder
+                .begin_render_pass(
+                    self.trace_framebuffer.clone(),
+                    SubpassContents::Inline,
+                    vec![ClearValue::None],
+                )
+                .unwrap()
+                .draw(
+                    self.trace_pipeline.clone(),
+                    &DynamicState::none(),
+                    vec![fullscreen_vertex_buffer.clone()],
+                    self.trace_set.clone(),
+                    push_constants,
+                    vec![],
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap();
+        }
+
+        let push_constants = ssr_composite_frag::ty::CompositePushConstants {
+            view: view.into(),
+            projection_scale: [proj.x.x, proj.y.y],
+            reflections_enabled: settings.enabled as u32,
+            max_blur_radius: settings.max_blur_radius,
+        };
+
+        builder
+            .begin_render_pass(
+                self.composite_framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.composite_pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                (self.composite_set.clone(), environment_set),
+                push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+/// Counts of the work done to draw the last frame, and how long frames take.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// The primitives drawn.
+    pub drawn_primitives: u32,
+    /// The primitives skipped as they were outside the view frustum.
+    pub culled_primitives: u32,
+    /// The primitives skipped as they were hidden behind others. Read back from the GPU, so it
+    /// lags a frame or more behind.
+    pub occluded_primitives: u32,
+    /// The time between the starts of consecutive frames, averaged over recent frames.
+    pub frame_time: Duration,
+}
@@ -0,0 +1,199 @@
+use crate::render::base::SCENE_FORMAT;
+use crate::render::shaders::*;
+use crate::vulkan::Pipeline;
+
+use cgmath::{Matrix4, Vector3};
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer, SubpassContents};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::ClearValue;
+use vulkano::image::view::ImageView;
+use vulkano::image::{AttachmentImage, ImageUsage, ImageViewAbstract};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::render_pass::{Framebuffer, FramebufferAbstract, RenderPass};
+use vulkano::sampler::Sampler;
+
+use std::sync::Arc;
+
+/// Resolves the jittered scene into an accumulated history, which is presented in its place.
+///
+/// Two history images are used, swapping each frame: one is read as the previous frame's result,
+/// while the other is written.
+pub struct TemporalAntiAliasing {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    framebuffers: [Arc<dyn FramebufferAbstract + Send + Sync>; 2],
+    resolve_sets: [Arc<dyn DescriptorSet + Send + Sync>; 2],
+    present_sets: [Arc<dyn DescriptorSet + Send + Sync>; 2],
+    history_images: [Arc<AttachmentImage>; 2],
+    dimensions: [u32; 2],
+    // The index of the history image most recently written to.
+    current: usize,
+    frame: u32,
+    history_valid: bool,
+}
+
+impl TemporalAntiAliasing {
+    /// The fraction of the history kept each frame.
+    pub const FEEDBACK: f32 = 0.9;
+
+    /// The number of jitter offsets cycled through before repeating.
+    pub const JITTER_SAMPLES: u32 = 8;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: Arc<Device>,
+        shaders: &Shaders,
+        dimensions: [u32; 2],
+        post_render_pass: Arc<RenderPass>,
+        scene_view: Arc<dyn ImageViewAbstract + Send + Sync>,
+        velocity_view: Arc<dyn ImageViewAbstract + Send + Sync>,
+        present_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        sampler: Arc<Sampler>,
+    ) -> Option<Self> {
+        // The history is copied from when the presented image is read back.
+        let usage = ImageUsage {
+            sampled: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        let history_images = [
+            AttachmentImage::with_usage(device.clone(), dimensions, SCENE_FORMAT, usage).ok()?,
+            AttachmentImage::with_usage(device.clone(), dimensions, SCENE_FORMAT, usage).ok()?,
+        ];
+        let history_views = [
+            ImageView::new(history_images[0].clone()).unwrap(),
+            ImageView::new(history_images[1].clone()).unwrap(),
+        ];
+
+        let framebuffer = |index: usize| {
+            Arc::new(
+                Framebuffer::start(post_render_pass.clone())
+                    .add(history_views[index].clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+        };
+
+        let pipeline = Pipeline::Taa.create(device, dimensions, shaders, post_render_pass.clone());
+
+        // Writing to history image `index` reads the other one as the previous frame.
+        let resolve_set = |index: usize| {
+            let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
+            Arc::new(
+                PersistentDescriptorSet::start(layout.clone())
+                    .add_sampled_image(scene_view.clone(), sampler.clone())
+                    .unwrap()
+                    .add_sampled_image(velocity_view.clone(), sampler.clone())
+                    .unwrap()
+                    .add_sampled_image(history_views[1 - index].clone(), sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn DescriptorSet + Send + Sync>
+        };
+
+        let present_set = |index: usize| {
+            let layout = present_pipeline.layout().descriptor_set_layout(0).unwrap();
+            Arc::new(
+                PersistentDescriptorSet::start(layout.clone())
+                    .add_sampled_image(history_views[index].clone(), sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn DescriptorSet + Send + Sync>
+        };
+
+        Some(Self {
+            framebuffers: [framebuffer(0), framebuffer(1)],
+            resolve_sets: [resolve_set(0), resolve_set(1)],
+            present_sets: [present_set(0), present_set(1)],
+            history_images,
+            pipeline,
+            dimensions,
+            current: 0,
+            frame: 0,
+            history_valid: false,
+        })
+    }
+
+    /// The sub-pixel offset applied to this frame's projection, in pixels.
+    pub fn jitter(&self) -> [f32; 2] {
+        let index = self.frame % Self::JITTER_SAMPLES + 1;
+        [halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+    }
+
+    /// Offsets the projection by this frame's jitter.
+    pub fn jittered_projection(&self, proj: Matrix4<f32>) -> Matrix4<f32> {
+        let [x, y] = self.jitter();
+        let offset = Vector3::new(
+            2.0 * x / self.dimensions[0] as f32,
+            2.0 * y / self.dimensions[1] as f32,
+            0.0,
+        );
+
+        Matrix4::from_translation(offset) * proj
+    }
+
+    /// Records the pass blending the scene into the history, and moves on to the next frame.
+    pub fn resolve(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        fullscreen_vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+    ) {
+        let target = 1 - self.current;
+
+        let push_constants = taa_frag::ty::TaaPushConstants {
+            feedback: Self::FEEDBACK,
+            reset: !self.history_valid as u32,
+        };
+
+        builder
+            .begin_render_pass(
+                self.framebuffers[target].clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.pipeline.clone(),
+                &DynamicState::none(),
+                vec![fullscreen_vertex_buffer],
+                self.resolve_sets[target].clone(),
+                push_constants,
+                vec![],
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+
+        self.current = target;
+        self.frame = self.frame.wrapping_add(1);
+        self.history_valid = true;
+    }
+
+    /// The descriptor set for presenting the most recently resolved history image.
+    pub fn present_set(&self) -> Arc<dyn DescriptorSet + Send + Sync> {
+        self.present_sets[self.current].clone()
+    }
+
+    /// The most recently resolved history image.
+    pub fn presented_image(&self) -> Arc<AttachmentImage> {
+        self.history_images[self.current].clone()
+    }
+}
+
+/// The `index`th element of the Halton low-discrepancy sequence with the given base, in [0, 1).
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
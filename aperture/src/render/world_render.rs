@@ -1,23 +1,48 @@
 use crate::render::shaders::*;
 use crate::vulkan::{DescriptorSet, Pipeline};
+use crate::world::cube::Cube;
+use crate::world::World;
 
-use aperture_common::{Transform, VPosNormTex};
-use aperture_mesh::{Material, Mesh, Texture};
+use aperture_common::{InstanceTransform, Transform, VPosNormTex};
+use aperture_mesh::{Aabb, BoundingSphere, ImageFormat, Material, Mesh, Primitive, Texture};
 
-use cgmath::Matrix4;
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::buffer::{BufferAccess, BufferSlice, BufferUsage, CpuBufferPool, DeviceLocalBuffer, ImmutableBuffer};
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::device::{Device, Queue};
 use vulkano::format::Format;
 use vulkano::image::view::ImageView;
 use vulkano::image::{ImageDimensions, ImmutableImage, MipmapsCount};
 use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::sync::{self, GpuFuture};
 use vulkano::{image::ImageViewAbstract, sampler::Sampler};
 
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+use super::affine_rows;
 use super::environment::Environment;
+use super::frames::FrameRing;
+
+/// The textures sampled in place of those a material doesn't have, and their single pixel. The
+/// metallic-roughness texture is fully rough and dielectric, and the emissive texture leaves the
+/// emissive factor as it is.
+const DUMMY_TEXTURES: [(&str, [u8; 4]); 5] = [
+    ("DUMMY_COLOR", [255, 255, 255, 255]),
+    ("DUMMY_NORMAL", [128, 128, 255, 255]),
+    ("DUMMY_METAL_ROUGH", [0, 255, 0, 255]),
+    ("DUMMY_AO", [255, 255, 255, 255]),
+    ("DUMMY_EMISSIVE", [255, 255, 255, 255]),
+];
+
+/// The vertices of a primitive, in a buffer shared with the other primitives loaded with it.
+pub type VertexSlice = BufferSlice<[VPosNormTex], Arc<ImmutableBuffer<[VPosNormTex]>>>;
+
+/// The indices of a primitive, in a buffer shared with the other primitives loaded with it.
+pub type IndexSlice = BufferSlice<[u32], Arc<ImmutableBuffer<[u32]>>>;
+
+/// Uploads submitted to the GPU, which work using the uploaded resources is submitted after.
+pub type Upload = Box<dyn GpuFuture + Send + Sync>;
 
 #[derive(Default)]
 pub struct WorldRender {
@@ -28,6 +53,9 @@ pub struct WorldRender {
 }
 
 impl WorldRender {
+    /// Uploads the meshes, materials and textures, returning the upload, which the first frame
+    /// drawing them must be submitted after.
+    #[allow(clippy::too_many_arguments)]
     pub fn update<'a>(
         &mut self,
         meshes: impl Iterator<Item = &'a Mesh>,
@@ -37,36 +65,108 @@ impl WorldRender {
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         device: Arc<Device>,
         queue: Arc<Queue>,
-    ) {
-        self.gen_samplers(textures, device.clone(), queue.clone());
-        self.gen_primitive_info(meshes, device.clone());
-        self.gen_material_info(materials, pipeline_type, pipeline.clone(), device.clone());
+    ) -> Upload {
+        // Anything loaded before is replaced, as names may be reused with different contents.
+        self.primitive_info.clear();
+        self.material_info.clear();
+        self.image_samplers.clear();
+
+        let textures_upload = self.gen_samplers(textures, device.clone(), queue.clone());
+        let geometry_upload = self.gen_primitive_info(meshes, device.clone(), queue);
+        self.gen_material_info(materials, pipeline_type, pipeline.clone(), device);
+
+        // Submit the uploads now, so they run while the CPU carries on.
+        let upload = textures_upload.join(geometry_upload);
+        if let Err(e) = upload.flush() {
+            println!("failed to flush uploads: {:?}", e);
+        }
+
+        Box::new(upload)
     }
 
     pub fn update_environment(
         &mut self,
+        cube: Option<&Cube>,
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         shaders: &Shaders,
         device: Arc<Device>,
         queue: Arc<Queue>,
     ) {
-        self.environment = Some(Environment::new(
-            pipeline, 
-            shaders,
-            device, 
-            queue,
-        ));
+        self.environment = cube.map(|cube| Environment::new(cube, pipeline, shaders, device, queue));
     }
 
+    /// Uploads the geometry of the meshes to device-local memory. The vertices and indices of all
+    /// of the primitives are packed into one buffer each, which the primitives reference ranges of.
     fn gen_primitive_info<'a>(
         &mut self,
         meshes: impl Iterator<Item = &'a Mesh>,
         device: Arc<Device>,
-    ) {
-        for mesh in meshes {
-            self.primitive_info
-                .extend(PrimitiveInfo::generate_from_mesh(&mesh, device.clone()));
+        queue: Arc<Queue>,
+    ) -> Upload {
+        // Meshes without instances aren't drawn.
+        let primitives = meshes
+            .filter(|mesh| !mesh.instances.is_empty())
+            .flat_map(|mesh| mesh.primitives.iter().map(move |p| (p, &mesh.instances)))
+            .collect::<Vec<_>>();
+
+        let vertex_count = primitives.iter().map(|(p, _)| p.vertices.len()).sum::<usize>();
+        let index_count = primitives.iter().map(|(p, _)| p.indices.len()).sum::<usize>();
+        if vertex_count == 0 {
+            return Box::new(sync::now(device));
         }
+
+        // Each buffer is filled through a staging buffer, copied from by the returned upload.
+        let (vertex_buffer, vertex_upload) = ImmutableBuffer::from_iter(
+            primitives.iter().flat_map(|(p, _)| p.vertices.iter().cloned()).collect::<Vec<_>>().into_iter(),
+            BufferUsage::vertex_buffer(),
+            queue.clone(),
+        )
+        .unwrap();
+
+        let mut upload: Upload = Box::new(vertex_upload);
+
+        let index_buffer = if index_count > 0 {
+            let (index_buffer, index_upload) = ImmutableBuffer::from_iter(
+                primitives.iter().flat_map(|(p, _)| p.indices.iter().cloned()).collect::<Vec<_>>().into_iter(),
+                BufferUsage::index_buffer(),
+                queue,
+            )
+            .unwrap();
+            upload = Box::new(upload.join(index_upload));
+            Some(index_buffer)
+        } else {
+            None
+        };
+
+        let mut vertex_offset = 0;
+        let mut index_offset = 0;
+        for (p, instances) in primitives {
+            let vertices = vertex_buffer
+                .clone()
+                .into_buffer_slice()
+                .slice(vertex_offset..vertex_offset + p.vertices.len())
+                .unwrap();
+            vertex_offset += p.vertices.len();
+
+            // The indices stay relative to the primitive's vertices, as its vertex buffer is bound
+            // at the start of its range.
+            let indices = match &index_buffer {
+                Some(index_buffer) if !p.indices.is_empty() => {
+                    let indices = index_buffer
+                        .clone()
+                        .into_buffer_slice()
+                        .slice(index_offset..index_offset + p.indices.len())
+                        .unwrap();
+                    index_offset += p.indices.len();
+                    Some(Arc::new(indices))
+                }
+                _ => None,
+            };
+
+            self.primitive_info.push(PrimitiveInfo::new(p, instances, Arc::new(vertices), indices));
+        }
+
+        upload
     }
 
     fn gen_material_info<'a>(
@@ -81,7 +181,7 @@ impl WorldRender {
                 self.material_info.insert(
                     material.name.clone(),
                     MaterialInfo::new(
-                        &material,
+                        material,
                         &self.image_samplers,
                         pipeline_type,
                         pipeline.clone(),
@@ -97,29 +197,63 @@ impl WorldRender {
         textures: impl Iterator<Item = &'a Texture<u8>>,
         device: Arc<Device>,
         queue: Arc<Queue>,
-    ) {
+    ) -> Upload {
+        let mut upload: Upload = Box::new(sync::now(device.clone()));
+
+        // Materials without a texture sample these instead.
+        for (name, pixel) in DUMMY_TEXTURES {
+            let texture = Texture {
+                name: name.to_string(),
+                format: ImageFormat::R8G8B8A8,
+                pixels: pixel.to_vec(),
+                width: 1,
+                height: 1,
+            };
+            let (data, texture_upload) = ImageData::new(&texture, Format::R8G8B8A8Unorm, device.clone(), queue.clone());
+            self.image_samplers.insert(texture.name, data);
+            upload = Box::new(upload.join(texture_upload));
+        }
+
         for texture in textures {
             if !self.image_samplers.contains_key(&texture.name) {
-                self.image_samplers.insert(
-                    texture.name.clone(),
-                    ImageData::new(
-                        &texture, 
-                        // FIXME
-                        Format::R8G8B8A8Unorm,
-                        device.clone(),
-                        queue.clone(),
-                    ),
+                let (data, texture_upload) = ImageData::new(
+                    texture,
+                    // FIXME
+                    Format::R8G8B8A8Unorm,
+                    device.clone(),
+                    queue.clone(),
                 );
+                self.image_samplers.insert(texture.name.clone(), data);
+                upload = Box::new(upload.join(texture_upload));
             }
         }
+
+        upload
     }
 }
 
+/// A primitive of a mesh, drawn once for each of the mesh's instances in a single draw.
 pub struct PrimitiveInfo {
-    pub vertex_buffer: Arc<CpuAccessibleBuffer<[VPosNormTex]>>,
-    pub index_buffer: Option<Arc<CpuAccessibleBuffer<[u32]>>>,
+    pub vertex_buffer: Arc<VertexSlice>,
+    pub index_buffer: Option<Arc<IndexSlice>>,
+    /// The transform of the primitive within its mesh, applied before the instance transforms.
     pub transform: Arc<Mutex<Transform>>,
+    /// The transforms of the mesh's instances, shared with the other primitives of the mesh.
+    pub instances: Vec<Arc<Mutex<Transform>>>,
+    /// The per-instance transforms, uploaded each frame by `update_instance_buffer`.
+    pub instance_buffer: Option<Arc<dyn BufferAccess + Send + Sync>>,
     pub material_name: Option<String>,
+    /// The transforms the primitive and its instances were drawn with last frame, used to write
+    /// motion vectors.
+    pub previous_transform: Matrix4<f32>,
+    pub previous_instance_transforms: Vec<Matrix4<f32>>,
+    /// The bounds of the primitive in model space.
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    /// The bounds of every instance of the primitive in world space, updated from the transforms
+    /// each frame.
+    pub world_aabb: Aabb,
+    pub world_bounding_sphere: BoundingSphere,
 }
 
 impl PrimitiveInfo {
@@ -127,6 +261,10 @@ impl PrimitiveInfo {
         self.index_buffer.is_some()
     }
 
+    pub fn instance_count(&self) -> usize {
+        self.instances.len()
+    }
+
     pub fn composed_transform(&self) -> Matrix4<f32> {
         self.transform
             .lock()
@@ -135,45 +273,101 @@ impl PrimitiveInfo {
             .compose()
     }
 
-    pub fn generate_from_mesh(mesh: &Mesh, device: Arc<Device>) -> Vec<PrimitiveInfo> {
-        mesh.primitives
+    pub fn composed_instance_transforms(&self) -> Vec<Matrix4<f32>> {
+        self.instances
             .iter()
-            .map(|p| {
-                let vertex_buffer = CpuAccessibleBuffer::from_iter(
-                    device.clone(),
-                    BufferUsage::vertex_buffer(),
-                    false,
-                    p.vertices.iter().cloned(),
-                )
-                .unwrap();
+            .map(|instance| instance.lock().expect("poisoned_lock").compose())
+            .collect()
+    }
 
-                let index_buffer = if !p.indices.is_empty() {
-                    Some(
-                        CpuAccessibleBuffer::from_iter(
-                            device.clone(),
-                            BufferUsage::index_buffer(),
-                            false,
-                            p.indices.iter().cloned(),
-                        )
-                        .unwrap(),
-                    )
-                } else {
-                    None
-                };
-
-                PrimitiveInfo {
-                    vertex_buffer,
-                    index_buffer,
-                    transform: p.transform.clone(),
-                    material_name: p.material_name.clone(),
+    /// Transforms the model space bounds into world space with the current transforms.
+    pub fn update_world_bounds(&mut self) {
+        let transform = self.composed_transform();
+        let world_bounds = self
+            .composed_instance_transforms()
+            .iter()
+            .map(|instance| {
+                let transform = instance * transform;
+                (self.aabb.transformed(&transform), self.bounding_sphere.transformed(&transform))
+            })
+            .reduce(|(aabb, sphere), (instance_aabb, instance_sphere)| {
+                (aabb.union(&instance_aabb), sphere.union(&instance_sphere))
+            });
+
+        if let Some((world_aabb, world_bounding_sphere)) = world_bounds {
+            self.world_aabb = world_aabb;
+            self.world_bounding_sphere = world_bounding_sphere;
+        }
+    }
+
+    /// Uploads the current and previous transforms of the instances, to be drawn with this frame.
+    pub fn update_instance_buffer(&mut self, pool: &CpuBufferPool<InstanceTransform>) {
+        let instances = self
+            .composed_instance_transforms()
+            .into_iter()
+            .zip(&self.previous_instance_transforms)
+            .map(|(transform, previous_transform)| {
+                let [model_0, model_1, model_2] = affine_rows(transform);
+                let [prev_model_0, prev_model_1, prev_model_2] = affine_rows(*previous_transform);
+
+                InstanceTransform {
+                    instance_model_0: model_0,
+                    instance_model_1: model_1,
+                    instance_model_2: model_2,
+                    instance_prev_model_0: prev_model_0,
+                    instance_prev_model_1: prev_model_1,
+                    instance_prev_model_2: prev_model_2,
                 }
             })
-            .collect()
+            .collect::<Vec<_>>();
+
+        self.instance_buffer = Some(Arc::new(pool.chunk(instances).unwrap()));
+    }
+
+    /// Keeps the transforms drawn with this frame, to write motion vectors against the next.
+    pub fn save_previous_transforms(&mut self) {
+        self.previous_transform = self.composed_transform();
+        self.previous_instance_transforms = self.composed_instance_transforms();
+    }
+
+    /// The material the primitive is drawn with.
+    pub fn material<'a>(&self, world: &'a World) -> &'a Material {
+        match &self.material_name {
+            Some(name) => &world.materials[name.as_str()],
+            None => &world.default_material,
+        }
+    }
+
+    pub fn new(
+        primitive: &Primitive,
+        instances: &[Arc<Mutex<Transform>>],
+        vertex_buffer: Arc<VertexSlice>,
+        index_buffer: Option<Arc<IndexSlice>>,
+    ) -> Self {
+        let mut primitive_info = PrimitiveInfo {
+            vertex_buffer,
+            index_buffer,
+            transform: primitive.transform.clone(),
+            instances: instances.to_vec(),
+            instance_buffer: None,
+            material_name: primitive.material_name.clone(),
+            previous_transform: Matrix4::identity(),
+            previous_instance_transforms: vec![],
+            aabb: primitive.aabb,
+            bounding_sphere: primitive.bounding_sphere,
+            world_aabb: primitive.aabb,
+            world_bounding_sphere: primitive.bounding_sphere,
+        };
+
+        primitive_info.save_previous_transforms();
+        primitive_info.update_world_bounds();
+        primitive_info
     }
 }
 
 pub struct MaterialInfo {
-    pub descriptor_set: Arc<DescriptorSet>,
+    // A set for each frame in flight, each with its own uniform buffers.
+    descriptor_sets: FrameRing<Arc<DescriptorSet>>,
 }
 
 impl MaterialInfo {
@@ -184,14 +378,34 @@ impl MaterialInfo {
         pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
         device: Arc<Device>,
     ) -> Self {
+        Self {
+            descriptor_sets: FrameRing::new(|_| {
+                Arc::new(Self::create_set(material, image_samplers, pipeline_type, pipeline.clone(), device.clone()))
+            }),
+        }
+    }
+
+    /// The set the material is drawn with in the frame slot `frame`.
+    pub fn descriptor_set(&self, frame: usize) -> &Arc<DescriptorSet> {
+        self.descriptor_sets.get(frame)
+    }
+
+    fn create_set(
+        material: &Material,
+        image_samplers: &HashMap<String, ImageData>,
+        pipeline_type: Pipeline,
+        pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        device: Arc<Device>,
+    ) -> DescriptorSet {
         let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
 
         let dummy_color = "DUMMY_COLOR".to_string();
         let dummy_normal = "DUMMY_NORMAL".to_string();
         let dummy_metal_rough = "DUMMY_METAL_ROUGH".to_string();
         let dummy_ao = "DUMMY_AO".to_string();
+        let dummy_emissive = "DUMMY_EMISSIVE".to_string();
 
-        let set = match pipeline_type {
+        match pipeline_type {
             Pipeline::Shaded => {
                 let color_data = image_samplers[material
                     .textures
@@ -217,6 +431,13 @@ impl MaterialInfo {
                 let ao_data = image_samplers
                     [material.textures.ao.as_ref().unwrap_or(&dummy_ao).as_str()]
                 .clone();
+                let emissive_data = image_samplers[material
+                    .textures
+                    .emissive
+                    .as_ref()
+                    .unwrap_or(&dummy_emissive)
+                    .as_str()]
+                .clone();
 
                 let vertex_uniform_buffer = DeviceLocalBuffer::<vert::ty::Data>::new(
                     device.clone(),
@@ -245,6 +466,8 @@ impl MaterialInfo {
                     .unwrap()
                     .add_buffer(fragment_uniform_buffer.clone())
                     .unwrap()
+                    .add_sampled_image(emissive_data.view, emissive_data.sampler)
+                    .unwrap()
                     .build()
                     .unwrap();
 
@@ -254,10 +477,6 @@ impl MaterialInfo {
             }
             // TODO probably need to re-work this.
             _ => unimplemented!()
-        };
-
-        Self {
-            descriptor_set: Arc::new(set),
         }
     }
 }
@@ -269,13 +488,14 @@ pub struct ImageData {
 }
 
 impl ImageData {
+    /// Creates the image of the texture, returning the upload of its pixels.
     fn new(
         texture: &Texture<u8>,
         format: Format,
         device: Arc<Device>,
         queue: Arc<Queue>,
-    ) -> Self {
-        let (image, _) = ImmutableImage::from_iter(
+    ) -> (Self, Upload) {
+        let (image, upload) = ImmutableImage::from_iter(
             texture.pixels.iter().cloned(),
             ImageDimensions::Dim2d {
                 width: texture.width,
@@ -292,9 +512,11 @@ impl ImageData {
 
         let sampler = Sampler::simple_repeat_linear_no_mipmap(device);
 
-        Self {
+        let data = Self {
             view: Arc::new(view),
             sampler,
-        }
+        };
+
+        (data, Box::new(upload))
     }
 }
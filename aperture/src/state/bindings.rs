@@ -0,0 +1,398 @@
+use crate::state::InputState;
+
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The default bindings, in the format of a bindings file.
+const DEFAULT_BINDINGS: &str = include_str!("../../../data/bindings.cfg");
+
+/// Something the viewer does in response to input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Orbits the camera around the point it looks at by moving the mouse, while held.
+    Orbit,
+    /// Moves the camera and the point it looks at with the mouse, while held.
+    Pan,
+    /// Turns the camera in place by moving the mouse, while held in the fly mode.
+    Look,
+    /// Moves the camera forwards while held, in the fly mode.
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    /// Speeds up the fly mode while held.
+    MoveFaster,
+    /// Slows down the fly mode while held.
+    MoveSlower,
+    ToggleCameraMode,
+    FocusAtCursor,
+    ResetCamera,
+    ToggleWireframe,
+    OpenAperture,
+    CloseAperture,
+    Screenshot,
+    HighResolutionScreenshot,
+    CycleAntiAliasing,
+    ToggleAmbientOcclusion,
+    ToggleDepthOfField,
+    CycleBokeh,
+    ToggleMotionBlur,
+    ToggleReflections,
+    CycleRenderPath,
+    CycleTransparency,
+    PrintStats,
+    ToggleOcclusionCulling,
+    ToggleGpuDriven,
+}
+
+impl Action {
+    pub const ALL: [Self; 30] = [
+        Self::Orbit,
+        Self::Pan,
+        Self::Look,
+        Self::MoveForward,
+        Self::MoveBack,
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::MoveUp,
+        Self::MoveDown,
+        Self::MoveFaster,
+        Self::MoveSlower,
+        Self::ToggleCameraMode,
+        Self::FocusAtCursor,
+        Self::ResetCamera,
+        Self::ToggleWireframe,
+        Self::OpenAperture,
+        Self::CloseAperture,
+        Self::Screenshot,
+        Self::HighResolutionScreenshot,
+        Self::CycleAntiAliasing,
+        Self::ToggleAmbientOcclusion,
+        Self::ToggleDepthOfField,
+        Self::CycleBokeh,
+        Self::ToggleMotionBlur,
+        Self::ToggleReflections,
+        Self::CycleRenderPath,
+        Self::CycleTransparency,
+        Self::PrintStats,
+        Self::ToggleOcclusionCulling,
+        Self::ToggleGpuDriven,
+    ];
+
+    /// The name of the action in bindings files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Orbit => "orbit",
+            Self::Pan => "pan",
+            Self::Look => "look",
+            Self::MoveForward => "move_forward",
+            Self::MoveBack => "move_back",
+            Self::MoveLeft => "move_left",
+            Self::MoveRight => "move_right",
+            Self::MoveUp => "move_up",
+            Self::MoveDown => "move_down",
+            Self::MoveFaster => "move_faster",
+            Self::MoveSlower => "move_slower",
+            Self::ToggleCameraMode => "toggle_camera_mode",
+            Self::FocusAtCursor => "focus_at_cursor",
+            Self::ResetCamera => "reset_camera",
+            Self::ToggleWireframe => "toggle_wireframe",
+            Self::OpenAperture => "open_aperture",
+            Self::CloseAperture => "close_aperture",
+            Self::Screenshot => "screenshot",
+            Self::HighResolutionScreenshot => "high_resolution_screenshot",
+            Self::CycleAntiAliasing => "cycle_anti_aliasing",
+            Self::ToggleAmbientOcclusion => "toggle_ambient_occlusion",
+            Self::ToggleDepthOfField => "toggle_depth_of_field",
+            Self::CycleBokeh => "cycle_bokeh",
+            Self::ToggleMotionBlur => "toggle_motion_blur",
+            Self::ToggleReflections => "toggle_reflections",
+            Self::CycleRenderPath => "cycle_render_path",
+            Self::CycleTransparency => "cycle_transparency",
+            Self::PrintStats => "print_stats",
+            Self::ToggleOcclusionCulling => "toggle_occlusion_culling",
+            Self::ToggleGpuDriven => "toggle_gpu_driven",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+/// A key or mouse button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// An input, and the modifier keys held with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub input: Input,
+    pub modifiers: ModifiersState,
+}
+
+impl Binding {
+    /// Parses a binding such as `Shift+F1` or `MouseLeft`.
+    fn parse(binding: &str) -> Option<Self> {
+        let mut parts = binding.split('+').map(str::trim).collect::<Vec<_>>();
+        let input = parse_input(parts.pop()?)?;
+
+        let mut modifiers = ModifiersState::empty();
+        for part in parts {
+            modifiers |= match part.to_ascii_lowercase().as_str() {
+                "shift" => ModifiersState::SHIFT,
+                "ctrl" => ModifiersState::CTRL,
+                "alt" => ModifiersState::ALT,
+                "logo" => ModifiersState::LOGO,
+                _ => return None,
+            };
+        }
+
+        Some(Self { input, modifiers })
+    }
+}
+
+/// The inputs bound to each action. The defaults are those of `data/bindings.cfg`.
+pub struct Bindings {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Self {
+            bindings: HashMap::new(),
+        };
+        bindings.apply(DEFAULT_BINDINGS).expect("invalid default bindings");
+
+        bindings
+    }
+}
+
+impl Bindings {
+    /// Loads a bindings file, replacing the default bindings of the actions it lists.
+    pub fn load(path: &Path) -> Result<Self, BindingsError> {
+        let text = fs::read_to_string(path).map_err(BindingsError::Io)?;
+
+        let mut bindings = Self::default();
+        bindings.apply(&text)?;
+
+        Ok(bindings)
+    }
+
+    /// The actions bound to pressing the input, with exactly the modifiers held.
+    pub fn triggered(&self, input: Input, modifiers: ModifiersState) -> impl Iterator<Item = Action> + '_ {
+        let binding = Binding { input, modifiers };
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(&action, _)| action)
+    }
+
+    /// Whether an input bound to the action is held down, with at least its modifiers.
+    pub fn is_held(&self, action: Action, input_state: &InputState) -> bool {
+        self.bindings.get(&action).is_some_and(|bindings| {
+            bindings
+                .iter()
+                .any(|binding| input_state.is_down(binding.input) && input_state.modifiers.contains(binding.modifiers))
+        })
+    }
+
+    /// Binds the actions listed in the bindings file contents, replacing their bindings.
+    fn apply(&mut self, text: &str) -> Result<(), BindingsError> {
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, bindings) = line.split_once('=').ok_or(BindingsError::Syntax(line_number))?;
+            let action = Action::from_name(name.trim())
+                .ok_or_else(|| BindingsError::UnknownAction(line_number, name.trim().to_string()))?;
+
+            let bindings = bindings
+                .split(',')
+                .map(str::trim)
+                .filter(|binding| !binding.is_empty())
+                .map(|binding| {
+                    Binding::parse(binding).ok_or_else(|| BindingsError::InvalidBinding(line_number, binding.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            self.bindings.insert(action, bindings);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(io::Error),
+    /// The line isn't of the form `action = binding, ...`.
+    Syntax(usize),
+    UnknownAction(usize, String),
+    InvalidBinding(usize, String),
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Syntax(line) => write!(f, "Line {}: expected `action = binding, ...`", line),
+            Self::UnknownAction(line, name) => write!(f, "Line {}: unknown action \"{}\"", line, name),
+            Self::InvalidBinding(line, binding) => write!(f, "Line {}: invalid binding \"{}\"", line, binding),
+        }
+    }
+}
+
+impl Error for BindingsError {}
+
+fn parse_input(name: &str) -> Option<Input> {
+    let mouse = match name.to_ascii_lowercase().as_str() {
+        "mouseleft" => Some(MouseButton::Left),
+        "mouseright" => Some(MouseButton::Right),
+        "mousemiddle" => Some(MouseButton::Middle),
+        other => other.strip_prefix("mouse").and_then(|button| button.parse().ok()).map(MouseButton::Other),
+    };
+    if let Some(button) = mouse {
+        return Some(Input::Mouse(button));
+    }
+
+    KEYS.iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+        .map(|&key| Input::Key(key))
+}
+
+/// The keys that can be bound, by the name of their variant.
+const KEYS: &[VirtualKeyCode] = {
+    use VirtualKeyCode::*;
+    &[
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24,
+        Escape, Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+        Left, Up, Right, Down, Back, Return, Space, Tab,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        NumpadAdd, NumpadSubtract, NumpadMultiply, NumpadDivide, NumpadDecimal, NumpadEnter,
+        Apostrophe, Backslash, Comma, Equals, Grave, LBracket, Minus, Period, RBracket, Semicolon, Slash,
+        LShift, RShift, LControl, RControl, LAlt, RAlt,
+    ]
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: VirtualKeyCode) -> Input {
+        Input::Key(key)
+    }
+
+    fn triggered(bindings: &Bindings, input: Input, modifiers: ModifiersState) -> Vec<Action> {
+        bindings.triggered(input, modifiers).collect()
+    }
+
+    #[test]
+    fn every_action_has_a_default_binding() {
+        let bindings = Bindings::default();
+
+        for action in Action::ALL {
+            assert!(bindings.bindings.contains_key(&action), "{} isn't in data/bindings.cfg", action.name());
+            assert_eq!(Action::from_name(action.name()), Some(action));
+        }
+    }
+
+    #[test]
+    fn bindings_are_parsed_with_their_modifiers() {
+        assert_eq!(
+            Binding::parse("Ctrl+Shift+S"),
+            Some(Binding {
+                input: key(VirtualKeyCode::S),
+                modifiers: ModifiersState::CTRL | ModifiersState::SHIFT,
+            })
+        );
+        assert_eq!(
+            Binding::parse(" alt + logo + f1 "),
+            Some(Binding {
+                input: key(VirtualKeyCode::F1),
+                modifiers: ModifiersState::ALT | ModifiersState::LOGO,
+            })
+        );
+        assert_eq!(Binding::parse("Hyper+S"), None);
+        assert_eq!(Binding::parse("Shift+"), None);
+    }
+
+    #[test]
+    fn mouse_buttons_are_parsed() {
+        assert_eq!(parse_input("MouseLeft"), Some(Input::Mouse(MouseButton::Left)));
+        assert_eq!(parse_input("mouseright"), Some(Input::Mouse(MouseButton::Right)));
+        assert_eq!(parse_input("MouseMiddle"), Some(Input::Mouse(MouseButton::Middle)));
+        assert_eq!(parse_input("Mouse4"), Some(Input::Mouse(MouseButton::Other(4))));
+        assert_eq!(parse_input("MouseSide"), None);
+    }
+
+    #[test]
+    fn unknown_actions_and_keys_are_rejected_with_their_line() {
+        let mut bindings = Bindings::default();
+
+        assert!(matches!(
+            bindings.apply("screenshot = F1\nfly_away = F2"),
+            Err(BindingsError::UnknownAction(2, name)) if name == "fly_away"
+        ));
+        assert!(matches!(
+            bindings.apply("\nscreenshot = F1, Hyper"),
+            Err(BindingsError::InvalidBinding(2, binding)) if binding == "Hyper"
+        ));
+        assert!(matches!(bindings.apply("screenshot F1"), Err(BindingsError::Syntax(1))));
+    }
+
+    #[test]
+    fn listed_actions_replace_their_default_bindings() {
+        let mut bindings = Bindings::default();
+        bindings.apply("screenshot = F5\nscreenshot = Ctrl+P, F13\nreset_camera =").unwrap();
+
+        let none = ModifiersState::empty();
+        assert_eq!(triggered(&bindings, key(VirtualKeyCode::F1), none), []);
+        assert_eq!(triggered(&bindings, key(VirtualKeyCode::F13), none), [Action::Screenshot]);
+        assert_eq!(triggered(&bindings, key(VirtualKeyCode::P), ModifiersState::CTRL), [Action::Screenshot]);
+        assert_eq!(triggered(&bindings, key(VirtualKeyCode::R), none), []);
+
+        // The later line replaces the earlier, and the others keep their defaults.
+        assert_eq!(triggered(&bindings, key(VirtualKeyCode::F5), none), [Action::CycleBokeh]);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let mut bindings = Bindings::default();
+        bindings
+            .apply("# A comment = not a binding\n\n   \nscreenshot = F13 # after the binding\n")
+            .unwrap();
+
+        assert_eq!(triggered(&bindings, key(VirtualKeyCode::F13), ModifiersState::empty()), [Action::Screenshot]);
+    }
+
+    #[test]
+    fn held_bindings_need_at_least_their_modifiers() {
+        let mut bindings = Bindings::default();
+        bindings.apply("move_faster = Shift+W").unwrap();
+
+        let mut input_state = InputState::default();
+        input_state.set_down(key(VirtualKeyCode::W), true);
+        assert!(!bindings.is_held(Action::MoveFaster, &input_state));
+
+        input_state.modifiers = ModifiersState::SHIFT | ModifiersState::CTRL;
+        assert!(bindings.is_held(Action::MoveFaster, &input_state));
+    }
+}
@@ -1,9 +1,22 @@
+use crate::state::Input;
+
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+use std::collections::HashSet;
+
 #[derive(Default)]
 pub struct InputState {
-    pub mouse_left_down: bool,
-    pub mouse_right_down: bool,
+    /// The keys held down.
+    pub keys_down: HashSet<VirtualKeyCode>,
+    /// The mouse buttons held down.
+    pub buttons_down: HashSet<MouseButton>,
+    /// The cursor position within the window, in pixels.
+    pub cursor_position: Option<[f32; 2]>,
+    /// How far the mouse moved this frame, and the wheel turned.
     pub position_delta: Option<[f32; 2]>,
     pub wheel_delta: Option<f32>,
+    /// The modifier keys held down.
+    pub modifiers: ModifiersState,
 }
 
 impl InputState {
@@ -11,4 +24,45 @@ impl InputState {
         self.position_delta = None;
         self.wheel_delta = None;
     }
+
+    /// Adds a motion of the mouse to those this frame, as several are usually reported per frame.
+    pub fn add_position_delta(&mut self, [x, y]: [f32; 2]) {
+        let [total_x, total_y] = self.position_delta.unwrap_or([0.0, 0.0]);
+        self.position_delta = Some([total_x + x, total_y + y]);
+    }
+
+    /// Adds a turn of the mouse wheel to those this frame.
+    pub fn add_wheel_delta(&mut self, delta: f32) {
+        self.wheel_delta = Some(self.wheel_delta.unwrap_or(0.0) + delta);
+    }
+
+    pub fn is_down(&self, input: Input) -> bool {
+        match input {
+            Input::Key(key) => self.keys_down.contains(&key),
+            Input::Mouse(button) => self.buttons_down.contains(&button),
+        }
+    }
+
+    /// Records the input being pressed or released, returning whether it was pressed when it
+    /// wasn't already held, as held keys repeat.
+    pub fn set_down(&mut self, input: Input, down: bool) -> bool {
+        match (input, down) {
+            (Input::Key(key), true) => self.keys_down.insert(key),
+            (Input::Mouse(button), true) => self.buttons_down.insert(button),
+            (Input::Key(key), false) => {
+                self.keys_down.remove(&key);
+                false
+            }
+            (Input::Mouse(button), false) => {
+                self.buttons_down.remove(&button);
+                false
+            }
+        }
+    }
+
+    /// Releases every key and button, as their releases aren't seen while the window is unfocused.
+    pub fn release_all(&mut self) {
+        self.keys_down.clear();
+        self.buttons_down.clear();
+    }
 }
@@ -1,3 +1,5 @@
+mod bindings;
 mod input;
 
+pub use bindings::{Action, Bindings, BindingsError, Input};
 pub use input::InputState;
@@ -0,0 +1,230 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+/// The most triangles kept in a leaf.
+const LEAF_SIZE: usize = 4;
+
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+/// The nearest intersection of a ray with a triangle.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub t: f32,
+    pub triangle: usize,
+    /// The barycentric coordinates of the hit, weighting the second and third vertices.
+    pub barycentrics: [f32; 2],
+}
+
+/// A bounding volume hierarchy over triangles, split at the median of their centres along the
+/// longest axis.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // The triangles in the order the leaves reference them, with their original indices.
+    triangles: Vec<Triangle>,
+}
+
+struct Node {
+    min: [f32; 3],
+    max: [f32; 3],
+    // For a leaf, the first of its triangles, and otherwise the index of the second child. The
+    // first child follows its parent.
+    offset: u32,
+    // The number of triangles in a leaf, or zero for an interior node.
+    count: u32,
+}
+
+struct Triangle {
+    vertex: Point3<f32>,
+    edges: [Vector3<f32>; 2],
+    index: usize,
+}
+
+impl Bvh {
+    pub fn new(triangles: &[[Point3<f32>; 3]]) -> Self {
+        let mut order = (0..triangles.len()).collect::<Vec<_>>();
+        let centers = triangles
+            .iter()
+            .map(|[a, b, c]| Point3::new((a.x + b.x + c.x) / 3.0, (a.y + b.y + c.y) / 3.0, (a.z + b.z + c.z) / 3.0))
+            .collect::<Vec<_>>();
+
+        let mut nodes = Vec::with_capacity(2 * triangles.len() / LEAF_SIZE + 1);
+        if !triangles.is_empty() {
+            build(&mut nodes, triangles, &centers, &mut order, 0);
+        }
+
+        let triangles = order
+            .into_iter()
+            .map(|index| {
+                let [a, b, c] = triangles[index];
+                Triangle {
+                    vertex: a,
+                    edges: [b - a, c - a],
+                    index,
+                }
+            })
+            .collect();
+
+        Self { nodes, triangles }
+    }
+
+    /// Finds the nearest triangle the ray hits closer than `t_max`.
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = [1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z];
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+
+        let mut nearest: Option<Hit> = None;
+        let mut t_max = t_max;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.slab_test(origin, inverse_direction, t_max).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.offset as usize;
+                for triangle in &self.triangles[first..first + node.count as usize] {
+                    if let Some((t, barycentrics)) = triangle.intersect(ray, t_max) {
+                        t_max = t;
+                        nearest = Some(Hit {
+                            t,
+                            triangle: triangle.index,
+                            barycentrics,
+                        });
+                    }
+                }
+                continue;
+            }
+
+            // Visit the nearer child first, so the further is more likely to be culled.
+            let (first, second) = (index + 1, node.offset as usize);
+            let first_t = self.nodes[first].slab_test(origin, inverse_direction, t_max);
+            let second_t = self.nodes[second].slab_test(origin, inverse_direction, t_max);
+            match (first_t, second_t) {
+                (Some(first_t), Some(second_t)) if second_t < first_t => {
+                    stack.push(first);
+                    stack.push(second);
+                }
+                (Some(_), Some(_)) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                (Some(_), None) => stack.push(first),
+                (None, Some(_)) => stack.push(second),
+                (None, None) => {}
+            }
+        }
+
+        nearest
+    }
+}
+
+impl Node {
+    /// The distance the ray enters the box at, if it does before `t_max`.
+    fn slab_test(&self, origin: [f32; 3], inverse_direction: [f32; 3], t_max: f32) -> Option<f32> {
+        let mut t_near = 0.0f32;
+        let mut t_far = t_max;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+impl Triangle {
+    /// See Möller and Trumbore, "Fast, Minimum Storage Ray/Triangle Intersection".
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<(f32, [f32; 2])> {
+        let [edge1, edge2] = self.edges;
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let s = ray.origin - self.vertex;
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = ray.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inverse_determinant;
+        if t > 0.0 && t < t_max {
+            Some((t, [u, v]))
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds the node over the triangles in `order`, and its children, returning its index.
+fn build(
+    nodes: &mut Vec<Node>,
+    triangles: &[[Point3<f32>; 3]],
+    centers: &[Point3<f32>],
+    order: &mut [usize],
+    first: usize,
+) -> usize {
+    let (min, max) = bounds(order.iter().flat_map(|&index| triangles[index].iter().copied()));
+    let index = nodes.len();
+    nodes.push(Node {
+        min,
+        max,
+        offset: first as u32,
+        count: order.len() as u32,
+    });
+
+    if order.len() <= LEAF_SIZE {
+        return index;
+    }
+
+    let (center_min, center_max) = bounds(order.iter().map(|&index| centers[index]));
+    let extent = [center_max[0] - center_min[0], center_max[1] - center_min[1], center_max[2] - center_min[2]];
+    let axis = (0..3).fold(0, |longest, axis| if extent[axis] > extent[longest] { axis } else { longest });
+    if extent[axis] <= 0.0 {
+        return index;
+    }
+
+    let middle = order.len() / 2;
+    order.select_nth_unstable_by(middle, |&a, &b| centers[a][axis].total_cmp(&centers[b][axis]));
+
+    let (left, right) = order.split_at_mut(middle);
+    build(nodes, triangles, centers, left, first);
+    let second = build(nodes, triangles, centers, right, first + middle);
+
+    nodes[index].offset = second as u32;
+    nodes[index].count = 0;
+    index
+}
+
+fn bounds(points: impl Iterator<Item = Point3<f32>>) -> ([f32; 3], [f32; 3]) {
+    points.fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+        (
+            [min[0].min(p.x), min[1].min(p.y), min[2].min(p.z)],
+            [max[0].max(p.x), max[1].max(p.y), max[2].max(p.z)],
+        )
+    })
+}
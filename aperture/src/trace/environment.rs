@@ -0,0 +1,152 @@
+use crate::trace::sampling::{sample_cdf, Rng};
+
+use aperture_mesh::Texture;
+use cgmath::{InnerSpace, Vector3};
+
+use std::f32::consts::PI;
+
+/// The equirectangular environment map lighting the scene, mapped onto directions as the
+/// rasteriser's `offscreen_hdri.frag` maps it onto the skybox. Directions are sampled in
+/// proportion to the radiance of the environment.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f32>>,
+    // The cumulative distribution of each row, and of the rows themselves.
+    row_cdfs: Vec<Vec<f32>>,
+    marginal_cdf: Vec<f32>,
+    // The probability of sampling each pixel.
+    pixel_pdfs: Vec<f32>,
+}
+
+/// A direction towards the environment, and its radiance.
+pub struct EnvironmentSample {
+    pub direction: Vector3<f32>,
+    pub radiance: Vector3<f32>,
+    /// The density the direction was sampled with, per unit solid angle.
+    pub pdf: f32,
+}
+
+impl EnvironmentMap {
+    /// Creates the map from an RGBA texture of linear radiance.
+    pub fn new(texture: &Texture<f32>) -> Self {
+        let (width, height) = (texture.width as usize, texture.height as usize);
+        let pixels = texture
+            .pixels
+            .chunks_exact(4)
+            .map(|pixel| Vector3::new(pixel[0], pixel[1], pixel[2]))
+            .collect::<Vec<_>>();
+
+        // Rows nearer the poles cover less of the sphere, so are sampled less.
+        let weights = (0..height)
+            .map(|y| {
+                let (_, cos_latitude) = Self::latitude((y as f32 + 0.5) / height as f32);
+                (0..width)
+                    .map(|x| luminance(pixels[y * width + x]) * cos_latitude)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let row_totals = weights.iter().map(|row| row.iter().sum::<f32>()).collect::<Vec<_>>();
+        let total = row_totals.iter().sum::<f32>();
+
+        let row_cdfs = weights.iter().map(|row| cumulative(row)).collect();
+        let marginal_cdf = cumulative(&row_totals);
+        let pixel_pdfs = weights
+            .iter()
+            .flatten()
+            .map(|&weight| if total > 0.0 { weight / total } else { 0.0 })
+            .collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+            row_cdfs,
+            marginal_cdf,
+            pixel_pdfs,
+        }
+    }
+
+    /// The radiance arriving from the direction.
+    pub fn radiance(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        self.pixels[self.pixel_index(direction)]
+    }
+
+    /// The density `sample` chooses the direction with, per unit solid angle.
+    pub fn pdf(&self, direction: Vector3<f32>) -> f32 {
+        let direction = direction.normalize();
+        let cos_latitude = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if cos_latitude <= 0.0 {
+            return 0.0;
+        }
+
+        let pdf_uv = self.pixel_pdfs[self.pixel_index(direction)] * (self.width * self.height) as f32;
+        pdf_uv / (2.0 * PI * PI * cos_latitude)
+    }
+
+    /// Chooses a direction in proportion to the radiance from it.
+    pub fn sample(&self, rng: &mut Rng) -> Option<EnvironmentSample> {
+        let y = sample_cdf(&self.marginal_cdf, rng.next_f32());
+        let x = sample_cdf(&self.row_cdfs[y], rng.next_f32());
+        let [du, dv] = rng.next_2d();
+
+        let u = (x as f32 + du) / self.width as f32;
+        let v = (y as f32 + dv) / self.height as f32;
+        let (sin_latitude, cos_latitude) = Self::latitude(v);
+        if cos_latitude <= 0.0 {
+            return None;
+        }
+
+        let phi = (u - 0.5) * 2.0 * PI;
+        let direction = Vector3::new(cos_latitude * phi.cos(), sin_latitude, cos_latitude * phi.sin());
+        let pdf_uv = self.pixel_pdfs[y * self.width + x] * (self.width * self.height) as f32;
+        if pdf_uv <= 0.0 {
+            return None;
+        }
+
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.pixels[y * self.width + x],
+            pdf: pdf_uv / (2.0 * PI * PI * cos_latitude),
+        })
+    }
+
+    fn pixel_index(&self, direction: Vector3<f32>) -> usize {
+        let direction = direction.normalize();
+        let u = direction.z.atan2(direction.x) / (2.0 * PI) + 0.5;
+        let v = 0.5 - direction.y.clamp(-1.0, 1.0).asin() / PI;
+
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        y * self.width + x
+    }
+
+    /// The sine and cosine of the latitude at a vertical texture coordinate.
+    fn latitude(v: f32) -> (f32, f32) {
+        let latitude = (0.5 - v) * PI;
+        latitude.sin_cos()
+    }
+}
+
+pub fn luminance(color: Vector3<f32>) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// The cumulative sums of the values, normalised to end in one. All zeros become a uniform
+/// distribution.
+fn cumulative(values: &[f32]) -> Vec<f32> {
+    let total = values.iter().sum::<f32>();
+    if total <= 0.0 {
+        return (1..=values.len()).map(|i| i as f32 / values.len() as f32).collect();
+    }
+
+    let mut sum = 0.0;
+    values
+        .iter()
+        .map(|value| {
+            sum += value;
+            sum / total
+        })
+        .collect()
+}
@@ -0,0 +1,327 @@
+use crate::trace::environment::luminance;
+use crate::trace::sampling::{cosine_hemisphere, Rng};
+
+use aperture_mesh::{AlphaMode, Material, Texture};
+use cgmath::{ElementWise, InnerSpace, Vector2, Vector3, Vector4};
+
+use std::f32::consts::PI;
+
+/// The lowest roughness shaded, as lower values make the GGX distribution degenerate.
+const MIN_ROUGHNESS: f32 = 0.045;
+
+/// An RGBA texture, sampled bilinearly and repeated.
+pub struct TextureImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    // The linear value of each sRGB encoded byte.
+    srgb_table: [f32; 256],
+}
+
+impl TextureImage {
+    pub fn new(texture: &Texture<u8>) -> Self {
+        Self {
+            width: texture.width,
+            height: texture.height,
+            pixels: texture.pixels.clone(),
+            srgb_table: {
+                let mut table = [0.0; 256];
+                for (value, linear) in table.iter_mut().enumerate() {
+                    *linear = decode_srgb(value as f32 / 255.0);
+                }
+                table
+            },
+        }
+    }
+
+    /// Samples the texture, decoding the colour from sRGB if it's `srgb`. The alpha is linear.
+    pub fn sample(&self, uv: Vector2<f32>, srgb: bool) -> Vector4<f32> {
+        if self.width == 0 || self.height == 0 {
+            return Vector4::new(1.0, 1.0, 1.0, 1.0);
+        }
+
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |dx: i64, dy: i64| {
+            let x = (x0 as i64 + dx).rem_euclid(self.width as i64) as usize;
+            let y = (y0 as i64 + dy).rem_euclid(self.height as i64) as usize;
+            let offset = 4 * (y * self.width as usize + x);
+            let channel = |c: usize| {
+                let value = self.pixels[offset + c];
+                if srgb && c < 3 {
+                    self.srgb_table[value as usize]
+                } else {
+                    value as f32 / 255.0
+                }
+            };
+            Vector4::new(channel(0), channel(1), channel(2), channel(3))
+        };
+
+        let top = texel(0, 0) * (1.0 - fx) + texel(1, 0) * fx;
+        let bottom = texel(0, 1) * (1.0 - fx) + texel(1, 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// A material with its textures resolved to indices into the path tracer's textures.
+pub struct SurfaceMaterial {
+    pub base_color: Vector4<f32>,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub reflectance: f32,
+    pub emissive: Vector3<f32>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub base_color_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub metallic_roughness_texture: Option<usize>,
+    pub emissive_texture: Option<usize>,
+}
+
+impl SurfaceMaterial {
+    pub fn new(material: &Material, texture_index: impl Fn(&Option<String>) -> Option<usize>) -> Self {
+        Self {
+            base_color: material.base_color_factor,
+            metallic: material.metallic_factor,
+            roughness: material.roughness_factor,
+            reflectance: material.reflectance,
+            emissive: material.emissive_factor,
+            alpha_mode: material.alpha_mode,
+            alpha_cutoff: material.alpha_cutoff,
+            base_color_texture: texture_index(&material.textures.base_color),
+            normal_texture: texture_index(&material.textures.normal),
+            metallic_roughness_texture: texture_index(&material.textures.metallic_roughness),
+            emissive_texture: texture_index(&material.textures.emissive),
+        }
+    }
+
+    /// The light the surface emits at the texture coordinates. The texture is sRGB encoded, as in
+    /// glTF.
+    pub fn emission(&self, textures: &[TextureImage], uv: Vector2<f32>) -> Vector3<f32> {
+        match self.emissive_texture {
+            Some(texture) => self.emissive.mul_element_wise(textures[texture].sample(uv, true).truncate()),
+            None => self.emissive,
+        }
+    }
+
+    /// The base colour and alpha at the texture coordinates. The texture is sRGB encoded, as in
+    /// glTF.
+    pub fn base_color(&self, textures: &[TextureImage], uv: Vector2<f32>) -> Vector4<f32> {
+        match self.base_color_texture {
+            Some(texture) => self.base_color.mul_element_wise(textures[texture].sample(uv, true)),
+            None => self.base_color,
+        }
+    }
+
+    /// How much light the surface blocks at the texture coordinates, from zero to one.
+    pub fn opacity(&self, textures: &[TextureImage], uv: Vector2<f32>) -> f32 {
+        match self.alpha_mode {
+            AlphaMode::Opaque => 1.0,
+            AlphaMode::Mask if self.base_color(textures, uv).w < self.alpha_cutoff => 0.0,
+            AlphaMode::Mask => 1.0,
+            AlphaMode::Blend => self.base_color(textures, uv).w,
+        }
+    }
+
+    /// The surface's reflectance at the texture coordinates. The metalness is read from the blue
+    /// channel of the metallic-roughness texture and the roughness from the green, as in glTF.
+    pub fn surface(&self, textures: &[TextureImage], uv: Vector2<f32>) -> Surface {
+        let base_color = self.base_color(textures, uv).truncate();
+        let (metallic, roughness) = match self.metallic_roughness_texture {
+            Some(texture) => {
+                let sample = textures[texture].sample(uv, false);
+                (self.metallic * sample.z, self.roughness * sample.y)
+            }
+            None => (self.metallic, self.roughness),
+        };
+        let metallic = metallic.clamp(0.0, 1.0);
+
+        let reflectance = self.reflectance.clamp(0.0, 1.0);
+        let dielectric_f0 = 0.16 * reflectance * reflectance;
+
+        Surface {
+            diffuse_color: base_color * (1.0 - metallic),
+            f0: Vector3::new(dielectric_f0, dielectric_f0, dielectric_f0) * (1.0 - metallic) + base_color * metallic,
+            roughness: roughness.clamp(MIN_ROUGHNESS, 1.0),
+        }
+    }
+
+    /// The tangent-space normal at the texture coordinates, if the material has a normal map.
+    pub fn tangent_normal(&self, textures: &[TextureImage], uv: Vector2<f32>) -> Option<Vector3<f32>> {
+        self.normal_texture.map(|texture| {
+            let sample = textures[texture].sample(uv, false);
+            Vector3::new(sample.x * 2.0 - 1.0, sample.y * 2.0 - 1.0, sample.z * 2.0 - 1.0)
+        })
+    }
+}
+
+/// The reflectance of a point on a surface: Burley diffuse, with Frostbite's renormalisation, and
+/// GGX specular with height-correlated Smith visibility and Schlick's Fresnel. This is the model
+/// `pbr.frag` approximates. See Karis, "Real Shading in Unreal Engine 4", and Lagarde and de
+/// Rousiers, "Moving Frostbite to Physically Based Rendering".
+///
+/// Directions are in the tangent frame of the shading normal, with the normal as z.
+pub struct Surface {
+    pub diffuse_color: Vector3<f32>,
+    pub f0: Vector3<f32>,
+    pub roughness: f32,
+}
+
+/// A direction chosen by `Surface::sample`.
+pub struct SurfaceSample {
+    pub direction: Vector3<f32>,
+    /// The reflectance times the cosine of the direction, divided by its density.
+    pub weight: Vector3<f32>,
+    pub pdf: f32,
+}
+
+impl Surface {
+    /// The reflectance from `incoming` to `outgoing`, times the cosine of `incoming`.
+    pub fn eval(&self, outgoing: Vector3<f32>, incoming: Vector3<f32>) -> Vector3<f32> {
+        let (n_dot_v, n_dot_l) = (outgoing.z, incoming.z);
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        let half = (outgoing + incoming).normalize();
+        let n_dot_h = half.z.max(0.0);
+        let l_dot_h = incoming.dot(half).max(0.0);
+        let alpha = self.roughness * self.roughness;
+
+        let fresnel = fresnel_schlick(self.f0, l_dot_h);
+        let specular = fresnel * (d_ggx(n_dot_h, alpha) * v_smith_ggx_correlated(n_dot_v, n_dot_l, alpha));
+        let diffuse = self.diffuse_color * (fd_burley(n_dot_v, n_dot_l, l_dot_h, self.roughness) / PI);
+
+        (diffuse + specular) * n_dot_l
+    }
+
+    /// The density `sample` chooses `incoming` with, per unit solid angle.
+    pub fn pdf(&self, outgoing: Vector3<f32>, incoming: Vector3<f32>) -> f32 {
+        if outgoing.z <= 0.0 || incoming.z <= 0.0 {
+            return 0.0;
+        }
+
+        let specular_probability = self.specular_probability(outgoing.z);
+        let half = (outgoing + incoming).normalize();
+        let alpha = self.roughness * self.roughness;
+
+        let specular_pdf = d_ggx(half.z.max(0.0), alpha) * half.z.max(0.0) / (4.0 * outgoing.dot(half).max(1e-6));
+        let diffuse_pdf = incoming.z / PI;
+
+        specular_probability * specular_pdf + (1.0 - specular_probability) * diffuse_pdf
+    }
+
+    /// Chooses an incoming direction, either around the specular lobe or cosine weighted.
+    pub fn sample(&self, outgoing: Vector3<f32>, rng: &mut Rng) -> Option<SurfaceSample> {
+        if outgoing.z <= 0.0 {
+            return None;
+        }
+
+        let incoming = if rng.next_f32() < self.specular_probability(outgoing.z) {
+            let half = sample_ggx(self.roughness * self.roughness, rng.next_2d());
+            2.0 * outgoing.dot(half) * half - outgoing
+        } else {
+            cosine_hemisphere(rng.next_2d())
+        };
+
+        let pdf = self.pdf(outgoing, incoming);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(SurfaceSample {
+            direction: incoming,
+            weight: self.eval(outgoing, incoming) / pdf,
+            pdf,
+        })
+    }
+
+    /// How often the specular lobe is sampled rather than the diffuse, by their rough weights.
+    fn specular_probability(&self, n_dot_v: f32) -> f32 {
+        let specular = luminance(fresnel_schlick(self.f0, n_dot_v));
+        let diffuse = luminance(self.diffuse_color) * (1.0 - specular);
+        if diffuse <= 0.0 {
+            return 1.0;
+        }
+
+        (specular / (specular + diffuse)).clamp(0.1, 0.9)
+    }
+}
+
+fn fresnel_schlick(f0: Vector3<f32>, cos_theta: f32) -> Vector3<f32> {
+    let factor = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    f0 + (Vector3::new(1.0, 1.0, 1.0) - f0) * factor
+}
+
+fn d_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_2 - 1.0) + 1.0;
+    alpha_2 / (PI * denominator * denominator)
+}
+
+/// The height-correlated Smith visibility term, including the BRDF's 1 / (4 NdotL NdotV).
+/// See Heitz, "Understanding the Masking-Shadowing Function in Microfacet-Based BRDFs".
+fn v_smith_ggx_correlated(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let alpha_2 = alpha * alpha;
+    let ggx_v = n_dot_l * (n_dot_v * n_dot_v * (1.0 - alpha_2) + alpha_2).sqrt();
+    let ggx_l = n_dot_v * (n_dot_l * n_dot_l * (1.0 - alpha_2) + alpha_2).sqrt();
+    0.5 / (ggx_v + ggx_l)
+}
+
+/// The Disney diffuse factor, renormalised to conserve energy, without the 1 / PI.
+fn fd_burley(n_dot_v: f32, n_dot_l: f32, l_dot_h: f32, roughness: f32) -> f32 {
+    let energy_bias = 0.5 * roughness;
+    let energy_factor = 1.0 + (1.0 / 1.51 - 1.0) * roughness;
+    let f90 = energy_bias + 2.0 * l_dot_h * l_dot_h * roughness;
+
+    let scatter = |cos_theta: f32| 1.0 + (f90 - 1.0) * (1.0 - cos_theta).powi(5);
+    scatter(n_dot_l) * scatter(n_dot_v) * energy_factor
+}
+
+/// A half vector with a density of D(h) cos(h).
+fn sample_ggx(alpha: f32, [u, v]: [f32; 2]) -> Vector3<f32> {
+    let cos_theta_2 = (1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u);
+    let cos_theta = cos_theta_2.sqrt();
+    let sin_theta = (1.0 - cos_theta_2).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn decode_srgb(encoded: f32) -> f32 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emission_is_the_factor_times_the_emissive_texture() {
+        let mut material = Material {
+            emissive_factor: Vector3::new(1.0, 0.5, 1.0),
+            ..Default::default()
+        };
+        material.textures.emissive = Some("emissive".to_string());
+        let material = SurfaceMaterial::new(&material, |name| name.as_ref().map(|_| 0));
+
+        // The left half of the texture is black, and the right half white.
+        let texture = TextureImage::new(&Texture {
+            pixels: vec![0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            width: 4,
+            height: 1,
+            ..Default::default()
+        });
+        let textures = [texture];
+
+        assert_eq!(material.emission(&textures, Vector2::new(0.25, 0.5)), Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(material.emission(&textures, Vector2::new(0.75, 0.5)), Vector3::new(1.0, 0.5, 1.0));
+    }
+}
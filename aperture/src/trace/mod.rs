@@ -0,0 +1,475 @@
+mod bvh;
+mod environment;
+mod material;
+mod sampling;
+
+use crate::render::{Camera, RadianceImage};
+use crate::world::light::Light;
+use crate::world::World;
+
+use bvh::{Bvh, Ray};
+use environment::EnvironmentMap;
+use material::{SurfaceMaterial, TextureImage};
+use sampling::{power_heuristic, Frame, Rng};
+
+use cgmath::{
+    ElementWise, EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Transform, Vector2,
+    Vector3, Vector4,
+};
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::Mutex;
+use std::thread;
+
+/// The number of bounces after which paths are randomly terminated, in proportion to how little
+/// they can still contribute.
+const ROULETTE_BOUNCES: u32 = 3;
+
+/// How far rays leaving a surface start from it, relative to the magnitude of the position, so
+/// they don't hit it again.
+const RAY_OFFSET: f32 = 1e-4;
+
+/// The settings of a path-traced render.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathTracing {
+    /// The number of paths traced through each pixel.
+    pub samples: u32,
+    /// The most times a path is reflected before it's terminated.
+    pub max_bounces: u32,
+}
+
+impl Default for PathTracing {
+    fn default() -> Self {
+        Self {
+            samples: 256,
+            max_bounces: 8,
+        }
+    }
+}
+
+/// A CPU path tracer rendering a world as the rasteriser does, as a converged reference to check
+/// it against, and to render without a GPU. Its primitives are flattened into world-space triangles
+/// with a hierarchy over them, and lit by its point lights and environment map.
+pub struct PathTracer {
+    bvh: Bvh,
+    triangles: Vec<TriangleData>,
+    materials: Vec<SurfaceMaterial>,
+    textures: Vec<TextureImage>,
+    lights: Vec<PointLight>,
+    environment: Option<EnvironmentMap>,
+}
+
+/// What's needed to shade a triangle, in world space.
+struct TriangleData {
+    positions: [Point3<f32>; 3],
+    normals: [Vector3<f32>; 3],
+    uvs: [Vector2<f32>; 3],
+    material: usize,
+}
+
+/// A point light, with its radiant intensity.
+struct PointLight {
+    position: Point3<f32>,
+    intensity: Vector3<f32>,
+}
+
+/// Where a ray hit a surface.
+struct SurfaceHit {
+    position: Point3<f32>,
+    /// The normal of the triangle, facing the side the ray arrived from.
+    geometric_normal: Vector3<f32>,
+    /// The interpolated and normal-mapped normal, on the same side as the geometric normal.
+    shading_normal: Vector3<f32>,
+    uv: Vector2<f32>,
+    material: usize,
+}
+
+impl PathTracer {
+    pub fn new(world: &World) -> Self {
+        let texture_indices = world
+            .textures
+            .values()
+            .enumerate()
+            .map(|(index, texture)| (texture.name.as_str(), index))
+            .collect::<HashMap<_, _>>();
+        let textures = world.textures.values().map(TextureImage::new).collect();
+
+        let mut materials = vec![];
+        let mut material_indices = HashMap::new();
+        let mut triangles = vec![];
+
+        for mesh in &world.meshes {
+            for instance in &mesh.instances {
+                let instance = instance.lock().expect("poisoned lock").compose();
+
+                for primitive in &mesh.primitives {
+                    let transform = instance * primitive.transform.lock().expect("poisoned lock").compose();
+                    let normal_matrix = normal_matrix(&transform);
+
+                    let material_name = primitive.material_name.as_deref();
+                    let material = *material_indices.entry(material_name).or_insert_with(|| {
+                        let material = material_name
+                            .and_then(|name| world.materials.get(name))
+                            .unwrap_or(&world.default_material);
+                        materials.push(SurfaceMaterial::new(material, |name| {
+                            name.as_deref().and_then(|name| texture_indices.get(name).copied())
+                        }));
+                        materials.len() - 1
+                    });
+
+                    let vertex = |index: u32| {
+                        let vertex = &primitive.vertices[index as usize];
+                        (
+                            transform.transform_point(Point3::from(vertex.position)),
+                            (normal_matrix * Vector3::from(vertex.normal)).normalize(),
+                            Vector2::from(vertex.uv_coord),
+                        )
+                    };
+
+                    let indices = if primitive.indices.is_empty() {
+                        (0..primitive.vertices.len() as u32).collect()
+                    } else {
+                        primitive.indices.clone()
+                    };
+
+                    for corners in indices.chunks_exact(3) {
+                        if corners.iter().any(|&index| index as usize >= primitive.vertices.len()) {
+                            continue;
+                        }
+
+                        let [a, b, c] = [vertex(corners[0]), vertex(corners[1]), vertex(corners[2])];
+                        triangles.push(TriangleData {
+                            positions: [a.0, b.0, c.0],
+                            normals: [a.1, b.1, c.1],
+                            uvs: [a.2, b.2, c.2],
+                            material,
+                        });
+                    }
+                }
+            }
+        }
+
+        let bvh = Bvh::new(&triangles.iter().map(|triangle| triangle.positions).collect::<Vec<_>>());
+
+        let lights = world
+            .lights
+            .iter()
+            .map(|light| {
+                let [x, y, z, _] = light.position();
+                let [r, g, b, _] = light.color();
+                // As in `pbr.frag`, the power is spread evenly over the sphere.
+                let intensity = light.power()[0] as f32 / (4.0 * PI);
+                PointLight {
+                    position: Point3::new(x, y, z),
+                    intensity: Vector3::new(r, g, b) * intensity,
+                }
+            })
+            .collect();
+
+        Self {
+            bvh,
+            triangles,
+            materials,
+            textures,
+            lights,
+            environment: world.environment.as_ref().map(|cube| EnvironmentMap::new(&cube.texture)),
+        }
+    }
+
+    /// Renders the camera's view at the given size, spreading the rows over every available
+    /// thread. Each pixel is seeded by its position, so the image is the same on every run.
+    pub fn render(&self, camera: &Camera, [width, height]: [u32; 2], settings: PathTracing) -> RadianceImage {
+        let aspect_ratio = width as f32 / height as f32;
+        let clip_to_world = camera.clip_matrix(aspect_ratio).invert().expect("the clip matrix is invertible");
+
+        let mut pixels = vec![0.0; 4 * width as usize * height as usize];
+        let rows = Mutex::new(pixels.chunks_mut(4 * width as usize).enumerate());
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let (y, row) = match rows.lock().expect("poisoned lock").next() {
+                        Some(row) => row,
+                        None => break,
+                    };
+
+                    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                        let index = y as u64 * width as u64 + x as u64;
+                        let mut rng = Rng::new(index.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+
+                        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                        for _ in 0..settings.samples {
+                            // The camera ray through a random point in the pixel.
+                            let [dx, dy] = rng.next_2d();
+                            let ray = camera_ray(
+                                camera.eye,
+                                &clip_to_world,
+                                [x as f32 + dx, y as f32 + dy],
+                                [width, height],
+                            );
+
+                            let radiance = self.trace(ray, settings.max_bounces, &mut rng);
+                            // Rare paths with non-finite radiance are dropped, rather than spoiling
+                            // the pixel.
+                            if radiance.x.is_finite() && radiance.y.is_finite() && radiance.z.is_finite() {
+                                sum += radiance;
+                            }
+                        }
+
+                        let mean = sum / settings.samples.max(1) as f32;
+                        pixel.copy_from_slice(&[mean.x, mean.y, mean.z, 1.0]);
+                    }
+                });
+            }
+        });
+
+        RadianceImage::from_raw(width, height, pixels).expect("the buffer fits the image")
+    }
+
+    /// The radiance arriving along the ray. Lights and the environment are sampled directly at
+    /// every bounce, and the environment is also reached by the reflected paths, with the two
+    /// weighted by multiple importance sampling.
+    fn trace(&self, mut ray: Ray, max_bounces: u32, rng: &mut Rng) -> Vector3<f32> {
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        // The density the last reflection was sampled with, or `None` for camera rays.
+        let mut reflection_pdf = None;
+
+        for bounce in 0..=max_bounces {
+            let hit = match self.intersect(&ray, f32::INFINITY, rng) {
+                Some(hit) => hit,
+                None => {
+                    if let Some(environment) = &self.environment {
+                        let weight = reflection_pdf.map_or(1.0, |pdf| power_heuristic(pdf, environment.pdf(ray.direction)));
+                        radiance += throughput.mul_element_wise(environment.radiance(ray.direction)) * weight;
+                    }
+                    break;
+                }
+            };
+
+            let material = &self.materials[hit.material];
+            radiance += throughput.mul_element_wise(material.emission(&self.textures, hit.uv));
+            if bounce == max_bounces {
+                break;
+            }
+
+            let surface = material.surface(&self.textures, hit.uv);
+            let frame = Frame::new(hit.shading_normal);
+            let outgoing = frame.to_local(-ray.direction);
+
+            for light in &self.lights {
+                let to_light = light.position - hit.position;
+                let distance = to_light.magnitude();
+                let direction = to_light / distance;
+                let reflectance = surface.eval(outgoing, frame.to_local(direction));
+                if is_black(reflectance) || !self.is_visible(&hit, direction, distance, rng) {
+                    continue;
+                }
+
+                let irradiance = light.intensity / (distance * distance);
+                radiance += throughput.mul_element_wise(reflectance).mul_element_wise(irradiance);
+            }
+
+            if let Some(sample) = self.environment.as_ref().and_then(|environment| environment.sample(rng)) {
+                let incoming = frame.to_local(sample.direction);
+                let reflectance = surface.eval(outgoing, incoming);
+                if !is_black(reflectance) && self.is_visible(&hit, sample.direction, f32::INFINITY, rng) {
+                    let weight = power_heuristic(sample.pdf, surface.pdf(outgoing, incoming));
+                    radiance += throughput.mul_element_wise(reflectance).mul_element_wise(sample.radiance)
+                        * (weight / sample.pdf);
+                }
+            }
+
+            let sample = match surface.sample(outgoing, rng) {
+                Some(sample) => sample,
+                None => break,
+            };
+            let direction = frame.to_world(sample.direction);
+            // Reflections that would pass through the surface, due to the shading normal, are lost.
+            if direction.dot(hit.geometric_normal) <= 0.0 {
+                break;
+            }
+
+            throughput = throughput.mul_element_wise(sample.weight);
+            reflection_pdf = Some(sample.pdf);
+
+            if bounce >= ROULETTE_BOUNCES {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if rng.next_f32() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            ray = Ray {
+                origin: offset_origin(hit.position, hit.geometric_normal),
+                direction,
+            };
+        }
+
+        radiance
+    }
+
+    /// Finds the nearest surface the ray hits. Surfaces that are partly transparent are passed
+    /// through at random, in proportion to their transparency.
+    fn intersect(&self, ray: &Ray, t_max: f32, rng: &mut Rng) -> Option<SurfaceHit> {
+        let mut origin = ray.origin;
+        let mut t_max = t_max;
+
+        loop {
+            let segment = Ray {
+                origin,
+                direction: ray.direction,
+            };
+            let hit = self.bvh.intersect(&segment, t_max)?;
+            let triangle = &self.triangles[hit.triangle];
+            let [u, v] = hit.barycentrics;
+            let uv = triangle.uvs[0] * (1.0 - u - v) + triangle.uvs[1] * u + triangle.uvs[2] * v;
+
+            let material = &self.materials[triangle.material];
+            let opacity = material.opacity(&self.textures, uv);
+            if opacity >= 1.0 || rng.next_f32() < opacity {
+                return Some(self.surface_hit(triangle, &segment, hit.t, [u, v], uv));
+            }
+
+            let position = segment.origin + segment.direction * hit.t;
+            let edges = [triangle.positions[1] - triangle.positions[0], triangle.positions[2] - triangle.positions[0]];
+            let normal = edges[0].cross(edges[1]).normalize();
+            let normal = if normal.dot(ray.direction) > 0.0 { normal } else { -normal };
+            origin = offset_origin(position, normal);
+            t_max -= hit.t;
+        }
+    }
+
+    fn surface_hit(&self, triangle: &TriangleData, ray: &Ray, t: f32, [u, v]: [f32; 2], uv: Vector2<f32>) -> SurfaceHit {
+        let [p0, p1, p2] = triangle.positions;
+        let (edge1, edge2) = (p1 - p0, p2 - p0);
+
+        // Triangles are two-sided, so the normals face the ray.
+        let mut geometric_normal = edge1.cross(edge2).normalize();
+        let interpolated =
+            (triangle.normals[0] * (1.0 - u - v) + triangle.normals[1] * u + triangle.normals[2] * v).normalize();
+        let mut normal = if interpolated.x.is_finite() { interpolated } else { geometric_normal };
+        if geometric_normal.dot(ray.direction) > 0.0 {
+            geometric_normal = -geometric_normal;
+        }
+        if normal.dot(geometric_normal) < 0.0 {
+            normal = -normal;
+        }
+
+        let material = &self.materials[triangle.material];
+        if let Some(tangent_normal) = material.tangent_normal(&self.textures, uv) {
+            // The tangent runs along increasing u and the bitangent completes a right-handed
+            // frame, as in the glTF sample viewer.
+            let (duv1, duv2) = (triangle.uvs[1] - triangle.uvs[0], triangle.uvs[2] - triangle.uvs[0]);
+            let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+            if determinant.abs() > 1e-12 {
+                let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+                let tangent = (tangent - normal * normal.dot(tangent)).normalize();
+                let bitangent = normal.cross(tangent);
+                let mapped = (tangent * tangent_normal.x + bitangent * tangent_normal.y + normal * tangent_normal.z)
+                    .normalize();
+                if mapped.x.is_finite() {
+                    normal = mapped;
+                }
+            }
+        }
+
+        SurfaceHit {
+            position: ray.origin + ray.direction * t,
+            geometric_normal,
+            shading_normal: normal,
+            uv,
+            material: triangle.material,
+        }
+    }
+
+    /// Whether nothing blocks the path from the surface in the direction, up to the distance.
+    fn is_visible(&self, hit: &SurfaceHit, direction: Vector3<f32>, distance: f32, rng: &mut Rng) -> bool {
+        if direction.dot(hit.geometric_normal) <= 0.0 {
+            return false;
+        }
+
+        let ray = Ray {
+            origin: offset_origin(hit.position, hit.geometric_normal),
+            direction,
+        };
+        self.intersect(&ray, distance, rng).is_none()
+    }
+}
+
+/// The ray from the eye through a point of the image, in pixels from its top left corner. The
+/// point is unprojected through the inverse of the rasteriser's clip transform, so the image is
+/// the one it rasterises.
+fn camera_ray(eye: Point3<f32>, clip_to_world: &Matrix4<f32>, [x, y]: [f32; 2], [width, height]: [u32; 2]) -> Ray {
+    // The point on the far plane, with the rows running down the image as y does in Vulkan's
+    // clip space.
+    let ndc = Vector4::new(2.0 * x / width as f32 - 1.0, 2.0 * y / height as f32 - 1.0, 1.0, 1.0);
+    let point = Point3::from_homogeneous(clip_to_world * ndc);
+
+    Ray {
+        origin: eye,
+        direction: (point - eye).normalize(),
+    }
+}
+
+/// The matrix transforming normals by the transform, the inverse transpose of its upper 3x3.
+fn normal_matrix(transform: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+    linear.invert().unwrap_or(linear).transpose()
+}
+
+/// Moves a point off a surface along its normal, by an amount relative to its distance from the
+/// origin, to keep ahead of the precision of the intersection.
+fn offset_origin(position: Point3<f32>, normal: Vector3<f32>) -> Point3<f32> {
+    let scale = position.to_vec().map(f32::abs).dot(Vector3::new(1.0, 1.0, 1.0)).max(1.0);
+    position + normal * (RAY_OFFSET * scale)
+}
+
+fn is_black(color: Vector3<f32>) -> bool {
+    color.x <= 0.0 && color.y <= 0.0 && color.z <= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: [u32; 2] = [320, 200];
+
+    /// The position in pixels the rasteriser draws the point at, as the viewport transform maps
+    /// the clip space of the vertex shaders.
+    fn rasterised_position(camera: &Camera, point: Point3<f32>) -> [f32; 2] {
+        let [width, height] = SIZE;
+        let clip = camera.clip_matrix(width as f32 / height as f32) * point.to_homogeneous();
+        let ndc = clip.truncate() / clip.w;
+
+        [(ndc.x + 1.0) / 2.0 * width as f32, (ndc.y + 1.0) / 2.0 * height as f32]
+    }
+
+    #[test]
+    fn camera_rays_pass_through_points_where_they_are_rasterised() {
+        let camera = Camera::new(Point3::new(1.0, 2.0, 5.0), Point3::new(0.5, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let clip_to_world = camera.clip_matrix(SIZE[0] as f32 / SIZE[1] as f32).invert().unwrap();
+
+        for point in [Point3::new(1.5, 0.8, -0.5), Point3::new(-1.0, -0.3, 1.0), Point3::new(0.2, 1.2, 2.0)] {
+            let position = rasterised_position(&camera, point);
+            let ray = camera_ray(camera.eye, &clip_to_world, position, SIZE);
+
+            let direction = (point - camera.eye).normalize();
+            assert!(ray.direction.dot(direction) > 0.99999, "{:?} isn't at {:?}", point, position);
+        }
+    }
+
+    #[test]
+    fn images_are_not_mirrored() {
+        let camera = Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let [width, height] = SIZE;
+
+        // Looking down -z with y up, +x is to the right.
+        let [x, y] = rasterised_position(&camera, Point3::new(1.0, 1.0, 0.0));
+        assert!(x > width as f32 / 2.0);
+        assert!(y < height as f32 / 2.0);
+    }
+}
@@ -0,0 +1,96 @@
+use cgmath::{InnerSpace, Vector3};
+
+use std::f32::consts::PI;
+
+/// A small PCG random number generator. Each pixel seeds its own, so renders are repeatable
+/// however the pixels are shared between threads.
+/// See O'Neill, "PCG: A Family of Simple Fast Space-Efficient Statistically Good Algorithms for
+/// Random Number Generation".
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+    const INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.state = state.wrapping_mul(Self::MULTIPLIER).wrapping_add(Self::INCREMENT);
+
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        let rotation = (state >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    /// A uniform value in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn next_2d(&mut self) -> [f32; 2] {
+        [self.next_f32(), self.next_f32()]
+    }
+}
+
+/// An orthonormal basis around a unit normal, with the normal as z.
+/// See Duff et al., "Building an Orthonormal Basis, Revisited".
+pub struct Frame {
+    pub tangent: Vector3<f32>,
+    pub bitangent: Vector3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+impl Frame {
+    pub fn new(normal: Vector3<f32>) -> Self {
+        let sign = 1f32.copysign(normal.z);
+        let a = -1.0 / (sign + normal.z);
+        let b = normal.x * normal.y * a;
+
+        Self {
+            tangent: Vector3::new(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x),
+            bitangent: Vector3::new(b, sign + normal.y * normal.y * a, -normal.y),
+            normal,
+        }
+    }
+
+    pub fn to_world(&self, local: Vector3<f32>) -> Vector3<f32> {
+        self.tangent * local.x + self.bitangent * local.y + self.normal * local.z
+    }
+
+    pub fn to_local(&self, world: Vector3<f32>) -> Vector3<f32> {
+        Vector3::new(world.dot(self.tangent), world.dot(self.bitangent), world.dot(self.normal))
+    }
+}
+
+/// A direction in the hemisphere around z, with a density proportional to its cosine.
+pub fn cosine_hemisphere([u, v]: [f32; 2]) -> Vector3<f32> {
+    let radius = u.sqrt();
+    let phi = 2.0 * PI * v;
+
+    Vector3::new(radius * phi.cos(), radius * phi.sin(), (1.0 - u).max(0.0).sqrt())
+}
+
+/// Weights a sample from one of two strategies by how likely each was to produce it.
+/// See Veach, "Robust Monte Carlo Methods for Light Transport Simulation", chapter 9.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Finds the index of the first entry of a cumulative distribution, ending in one, above the value.
+pub fn sample_cdf(cdf: &[f32], value: f32) -> usize {
+    cdf.partition_point(|&c| c <= value).min(cdf.len() - 1)
+}
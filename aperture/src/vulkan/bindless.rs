@@ -0,0 +1,139 @@
+use vulkano::buffer::BufferAccess;
+use vulkano::descriptor::descriptor::{DescriptorDesc, DescriptorDescTy};
+use vulkano::descriptor::descriptor_set::{
+    DescriptorPool, DescriptorPoolAlloc, DescriptorSet, DescriptorSetDesc, DescriptorWrite,
+    StdDescriptorPoolAlloc, UnsafeDescriptorSet, UnsafeDescriptorSetLayout,
+};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::image::view::ImageViewAbstract;
+use vulkano::sampler::Sampler;
+
+use std::sync::Arc;
+
+pub type ImageView = Arc<dyn ImageViewAbstract + Send + Sync>;
+
+/// The resources for one binding of a `BindlessSet`.
+pub enum Binding {
+    /// A uniform or storage buffer, matching the descriptor in the layout.
+    Buffer(Arc<dyn BufferAccess + Send + Sync>),
+    /// Every element of an array of combined image samplers.
+    SampledImages(Vec<(ImageView, Arc<Sampler>)>),
+}
+
+/// A descriptor set written directly, for arrays of textures which the typed
+/// `PersistentDescriptorSet` builder can't fill in a loop.
+pub struct BindlessSet {
+    alloc: StdDescriptorPoolAlloc,
+    layout: Arc<UnsafeDescriptorSetLayout>,
+    buffers: Vec<(Arc<dyn BufferAccess + Send + Sync>, u32)>,
+    images: Vec<(ImageView, u32)>,
+    _samplers: Vec<Arc<Sampler>>,
+}
+
+impl BindlessSet {
+    /// Creates a set with a binding for each of `bindings`, in order.
+    ///
+    /// Panics if a binding doesn't match its descriptor in the layout.
+    pub fn new(layout: Arc<UnsafeDescriptorSetLayout>, bindings: Vec<Binding>) -> Self {
+        let device = layout.device().clone();
+        let mut pool = Device::standard_descriptor_pool(&device);
+        let mut alloc = pool.alloc(&layout).unwrap();
+
+        let mut writes = vec![];
+        let mut buffers = vec![];
+        let mut images = vec![];
+        let mut samplers = vec![];
+
+        for (binding, resource) in bindings.into_iter().enumerate() {
+            let desc = layout
+                .descriptor(binding)
+                .expect("binding is missing from the layout");
+            let binding = binding as u32;
+
+            match resource {
+                Binding::Buffer(buffer) => {
+                    let write = match desc.ty {
+                        DescriptorDescTy::Buffer(ref buffer_desc) if buffer_desc.storage => unsafe {
+                            DescriptorWrite::storage_buffer(binding, 0, &buffer)
+                        },
+                        DescriptorDescTy::Buffer(_) => unsafe {
+                            DescriptorWrite::uniform_buffer(binding, 0, &buffer)
+                        },
+                        _ => panic!("binding {} isn't a buffer", binding),
+                    };
+
+                    writes.push(write);
+                    buffers.push((buffer, binding));
+                }
+                Binding::SampledImages(array) => {
+                    assert_eq!(array.len() as u32, desc.array_count);
+
+                    for (element, (view, sampler)) in array.into_iter().enumerate() {
+                        writes.push(DescriptorWrite::combined_image_sampler(
+                            binding,
+                            element as u32,
+                            &sampler,
+                            &view,
+                        ));
+                        images.push((view, binding));
+                        samplers.push(sampler);
+                    }
+                }
+            }
+        }
+
+        unsafe {
+            alloc.inner_mut().write(&device, writes.into_iter());
+        }
+
+        Self {
+            alloc,
+            layout,
+            buffers,
+            images,
+            _samplers: samplers,
+        }
+    }
+}
+
+unsafe impl DescriptorSet for BindlessSet {
+    fn inner(&self) -> &UnsafeDescriptorSet {
+        self.alloc.inner()
+    }
+
+    fn num_buffers(&self) -> usize {
+        self.buffers.len()
+    }
+
+    fn buffer(&self, index: usize) -> Option<(&dyn BufferAccess, u32)> {
+        self.buffers
+            .get(index)
+            .map(|(buffer, binding)| (buffer.as_ref() as &dyn BufferAccess, *binding))
+    }
+
+    fn num_images(&self) -> usize {
+        self.images.len()
+    }
+
+    fn image(&self, index: usize) -> Option<(&dyn ImageViewAbstract, u32)> {
+        self.images
+            .get(index)
+            .map(|(view, binding)| (view.as_ref() as &dyn ImageViewAbstract, *binding))
+    }
+}
+
+unsafe impl DescriptorSetDesc for BindlessSet {
+    fn num_bindings(&self) -> usize {
+        self.layout.num_bindings()
+    }
+
+    fn descriptor(&self, binding: usize) -> Option<DescriptorDesc> {
+        self.layout.descriptor(binding)
+    }
+}
+
+unsafe impl DeviceOwned for BindlessSet {
+    fn device(&self) -> &Arc<Device> {
+        self.layout.device()
+    }
+}
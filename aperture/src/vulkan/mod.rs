@@ -1,5 +1,9 @@
+mod bindless;
 mod descriptor_set;
 mod pipeline;
+mod push_constants;
 
+pub use bindless::{BindlessSet, Binding, ImageView};
 pub use descriptor_set::DescriptorSet;
 pub use pipeline::Pipeline;
+pub use push_constants::{PushConstantBlock, PushConstantError, PushConstantLayout, PushConstants, StageBlock};
@@ -1,13 +1,15 @@
 use crate::render::shaders::*;
+use crate::vulkan::{PushConstantError, PushConstantLayout, StageBlock};
 
-use aperture_common::{VPos, VPosNormTex};
+use aperture_common::{InstanceTransform, VPos, VPosNormTex};
 
 use vulkano::descriptor::descriptor::ShaderStages;
 use vulkano::device::Device;
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor, BlendOp};
 use vulkano::pipeline::depth_stencil::{DepthBounds, DepthStencil};
-use vulkano::pipeline::layout::{PipelineLayout, PipelineLayoutDesc, PipelineLayoutDescPcRange};
-use vulkano::pipeline::shader::EntryPointAbstract;
-use vulkano::pipeline::vertex::SingleBufferDefinition;
+use vulkano::pipeline::layout::{PipelineLayout, PipelineLayoutDesc};
+use vulkano::pipeline::shader::{EntryPointAbstract, GraphicsEntryPoint};
+use vulkano::pipeline::vertex::{OneVertexOneInstanceDefinition, SingleBufferDefinition};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
 use vulkano::render_pass::{RenderPass, Subpass};
@@ -20,6 +22,32 @@ use std::sync::Arc;
 pub enum Pipeline {
     Cubemap,
     Shaded,
+    GBuffer,
+    /// The edges of the primitives, shaded as `Shaded` shades their surfaces.
+    Wireframe,
+    /// The edges of the primitives, written to the G-buffer as `GBuffer` writes their surfaces.
+    GBufferWireframe,
+    GpuDriven,
+    DepthPrepass,
+    DeferredLighting,
+    Transparent,
+    WeightedBlended,
+    Present,
+    Fxaa,
+    Taa,
+    Ssao,
+    SsaoBlur,
+    AmbientComposite,
+    DofCoc,
+    DofTiles,
+    Dof,
+    MotionBlurTiles,
+    MotionBlurNeighbours,
+    MotionBlur,
+    HiZ,
+    Ssr,
+    SsrComposite,
+    OitComposite,
 }
 
 impl Pipeline {
@@ -31,62 +59,298 @@ impl Pipeline {
         render_pass: Arc<RenderPass>,
     ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
         match self {
-            Self::Shaded => self.shaded(device, dimensions, shaders, render_pass),
+            Self::Shaded => self.shaded(device, dimensions, shaders, shaders.fragment.main_entry_point(), render_pass),
+            Self::GBuffer => self.shaded(device, dimensions, shaders, shaders.gbuffer_frag.main_entry_point(), render_pass),
+            Self::Wireframe => {
+                self.shaded(device, dimensions, shaders, shaders.fragment.main_entry_point(), render_pass)
+            }
+            Self::GBufferWireframe => {
+                self.shaded(device, dimensions, shaders, shaders.gbuffer_frag.main_entry_point(), render_pass)
+            }
+            Self::GpuDriven => self.gpu_driven(device, dimensions, shaders, render_pass),
+            // Writes the linear depth of the occluders, for occlusion culling.
+            Self::DepthPrepass => {
+                self.shaded(device, dimensions, shaders, shaders.depth_prepass_frag.main_entry_point(), render_pass)
+            }
+            // Blends the colour over the scene by its alpha, in the last subpass of the scene
+            // render pass. The other scene images are left with the values of the opaque
+            // surfaces behind.
+            Self::Transparent => {
+                let last_subpass = render_pass.desc().subpasses().len() as u32 - 1;
+                let subpass = Subpass::from(render_pass, last_subpass).unwrap();
+
+                let masked = AttachmentBlend {
+                    mask_red: false,
+                    mask_green: false,
+                    mask_blue: false,
+                    mask_alpha: false,
+                    ..AttachmentBlend::pass_through()
+                };
+                let blends = iter::once(Self::alpha_blend())
+                    .chain(iter::repeat_n(masked, subpass.num_color_attachments() as usize - 1))
+                    .collect();
+
+                self.transparent(device, dimensions, shaders, shaders.transparent_fragment.main_entry_point(), blends, subpass)
+            }
+            // Sums the weighted colours, and multiplies the transmittance of the surfaces.
+            Self::WeightedBlended => {
+                let accum = AttachmentBlend {
+                    enabled: true,
+                    color_op: BlendOp::Add,
+                    color_source: BlendFactor::One,
+                    color_destination: BlendFactor::One,
+                    alpha_op: BlendOp::Add,
+                    alpha_source: BlendFactor::One,
+                    alpha_destination: BlendFactor::One,
+                    ..AttachmentBlend::pass_through()
+                };
+                let revealage = AttachmentBlend {
+                    enabled: true,
+                    color_op: BlendOp::Add,
+                    color_source: BlendFactor::Zero,
+                    color_destination: BlendFactor::OneMinusSrcColor,
+                    alpha_op: BlendOp::Add,
+                    alpha_source: BlendFactor::Zero,
+                    alpha_destination: BlendFactor::OneMinusSrcAlpha,
+                    ..AttachmentBlend::pass_through()
+                };
+
+                self.transparent(
+                    device,
+                    dimensions,
+                    shaders,
+                    shaders.weighted_blended_fragment.main_entry_point(),
+                    vec![accum, revealage],
+                    Subpass::from(render_pass, 0).unwrap(),
+                )
+            }
+            // Lights the G-buffer in the second subpass of the deferred scene render pass.
+            Self::DeferredLighting => self.fullscreen_subpass(
+                device,
+                dimensions,
+                shaders,
+                shaders.deferred_lighting_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                Subpass::from(render_pass, 1).unwrap(),
+            ),
             Self::Cubemap => self.cubemap(device, dimensions, shaders, render_pass),
+            Self::Present => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.present_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::Fxaa => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.fxaa_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::Taa => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.taa_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::Ssao => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.ssao_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::SsaoBlur => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.ssao_blur_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            // Adds the ambient lighting on top of the scene.
+            Self::AmbientComposite => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.ambient_composite_frag.main_entry_point(),
+                AttachmentBlend {
+                    enabled: true,
+                    color_op: BlendOp::Add,
+                    color_source: BlendFactor::One,
+                    color_destination: BlendFactor::One,
+                    alpha_op: BlendOp::Add,
+                    alpha_source: BlendFactor::Zero,
+                    alpha_destination: BlendFactor::One,
+                    ..AttachmentBlend::pass_through()
+                },
+                render_pass,
+            ),
+            Self::DofCoc => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.dof_coc_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::DofTiles => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.dof_tiles_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::Dof => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.dof_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::MotionBlurTiles => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.motion_blur_tiles_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::MotionBlurNeighbours => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.motion_blur_neighbours_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::MotionBlur => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.motion_blur_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::HiZ => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.hiz_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            Self::Ssr => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.ssr_frag.main_entry_point(),
+                AttachmentBlend::pass_through(),
+                render_pass,
+            ),
+            // Adds the specular reflections on top of the scene.
+            Self::SsrComposite => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.ssr_composite_frag.main_entry_point(),
+                AttachmentBlend {
+                    enabled: true,
+                    color_op: BlendOp::Add,
+                    color_source: BlendFactor::One,
+                    color_destination: BlendFactor::One,
+                    alpha_op: BlendOp::Add,
+                    alpha_source: BlendFactor::Zero,
+                    alpha_destination: BlendFactor::One,
+                    ..AttachmentBlend::pass_through()
+                },
+                render_pass,
+            ),
+            // Blends the resolved transparent surfaces over the scene.
+            Self::OitComposite => self.fullscreen(
+                device,
+                dimensions,
+                shaders,
+                shaders.oit_composite_frag.main_entry_point(),
+                Self::alpha_blend(),
+                render_pass,
+            ),
+        }
+    }
+
+    /// Blends the colour over the destination by its alpha, leaving the destination alpha.
+    fn alpha_blend() -> AttachmentBlend {
+        AttachmentBlend {
+            enabled: true,
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::SrcAlpha,
+            color_destination: BlendFactor::OneMinusSrcAlpha,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::Zero,
+            alpha_destination: BlendFactor::One,
+            ..AttachmentBlend::pass_through()
         }
     }
 
+    /// A pipeline drawing primitives with their materials, writing the output of `fragment`.
+    /// The forward, G-buffer and depth prepass shaders all share the material descriptor sets and
+    /// push constants. The wireframe pipelines draw only the edges, including those of back faces.
     fn shaded(
         &self,
         device: Arc<Device>,
         dimensions: [u32; 2],
         shaders: &Shaders,
+        fragment: GraphicsEntryPoint,
         render_pass: Arc<RenderPass>,
     ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
-        let pipeline_layout_desc = {
-            let stages = vec![
-                shaders.vertex.main_entry_point(),
-                shaders.fragment.main_entry_point(),
-            ];
+        let pipeline_layout = self.shaded_layout(device.clone(), shaders, &fragment);
 
-            stages
-                .into_iter()
-                .fold(PipelineLayoutDesc::empty(), |total, stage| {
-                    total.union(stage.layout_desc())
-                })
-                .union(
-                    &PipelineLayoutDesc::new(
-                        vec![],
-                        vec![
-                            PipelineLayoutDescPcRange {
-                                offset: 0,
-                                size: 64,
-                                stages: ShaderStages {
-                                    vertex: true,
-                                    ..ShaderStages::none()
-                                },
-                            },
-                            PipelineLayoutDescPcRange {
-                                offset: 64,
-                                size: 28,
-                                stages: ShaderStages {
-                                    fragment: true,
-                                    ..ShaderStages::none()
-                                },
-                            },
-                        ],
-                    )
-                    .unwrap(),
-                )
+        let builder = GraphicsPipeline::start()
+            .vertex_input(OneVertexOneInstanceDefinition::<VPosNormTex, InstanceTransform>::new())
+            .vertex_shader(shaders.vertex.main_entry_point(), ());
+        let builder = match self {
+            Self::Wireframe | Self::GBufferWireframe => builder.polygon_mode_line().cull_mode_disabled(),
+            _ => builder.polygon_mode_fill().cull_mode_back(),
         };
 
-        let pipeline_layout =
-            Arc::new(PipelineLayout::new(device.clone(), pipeline_layout_desc).unwrap());
+        Arc::new(
+            builder
+                .viewports_dynamic_scissors_irrelevant(1)
+                .viewports(iter::once(Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                    depth_range: 0.0..1.0,
+                }))
+                .fragment_shader(fragment, ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .with_pipeline_layout(device.clone(), pipeline_layout)
+                .unwrap(),
+        )
+    }
 
+    /// A pipeline drawing the primitives of the GPU-driven path, with the forward shading of
+    /// `Shaded`. Every draw reads its transforms and material from storage buffers, so the
+    /// layout is the one the shaders declare, without push constants.
+    fn gpu_driven(
+        &self,
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        shaders: &Shaders,
+        render_pass: Arc<RenderPass>,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
         Arc::new(
             GraphicsPipeline::start()
                 .vertex_input(SingleBufferDefinition::<VPosNormTex>::new())
-                .vertex_shader(shaders.vertex.main_entry_point(), ())
+                .vertex_shader(shaders.gpu_driven_vert.main_entry_point(), ())
                 .polygon_mode_fill()
                 .viewports_dynamic_scissors_irrelevant(1)
                 .viewports(iter::once(Viewport {
@@ -94,15 +358,102 @@ impl Pipeline {
                     dimensions: [dimensions[0] as f32, dimensions[1] as f32],
                     depth_range: 0.0..1.0,
                 }))
-                .fragment_shader(shaders.fragment.main_entry_point(), ())
+                .fragment_shader(shaders.gpu_driven_frag.main_entry_point(), ())
                 .depth_stencil_simple_depth()
                 .cull_mode_back()
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .render_pass(Subpass::from(render_pass, 0).unwrap())
+                .build(device)
+                .unwrap(),
+        )
+    }
+
+    /// A pipeline drawing blended primitives with their materials, with a blend for each colour
+    /// attachment of the subpass. The depth is tested but not written, so primitives behind
+    /// transparent ones aren't hidden.
+    fn transparent(
+        &self,
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        shaders: &Shaders,
+        fragment: GraphicsEntryPoint,
+        blends: Vec<AttachmentBlend>,
+        subpass: Subpass,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        let pipeline_layout = self.shaded_layout(device.clone(), shaders, &fragment);
+
+        Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(OneVertexOneInstanceDefinition::<VPosNormTex, InstanceTransform>::new())
+                .vertex_shader(shaders.vertex.main_entry_point(), ())
+                .polygon_mode_fill()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .viewports(iter::once(Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                    depth_range: 0.0..1.0,
+                }))
+                .fragment_shader(fragment, ())
+                .blend_individual(blends)
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::Less,
+                    depth_write: false,
+                    depth_bounds_test: DepthBounds::Disabled,
+                    stencil_front: Default::default(),
+                    stencil_back: Default::default(),
+                })
+                .cull_mode_back()
+                .render_pass(subpass)
                 .with_pipeline_layout(device.clone(), pipeline_layout)
                 .unwrap(),
         )
     }
 
+    /// The push constants of the pipelines drawing primitives with their materials: the model
+    /// transforms for the vertex stage, followed by the material for the fragment stage. Fails if
+    /// the blocks generated from the forward shaders don't match their reflected sizes, or one of
+    /// the other fragment shaders drawing with the layout declares a different block.
+    pub fn material_push_constants(
+        vertex: &vert::Shader,
+        fragment: &frag::Shader,
+        other_fragments: &[&PipelineLayoutDesc],
+    ) -> Result<PushConstantLayout, PushConstantError> {
+        let fragment_stage = ShaderStages {
+            fragment: true,
+            ..ShaderStages::none()
+        };
+
+        let layout = PushConstantLayout::new(&[
+            StageBlock::new::<vert::ty::VertPushConstants>(
+                ShaderStages {
+                    vertex: true,
+                    ..ShaderStages::none()
+                },
+                vertex.main_entry_point().layout_desc(),
+            ),
+            StageBlock::new::<frag::ty::FragPushConstants>(fragment_stage, fragment.main_entry_point().layout_desc()),
+        ])?;
+
+        for shader in other_fragments {
+            layout.check_shader(fragment_stage, shader)?;
+        }
+
+        Ok(layout)
+    }
+
+    /// The layout shared by the pipelines drawing primitives with their materials, with the
+    /// material push constants. `fragment` must be one of the shaders they were checked against.
+    fn shaded_layout(
+        &self,
+        device: Arc<Device>,
+        shaders: &Shaders,
+        fragment: &GraphicsEntryPoint,
+    ) -> Arc<PipelineLayout> {
+        let descriptors = shaders.vertex.main_entry_point().layout_desc().union(fragment.layout_desc());
+        let layout_desc = shaders.material_push_constants.pipeline_layout_desc(&descriptors);
+
+        Arc::new(PipelineLayout::new(device, layout_desc).unwrap())
+    }
+
     fn cubemap(
         &self,
         device: Arc<Device>,
@@ -149,4 +500,67 @@ impl Pipeline {
                 .unwrap(),
         )
     }
+
+    /// A pipeline that draws a single screen-covering triangle, for post-processing passes.
+    fn fullscreen(
+        &self,
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        shaders: &Shaders,
+        fragment: GraphicsEntryPoint,
+        blend: AttachmentBlend,
+        render_pass: Arc<RenderPass>,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        self.fullscreen_subpass(
+            device,
+            dimensions,
+            shaders,
+            fragment,
+            blend,
+            Subpass::from(render_pass, 0).unwrap(),
+        )
+    }
+
+    /// A fullscreen pipeline drawn in the given subpass.
+    fn fullscreen_subpass(
+        &self,
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        shaders: &Shaders,
+        fragment: GraphicsEntryPoint,
+        blend: AttachmentBlend,
+        subpass: Subpass,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        let pipeline_layout = Arc::new(
+            PipelineLayout::new(
+                device.clone(),
+                shaders
+                    .fullscreen_vert
+                    .main_entry_point()
+                    .layout_desc()
+                    .union(fragment.layout_desc()),
+            )
+            .unwrap(),
+        );
+
+        Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(SingleBufferDefinition::<VPos>::new())
+                .vertex_shader(shaders.fullscreen_vert.main_entry_point(), ())
+                .polygon_mode_fill()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .viewports(iter::once(Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                    depth_range: 0.0..1.0,
+                }))
+                .fragment_shader(fragment, ())
+                .blend_collective(blend)
+                .depth_stencil_disabled()
+                .cull_mode_disabled()
+                .render_pass(subpass)
+                .with_pipeline_layout(device, pipeline_layout)
+                .unwrap(),
+        )
+    }
 }
@@ -0,0 +1,315 @@
+use vulkano::descriptor::descriptor::ShaderStages;
+use vulkano::pipeline::layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange};
+
+use std::any::{self, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::slice;
+
+/// The most push constant data every device supports.
+pub const MAX_PUSH_CONSTANTS_SIZE: usize = 128;
+
+/// A push constant block generated from a shader by vulkano-shaders.
+///
+/// # Safety
+///
+/// Every byte of the type must be initialised, as it's copied into the push constants. The
+/// generated structs are `repr(C)` with explicit padding fields, so have no implicit padding.
+pub unsafe trait PushConstantBlock: Copy + 'static {}
+
+#[derive(Debug)]
+pub enum PushConstantError {
+    /// The Rust type of a block isn't the size the shader declares.
+    SizeMismatch {
+        block: &'static str,
+        type_size: usize,
+        shader_size: usize,
+    },
+    /// A block doesn't start after the blocks of the stages before it.
+    Overlap { block: &'static str, start: usize },
+    /// A shader declares a block that doesn't end where its stage's range does.
+    IncompatibleShader { stages: ShaderStages, shader_size: usize },
+    /// The blocks take more than every device supports.
+    TooLarge { size: usize },
+}
+
+impl fmt::Display for PushConstantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SizeMismatch { block, type_size, shader_size } => write!(
+                f,
+                "{} is {} bytes, but the shader's block is {} bytes",
+                block, type_size, shader_size
+            ),
+            Self::Overlap { block, start } => {
+                write!(f, "{} doesn't start after the previous block, which ends at byte {}", block, start)
+            }
+            Self::IncompatibleShader { stages, shader_size } => write!(
+                f,
+                "A shader for {:?} declares a {} byte block, which doesn't match its range",
+                stages, shader_size
+            ),
+            Self::TooLarge { size } => {
+                write!(f, "The blocks take {} bytes, more than the {} supported", size, MAX_PUSH_CONSTANTS_SIZE)
+            }
+        }
+    }
+}
+
+impl Error for PushConstantError {}
+
+/// The push constant block of a stage, with the layout reflected from its shader.
+pub struct StageBlock<'a> {
+    stages: ShaderStages,
+    shader: &'a PipelineLayoutDesc,
+    type_id: TypeId,
+    type_name: &'static str,
+    type_size: usize,
+}
+
+impl<'a> StageBlock<'a> {
+    pub fn new<T: PushConstantBlock>(stages: ShaderStages, shader: &'a PipelineLayoutDesc) -> Self {
+        Self {
+            stages,
+            shader,
+            type_id: TypeId::of::<T>(),
+            type_name: any::type_name::<T>(),
+            type_size: mem::size_of::<T>(),
+        }
+    }
+}
+
+struct BlockRange {
+    range: PipelineLayoutDescPcRange,
+    type_id: TypeId,
+}
+
+/// The push constant ranges of a pipeline, with the block each stage's range is written from.
+///
+/// The blocks of the stages are laid out one after the other, with each stage's first member
+/// offset past the blocks before. vulkano-shaders reflects every block as starting at zero and
+/// ending after its last member, so each stage's range starts where the previous one ends.
+pub struct PushConstantLayout {
+    blocks: Vec<BlockRange>,
+}
+
+impl PushConstantLayout {
+    /// Derives the ranges of the blocks, in the order they're laid out, checking that each type
+    /// matches its shader.
+    pub fn new(blocks: &[StageBlock]) -> Result<Self, PushConstantError> {
+        let mut start = 0;
+        let mut ranges = Vec::with_capacity(blocks.len());
+
+        for block in blocks {
+            let shader_size = reflected_size(block.shader);
+            if block.type_size != shader_size {
+                return Err(PushConstantError::SizeMismatch {
+                    block: block.type_name,
+                    type_size: block.type_size,
+                    shader_size,
+                });
+            }
+            if shader_size <= start {
+                return Err(PushConstantError::Overlap {
+                    block: block.type_name,
+                    start,
+                });
+            }
+            if shader_size > MAX_PUSH_CONSTANTS_SIZE {
+                return Err(PushConstantError::TooLarge { size: shader_size });
+            }
+
+            ranges.push(BlockRange {
+                range: PipelineLayoutDescPcRange {
+                    offset: start,
+                    size: shader_size - start,
+                    stages: block.stages,
+                },
+                type_id: block.type_id,
+            });
+            start = shader_size;
+        }
+
+        Ok(Self { blocks: ranges })
+    }
+
+    /// Checks that another shader of a stage can be used with the layout. It must declare no
+    /// push constants, or a block ending where the stage's range does.
+    pub fn check_shader(&self, stages: ShaderStages, shader: &PipelineLayoutDesc) -> Result<(), PushConstantError> {
+        let shader_size = reflected_size(shader);
+        let range_end = self
+            .blocks
+            .iter()
+            .find(|block| block.range.stages == stages)
+            .map(|block| block.range.offset + block.range.size);
+
+        if shader_size == 0 || Some(shader_size) == range_end {
+            Ok(())
+        } else {
+            Err(PushConstantError::IncompatibleShader { stages, shader_size })
+        }
+    }
+
+    /// The size of the push constants, to the end of the last stage's range.
+    pub fn size(&self) -> usize {
+        self.blocks.last().map_or(0, |block| block.range.offset + block.range.size)
+    }
+
+    /// A pipeline layout with the descriptor sets of `desc`, and the ranges of the blocks in
+    /// place of its push constants.
+    pub fn pipeline_layout_desc(&self, desc: &PipelineLayoutDesc) -> PipelineLayoutDesc {
+        let ranges = self.blocks.iter().map(|block| block.range).collect();
+        PipelineLayoutDesc::new(desc.descriptor_sets().to_vec(), ranges).unwrap()
+    }
+}
+
+/// The push constants of one draw, written a block at a time into the ranges of a layout of
+/// `SIZE` bytes.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PushConstants<const SIZE: usize> {
+    data: [u8; SIZE],
+}
+
+impl<const SIZE: usize> PushConstants<SIZE> {
+    /// Panics if the layout's ranges don't end at `SIZE` bytes.
+    pub fn new(layout: &PushConstantLayout) -> Self {
+        assert_eq!(layout.size(), SIZE, "The push constants don't match the size of the layout");
+
+        Self { data: [0u8; SIZE] }
+    }
+
+    /// Writes the part of the block in its stage's range. The rest of the block, laid out over
+    /// the earlier stages' ranges, is ignored.
+    ///
+    /// Panics if the block isn't one of the layout's.
+    pub fn write<T: PushConstantBlock>(&mut self, layout: &PushConstantLayout, block: &T) -> &mut Self {
+        let block_range = layout
+            .blocks
+            .iter()
+            .find(|block_range| block_range.type_id == TypeId::of::<T>())
+            .unwrap_or_else(|| panic!("{} isn't a block of the layout", any::type_name::<T>()));
+        let range = block_range.range.offset..block_range.range.offset + block_range.range.size;
+
+        // The layout checked the type is the size of the range's end.
+        let bytes = unsafe { slice::from_raw_parts(block as *const T as *const u8, mem::size_of::<T>()) };
+        self.data[range.clone()].copy_from_slice(&bytes[range]);
+
+        self
+    }
+}
+
+/// The size of the block a shader declares, or zero if it has none.
+fn reflected_size(shader: &PipelineLayoutDesc) -> usize {
+    shader
+        .push_constants()
+        .iter()
+        .map(|range| range.offset + range.size)
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct Block<const SIZE: usize>([u8; SIZE]);
+
+    unsafe impl<const SIZE: usize> PushConstantBlock for Block<SIZE> {}
+
+    const VERTEX: ShaderStages = ShaderStages {
+        vertex: true,
+        ..ShaderStages::none()
+    };
+    const FRAGMENT: ShaderStages = ShaderStages {
+        fragment: true,
+        ..ShaderStages::none()
+    };
+
+    /// The layout reflected from a shader declaring a block from `offset` to `end`.
+    fn shader(stages: ShaderStages, offset: usize, end: usize) -> PipelineLayoutDesc {
+        let range = PipelineLayoutDescPcRange {
+            offset,
+            size: end - offset,
+            stages,
+        };
+        PipelineLayoutDesc::new(vec![], vec![range]).unwrap()
+    }
+
+    #[test]
+    fn blocks_are_laid_out_one_after_another() {
+        let vertex = shader(VERTEX, 0, 16);
+        let fragment = shader(FRAGMENT, 16, 48);
+        let layout = PushConstantLayout::new(&[
+            StageBlock::new::<Block<16>>(VERTEX, &vertex),
+            StageBlock::new::<Block<48>>(FRAGMENT, &fragment),
+        ])
+        .unwrap();
+
+        assert_eq!(layout.size(), 48);
+
+        let mut push_constants = PushConstants::<48>::new(&layout);
+        push_constants.write(&layout, &Block([1; 16])).write(&layout, &Block([2; 48]));
+        assert_eq!(push_constants.data[..16], [1; 16]);
+        assert_eq!(push_constants.data[16..], [2; 32]);
+    }
+
+    #[test]
+    fn block_of_the_wrong_size_is_rejected() {
+        let vertex = shader(VERTEX, 0, 32);
+
+        let result = PushConstantLayout::new(&[StageBlock::new::<Block<16>>(VERTEX, &vertex)]);
+
+        assert!(matches!(
+            result,
+            Err(PushConstantError::SizeMismatch {
+                type_size: 16,
+                shader_size: 32,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn block_overlapping_the_previous_one_is_rejected() {
+        let vertex = shader(VERTEX, 0, 32);
+        let fragment = shader(FRAGMENT, 0, 16);
+
+        let result = PushConstantLayout::new(&[
+            StageBlock::new::<Block<32>>(VERTEX, &vertex),
+            StageBlock::new::<Block<16>>(FRAGMENT, &fragment),
+        ]);
+
+        assert!(matches!(result, Err(PushConstantError::Overlap { start: 32, .. })));
+    }
+
+    #[test]
+    fn blocks_larger_than_every_device_supports_are_rejected() {
+        let vertex = shader(VERTEX, 0, 144);
+
+        let result = PushConstantLayout::new(&[StageBlock::new::<Block<144>>(VERTEX, &vertex)]);
+
+        assert!(matches!(result, Err(PushConstantError::TooLarge { size: 144 })));
+    }
+
+    #[test]
+    fn shaders_must_declare_no_block_or_one_ending_with_the_range() {
+        let vertex = shader(VERTEX, 0, 16);
+        let fragment = shader(FRAGMENT, 16, 48);
+        let layout = PushConstantLayout::new(&[
+            StageBlock::new::<Block<16>>(VERTEX, &vertex),
+            StageBlock::new::<Block<48>>(FRAGMENT, &fragment),
+        ])
+        .unwrap();
+
+        assert!(layout.check_shader(FRAGMENT, &PipelineLayoutDesc::empty()).is_ok());
+        assert!(layout.check_shader(FRAGMENT, &shader(FRAGMENT, 32, 48)).is_ok());
+        assert!(matches!(
+            layout.check_shader(FRAGMENT, &shader(FRAGMENT, 16, 64)),
+            Err(PushConstantError::IncompatibleShader { shader_size: 64, .. })
+        ));
+    }
+}
@@ -2,9 +2,12 @@ use aperture_common::VPos;
 use aperture_mesh::{ImageFormat, Texture};
 
 use image::hdr::HdrDecoder;
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ImageError, ImageResult};
 
 use std::fs::File;
 use std::io::BufReader;
+use std::path::Path;
 
 pub struct Cube {
     pub texture: Texture<f32>,
@@ -57,30 +60,63 @@ impl Cube {
         VPos { position: [ 1.0, -1.0,  1.0] },
     ];
     
-    pub fn textured() -> Self {
-        let hdr = HdrDecoder::new(
-            BufReader::new(File::open("data/images/desert_environment.hdr").unwrap())
-        )
-        .unwrap();
-
-        let (width, height) = (hdr.metadata().width, hdr.metadata().height);
-        let hdr_data = hdr.read_image_hdr().unwrap();
-
-        let mut pixel_data = vec![];
-        for pixel in hdr_data {
-            pixel_data.push(pixel.0[0]);
-            pixel_data.push(pixel.0[1]);
-            pixel_data.push(pixel.0[2]);
-            pixel_data.push(1.0);
-        }
-
-        let mut texture = Texture::default();
-        texture.name = "env_cubemap".to_string();
-        texture.format = ImageFormat::R32G32B32A32;
-        texture.pixels = pixel_data;
-        texture.width = width;
-        texture.height = height;
-
-        Self { texture }
+    /// Loads the equirectangular image the cube is textured with, from a Radiance HDR file or, for
+    /// `.exr` paths, an OpenEXR file.
+    pub fn load(path: &Path) -> ImageResult<Self> {
+        let is_exr = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+        let (width, height, pixel_data) = if is_exr { read_exr(path)? } else { read_hdr(path)? };
+
+        let texture = Texture {
+            name: "env_cubemap".to_string(),
+            format: ImageFormat::R32G32B32A32,
+            pixels: pixel_data,
+            width,
+            height,
+        };
+
+        Ok(Self { texture })
+    }
+}
+
+/// Reads a Radiance HDR image as RGBA floats.
+fn read_hdr(path: &Path) -> ImageResult<(u32, u32, Vec<f32>)> {
+    let hdr = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+
+    let (width, height) = (hdr.metadata().width, hdr.metadata().height);
+    let hdr_data = hdr.read_image_hdr()?;
+
+    let mut pixel_data = vec![];
+    for pixel in hdr_data {
+        pixel_data.push(pixel.0[0]);
+        pixel_data.push(pixel.0[1]);
+        pixel_data.push(pixel.0[2]);
+        pixel_data.push(1.0);
     }
+
+    Ok((width, height, pixel_data))
+}
+
+/// Reads the first layer of an OpenEXR image as RGBA floats. The alpha is ignored, as with HDR
+/// images.
+fn read_exr(path: &Path) -> ImageResult<(u32, u32, Vec<f32>)> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), vec![0.0; resolution.area() * 4]),
+        |(width, pixels), position, (r, g, b, _): (f32, f32, f32, f32)| {
+            let index = (position.y() * *width + position.x()) * 4;
+            pixels[index..index + 4].copy_from_slice(&[r, g, b, 1.0]);
+        },
+    )
+    .map_err(|err| match err {
+        exr::error::Error::Io(err) => ImageError::IoError(err),
+        err => ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("OpenEXR".to_string()), err)),
+    })?;
+
+    let size = image.layer_data.size;
+    let (_, pixel_data) = image.layer_data.channel_data.pixels;
+
+    Ok((size.width() as u32, size.height() as u32, pixel_data))
 }
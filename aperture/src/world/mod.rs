@@ -4,41 +4,93 @@ pub mod light;
 use aperture_mesh::*;
 use cgmath::Point3;
 
+use image::ImageResult;
+
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::mem;
 use std::path::Path;
 
+use self::cube::Cube;
 use self::light::PointLight;
 
 #[derive(Default)]
 pub struct World {
-    pub meshes: HashMap<String, Mesh>,
+    /// The meshes in the world. Names aren't unique, so several meshes may share one.
+    pub meshes: Vec<Mesh>,
     pub materials: HashMap<String, Material>,
     pub textures: HashMap<String, Texture<u8>>,
     pub default_material: Material,
     pub lights: Vec<PointLight>,
+    /// The environment map surrounding and lighting the world, if one is loaded.
+    pub environment: Option<Cube>,
 }
 
+/// The meshes, materials and textures of a model.
+type Model = (Vec<Mesh>, Vec<Material>, Vec<Texture<u8>>);
+
 impl World {
-    pub fn load_gltf<P>(&mut self, path: P)
-    where
-        P: AsRef<Path> + Clone + Debug,
-    {
-        let (meshes, materials, textures) = gltf::load(path).unwrap();
-
-        for mesh in meshes {
-            self.meshes.insert(mesh.name.clone(), mesh);
+    /// Loads a glTF or OBJ model, by the extension of its path, adding it to those in the world.
+    pub fn load_model(&mut self, path: &Path) -> Result<(), Error> {
+        let model = read_model(path)?;
+        self.add_model(model);
+
+        Ok(())
+    }
+
+    /// Loads a glTF or OBJ model in place of those in the world. If it can't be loaded, the world
+    /// is left as it was.
+    pub fn replace_models(&mut self, path: &Path) -> Result<(), Error> {
+        let model = read_model(path)?;
+
+        self.meshes.clear();
+        self.materials.clear();
+        self.textures.clear();
+        self.add_model(model);
+
+        Ok(())
+    }
+
+    /// Adds the meshes, materials and textures of a model. Their names are only unique within the
+    /// model, so those already used by another model are made unique, as the glTF loader does for
+    /// its textures, and the references to them renamed.
+    fn add_model(&mut self, (mut meshes, materials, textures): Model) {
+        let mut texture_names = HashMap::new();
+        for mut texture in textures {
+            let name = unique_name(&self.textures, &texture.name);
+            texture_names.insert(mem::replace(&mut texture.name, name.clone()), name.clone());
+            self.textures.insert(name, texture);
         }
 
-        for material in materials {
-            self.materials.insert(material.name.clone(), material);
+        let mut material_names = HashMap::new();
+        for mut material in materials {
+            let textures = &mut material.textures;
+            for texture in [
+                &mut textures.base_color,
+                &mut textures.normal,
+                &mut textures.metallic_roughness,
+                &mut textures.ao,
+                &mut textures.emissive,
+            ] {
+                rename(texture, &texture_names);
+            }
+
+            let name = unique_name(&self.materials, &material.name);
+            material_names.insert(mem::replace(&mut material.name, name.clone()), name.clone());
+            self.materials.insert(name, material);
         }
 
-        for texture in textures {
-            self.textures.insert(texture.name.clone(), texture);
+        for primitive in meshes.iter_mut().flat_map(|mesh| mesh.primitives.iter_mut()) {
+            rename(&mut primitive.material_name, &material_names);
         }
+        self.meshes.extend(meshes);
 
-        println!("Meshes: {:?}", self.meshes.keys());
+        println!(
+            "Meshes: {:?}",
+            self.meshes
+                .iter()
+                .map(|mesh| format!("{} ({} instances)", mesh.name, mesh.instances.len()))
+                .collect::<Vec<_>>()
+        );
         println!("Materials: {:?}", self.materials);
         println!("Textures: {:?}", self.textures.keys().collect::<Vec<_>>());
 
@@ -63,4 +115,124 @@ impl World {
 
         self.default_material = Material::default();
     }
+
+    /// Loads an equirectangular Radiance HDR or OpenEXR image as the environment, replacing any
+    /// other.
+    pub fn load_environment(&mut self, path: &Path) -> ImageResult<()> {
+        self.environment = Some(Cube::load(path)?);
+
+        Ok(())
+    }
+
+    /// The bounds of every primitive in world space, or `None` if the world is empty.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.meshes
+            .iter()
+            .flat_map(|mesh| {
+                mesh.instances.iter().flat_map(move |instance| {
+                    let instance = instance.lock().expect("poisoned lock").compose();
+
+                    mesh.primitives.iter().map(move |primitive| {
+                        let transform = instance * primitive.transform.lock().expect("poisoned lock").compose();
+                        primitive.aabb.transformed(&transform)
+                    })
+                })
+            })
+            .reduce(|aabb, other| aabb.union(&other))
+    }
+}
+
+/// The name, or if it's taken, the name with the first number after it that isn't.
+fn unique_name<T>(taken: &HashMap<String, T>, name: &str) -> String {
+    let mut count = 1;
+    let mut unique = name.to_string();
+
+    while taken.contains_key(&unique) {
+        unique = format!("{}_{}", name, count);
+        count += 1;
+    }
+
+    unique
+}
+
+/// Replaces a reference to a name with the name it was renamed to, if it was.
+fn rename(reference: &mut Option<String>, renamed: &HashMap<String, String>) {
+    if let Some(name) = reference {
+        if let Some(new_name) = renamed.get(name) {
+            *name = new_name.clone();
+        }
+    }
+}
+
+/// Reads a model with the loader for the extension of its path.
+fn read_model(path: &Path) -> Result<Model, Error> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("glb") | Some("gltf") => gltf::load(path),
+        Some("obj") => obj::load(path),
+        _ => Err(Error::UnsupportedFormat(path.as_os_str().to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Vector4;
+
+    /// A model of one primitive, with a material and base colour texture named as those of
+    /// another model could be.
+    fn model(color: Vector4<f32>, pixel: u8) -> Model {
+        let mesh = Mesh {
+            primitives: vec![Primitive {
+                material_name: Some("Material".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut material = Material {
+            name: "Material".to_string(),
+            base_color_factor: color,
+            ..Default::default()
+        };
+        material.textures.base_color = Some("_1".to_string());
+
+        let texture = Texture {
+            name: "_1".to_string(),
+            pixels: vec![pixel; 4],
+            width: 1,
+            height: 1,
+            ..Default::default()
+        };
+
+        (vec![mesh], vec![material], vec![texture])
+    }
+
+    #[test]
+    fn models_keep_their_own_materials_and_textures() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let blue = Vector4::new(0.0, 0.0, 1.0, 1.0);
+
+        let mut world = World::default();
+        world.add_model(model(red, 10));
+        world.add_model(model(blue, 20));
+
+        assert_eq!(world.materials.len(), 2);
+        assert_eq!(world.textures.len(), 2);
+
+        let expected = [(red, 10), (blue, 20)];
+        for (mesh, (color, pixel)) in world.meshes.iter().zip(expected) {
+            let material_name = mesh.primitives[0].material_name.as_ref().unwrap();
+            let material = &world.materials[material_name];
+            assert_eq!(material.base_color_factor, color);
+
+            let texture = &world.textures[material.textures.base_color.as_ref().unwrap()];
+            assert_eq!(texture.pixels[0], pixel);
+        }
+    }
 }
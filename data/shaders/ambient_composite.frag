@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D ambient;
+layout(set = 0, binding = 1) uniform sampler2D occlusion;
+
+layout(push_constant) uniform CompositePushConstants {
+    // Zero when the ambient occlusion pass is disabled, and the occlusion image is not written.
+    uint occlusion_enabled;
+} push_constants;
+
+// Adds the ambient lighting to the scene, blended additively, attenuated by the occlusion.
+void main() {
+    vec3 color = texture(ambient, tex_coord).rgb;
+
+    if (push_constants.occlusion_enabled != 0) {
+        color *= texture(occlusion, tex_coord).r;
+    }
+
+    f_color = vec4(color, 0.0);
+}
@@ -0,0 +1,264 @@
+#version 450
+
+#define MAX_LIGHT_COUNT 255
+
+layout(location = 0) in vec2 tex_coord;
+
+// The G-buffer, written by the previous subpass.
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput emissive;
+layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput albedo;
+layout(input_attachment_index = 2, set = 0, binding = 2) uniform subpassInput material;
+layout(input_attachment_index = 3, set = 0, binding = 3) uniform subpassInput normal;
+layout(input_attachment_index = 4, set = 0, binding = 4) uniform subpassInput normal_depth;
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    uvec4 power;
+};
+
+layout(set = 0, binding = 5) uniform Data {
+    vec4 view_pos;
+    // Diffuse irradiance of the environment, as 9 spherical harmonic coefficients.
+    vec4 irradiance[9];
+    PointLight lights[MAX_LIGHT_COUNT];
+} uniforms;
+
+layout(push_constant) uniform LightingPushConstants {
+    mat4 view;
+    // The x and y scale of the projection, for reconstructing positions from depth.
+    vec2 projection_scale;
+    uint point_light_count;
+} push_constants;
+
+// The same outputs as the forward shader, other than those already written to the G-buffer.
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec4 f_ambient;
+layout(location = 2) out vec4 f_specular;
+
+const float PI = 3.1415926538;
+
+const int POINT_LIGHT = 0;
+const int SPOT_LIGHT  = 1;
+
+const float ILLUMINANCE_FACTOR[2] = float[2](
+    4 * PI,
+    PI
+);
+
+// Calculates the Fresnel-Schlick approximation.
+//
+// This describes the amount of light that reflects from the surface given 
+// its index of refraction.
+//
+// Instead of using IoR, which is unintuitive, we use F0:
+//  - F0: the reflectance at normal incidence (angle of 0 degrees).
+//
+// For dielectrics, F0 is monochromatic, and usually between 2% and ~20%.
+// For metals, this is the "specular color", and is RGB.
+//
+vec3 F_FresnelSchlick(float cosTheta, vec3 F0) {
+    return F0 + (1.0 - F0) * pow(max(1.0 - cosTheta, 0.0), 5.0);
+}
+
+// Calculate the Smith Schlick-GGX approximation.
+//
+// This is the geometric shadowing function, and describes the shadowing
+// from material microfacets.
+//
+// Hence, roughness is accounted for in this calculation. The higher the
+// roughness, the greater the geometric shadowing.
+//
+float GeometrySchlickGGX(float NdotV, float roughness) {
+    float r = (roughness + 1.0);
+    float k = (r * r) / 8.0;
+    float denominator = NdotV * (1.0 - k) + k;
+
+    return NdotV / denominator;
+}
+
+// Smith-correlated visibility function. 
+float G_Smith(float NdotV, float NdotL, float alpha) {
+    float GGX_L = GeometrySchlickGGX(NdotV, alpha);
+    float GGX_V = GeometrySchlickGGX(NdotL, alpha);
+    
+    return 0.5 / GGX_L * GGX_V;
+}
+
+// Calculate the GGX (Trowbridge-Reitz) normal distribution.
+//
+// This Normal Distribution Function (NDF) describes the distribution
+// of microfacets for the surface that are angled so as to reflect
+// light in the direction of the view.
+//
+float D_GGX(float NdotH, float alpha) {
+    float alpha_2 = alpha * alpha;
+
+    float denominator = 
+        PI * 
+        ((NdotH * NdotH) * (alpha_2 - 1) + 1) *
+        ((NdotH * NdotH) * (alpha_2 - 1) + 1);
+
+    return alpha_2 / denominator;
+}
+
+// Calulcates the diffuse Fresnel-Schlick contribution.
+float Fd_Schlick(float u, float F0, float F90) {
+    return F0 + (F90 - F0) * pow(1.0 - u, 5.0);
+}
+
+// Calculates the Disney diffuse, with modifications for energy conservation.
+float Fd_Burley(float NdotV, float NdotL, float LdotH, float alpha) {
+    float energy_bias = mix(0.0, 0.5, alpha);
+    float energy_factor = mix(1.0, 1.0 / 1.51, alpha);
+
+    float F90 = energy_bias + 2.0 * LdotH * LdotH * alpha;
+
+    float light_scatter = Fd_Schlick(NdotL, 1.0, F90);
+    float view_scatter = Fd_Schlick(NdotV, 1.0, F90);
+
+    return light_scatter * view_scatter * energy_factor;
+}
+
+// Evaluates the irradiance of the environment for the given normal.
+// See Ramamoorthi and Hanrahan, "An Efficient Representation for Irradiance Environment Maps".
+vec3 Irradiance(vec3 N) {
+    const float c1 = 0.429043;
+    const float c2 = 0.511664;
+    const float c3 = 0.743125;
+    const float c4 = 0.886227;
+    const float c5 = 0.247708;
+
+    vec3 L[9];
+    for (int i = 0; i < 9; i++) {
+        L[i] = uniforms.irradiance[i].rgb;
+    }
+
+    vec3 irradiance =
+        c1 * L[8] * (N.x * N.x - N.y * N.y) +
+        c3 * L[6] * N.z * N.z +
+        c4 * L[0] -
+        c5 * L[6] +
+        2.0 * c1 * (L[4] * N.x * N.y + L[7] * N.x * N.z + L[5] * N.y * N.z) +
+        2.0 * c2 * (L[3] * N.x + L[1] * N.y + L[2] * N.z);
+
+    return max(irradiance, vec3(0.0));
+}
+
+// The environment BRDF, integrated over the specular lobe.
+// See Karis, "Physically Based Shading on Mobile".
+vec3 EnvironmentBRDF(vec3 specular_color, float roughness, float NdotV) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * NdotV)) * r.x + r.y;
+    vec2 AB = vec2(-1.04, 1.04) * a004 + r.zw;
+
+    return specular_color * AB.x + AB.y;
+}
+
+vec3 WorldPosition(float depth) {
+    vec2 ndc = tex_coord * 2.0 - 1.0;
+    vec3 view_position = vec3(
+        -ndc.x * depth / push_constants.projection_scale.x,
+        ndc.y * depth / push_constants.projection_scale.y,
+        -depth
+    );
+
+    mat4 view = push_constants.view;
+    return transpose(mat3(view)) * (view_position - view[3].xyz);
+}
+
+// Lights the G-buffer with the same BRDF as the forward shader.
+void main() {
+    float depth = subpassLoad(normal_depth).w;
+
+    // The background only has the environment, written as emissive.
+    if (depth <= 0.0) {
+        f_color = vec4(subpassLoad(emissive).rgb, 1.0);
+        f_ambient = vec4(0.0);
+        f_specular = vec4(0.0);
+        return;
+    }
+
+    vec3 base_color = subpassLoad(albedo).rgb;
+    vec4 surface = subpassLoad(material);
+    float metalness = surface.x;
+    float roughness = surface.y;
+    float ao = surface.z;
+    float reflectance = 0.16 * surface.w * surface.w;
+
+    mat4 view = push_constants.view;
+    vec3 frag_pos = WorldPosition(depth);
+
+    // V: view vector
+    // N: normal
+    vec3 V = normalize(vec3(uniforms.view_pos) - frag_pos);
+    vec3 N = normalize(subpassLoad(normal).xyz);
+
+    float alpha = roughness * roughness;
+
+    vec3 F0 = vec3(reflectance);
+
+    vec3 specular_color = mix(F0, base_color, metalness);
+    F0 = vec3(max(max(specular_color.r, specular_color.g), specular_color.b));
+
+    vec3 Lo = vec3(0.0);
+
+    for (uint i = 0; i < min(push_constants.point_light_count, uint(MAX_LIGHT_COUNT)); i++) {
+        PointLight light = uniforms.lights[i];
+
+        vec3 light_pos = vec3(light.position * view);
+
+        // L: incident light vector
+        // H: half vector
+        vec3 L = normalize(light_pos - frag_pos);
+        vec3 H = normalize(V + L);
+
+        float LdotH = clamp(dot(L, H), 0.0, 1.0);
+        float NdotH = clamp(dot(N, H), 0.0, 1.0);
+        float HdotV = clamp(dot(H, V), 0.0, 1.0);
+        float NdotL = clamp(dot(N, L), 0.00001, 1.0);
+        float NdotV = clamp(abs(dot(N, V)), 0.00001, 1.0);
+
+        // Specular highlights: Fresnel-Schlick
+        vec3 F = F_FresnelSchlick(HdotV, F0);
+
+        // Geometric shadowing: Smith Schlick-GGX
+        float G = G_Smith(NdotV, NdotL, roughness);
+
+        // Normal Distribution Function (NDF): GGX
+        float D = D_GGX(NdotH, alpha);
+
+        // Calulcate the specular contribution with the BRDF
+        vec3 numerator = F * G * D;
+        float denominator = 4 * NdotL + NdotL;
+        vec3 specular = clamp(numerator / denominator, 0.0, 1.0);
+        specular *= specular_color;
+
+        // Calculate the Disney diffuse contribution.
+        float F90 = 0.5 * 2.0 * roughness * LdotH * LdotH;
+        float diffuse_factor = Fd_Burley(NdotV, NdotL, LdotH, roughness); 
+        diffuse_factor *= (1.0 - metalness);
+        vec3 diffuse = diffuse_factor * base_color;
+
+        // Calulcate the radiance of this light source.
+        float distance = length(light_pos - frag_pos);
+        float attenuation = 1.0 / max(distance * distance, 0.01 * 0.01);
+        vec3 light_color = vec3(light.color) * light.power.x / ILLUMINANCE_FACTOR[POINT_LIGHT];
+        vec3 radiance = light_color * attenuation * NdotL;
+
+        Lo += (diffuse + specular) * radiance;
+    }
+
+    f_color = vec4(Lo * ao + subpassLoad(emissive).rgb, 1.0);
+
+    // Lambertian diffuse lighting from the environment.
+    vec3 ambient = Irradiance(N) / PI * base_color * (1.0 - metalness) * ao;
+    f_ambient = vec4(ambient, 1.0);
+
+    // Reflections are added after the screen-space reflection pass.
+    float NdotV = clamp(dot(N, V), 0.0, 1.0);
+    f_specular = vec4(EnvironmentBRDF(specular_color, roughness, NdotV) * ao, roughness);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec3 frag_pos;
+layout(location = 3) in mat4 view;
+
+// Only the linear depth is written, which the depth pyramid is built from.
+layout(location = 0) out vec4 f_normal_depth;
+
+void main() {
+    vec4 view_space_pos = view * vec4(frag_pos, 1.0);
+    f_normal_depth = vec4(0.0, 0.0, 0.0, -view_space_pos.z);
+}
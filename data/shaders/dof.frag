@@ -0,0 +1,102 @@
+#version 450
+
+#define SAMPLE_COUNT 96
+
+#define BOKEH_CIRCLE 0
+#define BOKEH_HEXAGON 1
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color_coc;
+layout(set = 0, binding = 1) uniform sampler2D near_coc_tiles;
+
+layout(push_constant) uniform DofPushConstants {
+    uint bokeh_shape;
+} push_constants;
+
+const float PI = 3.1415926538;
+const float GOLDEN_ANGLE = 2.39996323;
+
+// A point in the unit bokeh shape, spread over the sample count with a golden angle spiral.
+vec2 BokehOffset(int index) {
+    float t = (float(index) + 0.5) / float(SAMPLE_COUNT);
+    float angle = float(index) * GOLDEN_ANGLE;
+    float radius = sqrt(t);
+
+    if (push_constants.bokeh_shape == BOKEH_HEXAGON) {
+        // Pull the radius in to the edge of the hexagon at this angle.
+        float sector_angle = mod(angle, PI / 3.0) - PI / 6.0;
+        radius *= cos(PI / 6.0) / cos(sector_angle);
+    }
+
+    return radius * vec2(cos(angle), sin(angle));
+}
+
+// How much a sample with the given circle of confusion covers a pixel at the given distance.
+float Coverage(float coc, float distance) {
+    return clamp(abs(coc) - distance + 0.5, 0.0, 1.0);
+}
+
+void main() {
+    vec2 texel_size = 1.0 / vec2(textureSize(color_coc, 0));
+    vec4 center = texture(color_coc, tex_coord);
+
+    // Near field blur spreads over pixels around it, so the search radius must cover the largest
+    // near circle of confusion in the neighbouring tiles as well as the pixel's own.
+    vec2 tile_texel_size = 1.0 / vec2(textureSize(near_coc_tiles, 0));
+    float near_radius = 0.0;
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            near_radius = max(near_radius, texture(near_coc_tiles, tex_coord + vec2(x, y) * tile_texel_size).r);
+        }
+    }
+
+    float radius = max(abs(center.a), near_radius);
+    if (radius < 0.5) {
+        f_color = vec4(center.rgb, 1.0);
+        return;
+    }
+
+    // The in-focus and far field are gathered separately from the near field, which is composited
+    // over the top. This stops the sharp background bleeding into a blurred foreground, and lets
+    // the blurred foreground spread over sharp edges behind it.
+    vec3 far_color = vec3(0.0);
+    float far_weight = 0.0;
+    vec3 near_color = vec3(0.0);
+    float near_weight = 0.0;
+
+    for (int i = 0; i < SAMPLE_COUNT; ++i) {
+        vec2 offset = BokehOffset(i) * radius;
+        float distance = length(offset);
+
+        vec4 s = texture(color_coc, tex_coord + offset * texel_size);
+
+        // Weight samples by the inverse area of their circle of confusion, so that a blurred
+        // sample spreads its energy over the pixels it covers.
+        float area_weight = 1.0 / max(s.a * s.a, 1.0);
+
+        if (s.a < 0.0) {
+            float weight = Coverage(s.a, distance) * area_weight;
+            near_color += s.rgb * weight;
+            near_weight += weight;
+        } else {
+            // Samples behind the centre pixel can't blur over it.
+            float coc = min(s.a, max(center.a, 0.0));
+            float weight = Coverage(coc, distance) * area_weight;
+            far_color += s.rgb * weight;
+            far_weight += weight;
+        }
+    }
+
+    far_color = far_weight > 0.0 ? far_color / far_weight : center.rgb;
+    near_color = near_weight > 0.0 ? near_color / near_weight : vec3(0.0);
+
+    // The fraction of the pixel covered by the near field. A pixel surrounded by near field samples
+    // has a total weight equal to the number of samples per unit area of the kernel.
+    float sample_density = float(SAMPLE_COUNT) / (radius * radius);
+    float near_alpha = clamp(near_weight / sample_density, 0.0, 1.0);
+
+    f_color = vec4(mix(far_color, near_color, near_alpha), 1.0);
+}
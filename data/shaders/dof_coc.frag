@@ -0,0 +1,37 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+// The scene colour in rgb, and the signed circle of confusion in pixels in alpha.
+// The circle of confusion is negative in front of the focus distance, and positive behind it.
+layout(location = 0) out vec4 f_color_coc;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1) uniform sampler2D normal_depth;
+
+layout(push_constant) uniform CocPushConstants {
+    // All distances are in metres.
+    float focus_distance;
+    float focal_length;
+    float aperture_diameter;
+    // Converts a size on the camera sensor to a size on screen.
+    float pixels_per_metre;
+    float max_coc;
+} push_constants;
+
+void main() {
+    float depth = texture(normal_depth, tex_coord).w;
+
+    float focus = push_constants.focus_distance;
+    float f = push_constants.focal_length;
+    float A = push_constants.aperture_diameter;
+
+    // The background has no depth, and is treated as being infinitely far away.
+    float coc = depth > 0.0
+        ? A * f * (depth - focus) / (depth * (focus - f))
+        : A * f / (focus - f);
+
+    coc = clamp(coc * push_constants.pixels_per_metre, -push_constants.max_coc, push_constants.max_coc);
+
+    f_color_coc = vec4(texture(scene, tex_coord).rgb, coc);
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+// The largest near field circle of confusion in the tile, in pixels.
+layout(location = 0) out float f_near_coc;
+
+layout(set = 0, binding = 0) uniform sampler2D color_coc;
+
+layout(push_constant) uniform TilePushConstants {
+    uint tile_size;
+} push_constants;
+
+void main() {
+    ivec2 size = textureSize(color_coc, 0);
+    ivec2 origin = ivec2(gl_FragCoord.xy) * int(push_constants.tile_size);
+
+    float near_coc = 0.0;
+
+    for (int x = 0; x < push_constants.tile_size; ++x) {
+        for (int y = 0; y < push_constants.tile_size; ++y) {
+            ivec2 texel = min(origin + ivec2(x, y), size - 1);
+            near_coc = max(near_coc, -texelFetch(color_coc, texel, 0).a);
+        }
+    }
+
+    f_near_coc = near_coc;
+}
@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+layout(location = 0) out vec2 tex_coord;
+
+void main() {
+    tex_coord = position.xy * 0.5 + 0.5;
+    gl_Position = vec4(position.xy, 0.0, 1.0);
+}
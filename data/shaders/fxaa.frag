@@ -0,0 +1,174 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float EDGE_THRESHOLD_MAX = 0.125;
+const float SUBPIXEL_QUALITY = 0.75;
+
+const int ITERATIONS = 12;
+const float QUALITY[ITERATIONS] = float[ITERATIONS](
+    1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0
+);
+
+// Perceptual luma of a colour, estimated with a square root in place of the sRGB curve.
+float Luma(vec3 color) {
+    return sqrt(dot(clamp(color, 0.0, 1.0), vec3(0.299, 0.587, 0.114)));
+}
+
+float LumaAt(vec2 uv) {
+    return Luma(texture(scene, uv).rgb);
+}
+
+float LumaOffset(vec2 offset, vec2 inverse_size) {
+    return LumaAt(tex_coord + offset * inverse_size);
+}
+
+// Fast approximate anti-aliasing.
+//
+// Detects edges from the local luma contrast, then walks along the edge in both directions to
+// find its ends. The pixel is blended across the edge proportionally to its distance from the
+// closest end, with an additional sub-pixel blend for single-pixel features.
+//
+void main() {
+    vec3 color_center = texture(scene, tex_coord).rgb;
+    vec2 inverse_size = 1.0 / vec2(textureSize(scene, 0));
+
+    float luma_center = Luma(color_center);
+    float luma_down = LumaOffset(vec2(0.0, 1.0), inverse_size);
+    float luma_up = LumaOffset(vec2(0.0, -1.0), inverse_size);
+    float luma_left = LumaOffset(vec2(-1.0, 0.0), inverse_size);
+    float luma_right = LumaOffset(vec2(1.0, 0.0), inverse_size);
+
+    float luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    float luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    float luma_range = luma_max - luma_min;
+
+    // Skip pixels that are not on an edge, or are in a very dark area.
+    if (luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        f_color = vec4(color_center, 1.0);
+        return;
+    }
+
+    float luma_down_left = LumaOffset(vec2(-1.0, 1.0), inverse_size);
+    float luma_up_right = LumaOffset(vec2(1.0, -1.0), inverse_size);
+    float luma_up_left = LumaOffset(vec2(-1.0, -1.0), inverse_size);
+    float luma_down_right = LumaOffset(vec2(1.0, 1.0), inverse_size);
+
+    float luma_down_up = luma_down + luma_up;
+    float luma_left_right = luma_left + luma_right;
+
+    float luma_left_corners = luma_down_left + luma_up_left;
+    float luma_down_corners = luma_down_left + luma_down_right;
+    float luma_right_corners = luma_down_right + luma_up_right;
+    float luma_up_corners = luma_up_right + luma_up_left;
+
+    // Estimate whether the edge is horizontal or vertical.
+    float edge_horizontal =
+        abs(-2.0 * luma_left + luma_left_corners) +
+        abs(-2.0 * luma_center + luma_down_up) * 2.0 +
+        abs(-2.0 * luma_right + luma_right_corners);
+    float edge_vertical =
+        abs(-2.0 * luma_up + luma_up_corners) +
+        abs(-2.0 * luma_center + luma_left_right) * 2.0 +
+        abs(-2.0 * luma_down + luma_down_corners);
+    bool is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Choose the side of the pixel the edge lies on.
+    float luma_1 = is_horizontal ? luma_up : luma_left;
+    float luma_2 = is_horizontal ? luma_down : luma_right;
+    float gradient_1 = luma_1 - luma_center;
+    float gradient_2 = luma_2 - luma_center;
+
+    bool is_1_steepest = abs(gradient_1) >= abs(gradient_2);
+    float gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    float step_length = is_horizontal ? inverse_size.y : inverse_size.x;
+    float luma_local_average;
+
+    if (is_1_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_2 + luma_center);
+    }
+
+    // Shift the UV coordinate half a pixel onto the edge.
+    vec2 current_uv = tex_coord;
+    if (is_horizontal) {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+
+    // Walk along the edge in both directions until the luma delta leaves the edge.
+    vec2 offset = is_horizontal ? vec2(inverse_size.x, 0.0) : vec2(0.0, inverse_size.y);
+    vec2 uv_1 = current_uv - offset;
+    vec2 uv_2 = current_uv + offset;
+
+    float luma_end_1 = LumaAt(uv_1) - luma_local_average;
+    float luma_end_2 = LumaAt(uv_2) - luma_local_average;
+
+    bool reached_1 = abs(luma_end_1) >= gradient_scaled;
+    bool reached_2 = abs(luma_end_2) >= gradient_scaled;
+
+    if (!reached_1) {
+        uv_1 -= offset;
+    }
+    if (!reached_2) {
+        uv_2 += offset;
+    }
+
+    for (int i = 2; i < ITERATIONS && !(reached_1 && reached_2); i++) {
+        if (!reached_1) {
+            luma_end_1 = LumaAt(uv_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+        if (!reached_2) {
+            luma_end_2 = LumaAt(uv_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+        if (!reached_1) {
+            uv_1 -= offset * QUALITY[i];
+        }
+        if (!reached_2) {
+            uv_2 += offset * QUALITY[i];
+        }
+    }
+
+    float distance_1 = is_horizontal ? (tex_coord.x - uv_1.x) : (tex_coord.y - uv_1.y);
+    float distance_2 = is_horizontal ? (uv_2.x - tex_coord.x) : (uv_2.y - tex_coord.y);
+
+    bool is_direction_1 = distance_1 < distance_2;
+    float distance_final = min(distance_1, distance_2);
+    float edge_thickness = distance_1 + distance_2;
+
+    // Only blend if the luma variation at the closest end is consistent with the centre pixel.
+    bool is_luma_center_smaller = luma_center < luma_local_average;
+    bool correct_variation =
+        ((is_direction_1 ? luma_end_1 : luma_end_2) < 0.0) != is_luma_center_smaller;
+
+    float pixel_offset = correct_variation ? (-distance_final / edge_thickness + 0.5) : 0.0;
+
+    // Sub-pixel anti-aliasing, for features thinner than a pixel.
+    float luma_average = (1.0 / 12.0) * (2.0 * (luma_down_up + luma_left_right) +
+        luma_left_corners + luma_right_corners);
+    float subpixel_offset_1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    float subpixel_offset_2 = (-2.0 * subpixel_offset_1 + 3.0) * subpixel_offset_1 * subpixel_offset_1;
+    float subpixel_offset = subpixel_offset_2 * subpixel_offset_2 * SUBPIXEL_QUALITY;
+
+    pixel_offset = max(pixel_offset, subpixel_offset);
+
+    vec2 final_uv = tex_coord;
+    if (is_horizontal) {
+        final_uv.y += pixel_offset * step_length;
+    } else {
+        final_uv.x += pixel_offset * step_length;
+    }
+
+    f_color = vec4(texture(scene, final_uv).rgb, 1.0);
+}
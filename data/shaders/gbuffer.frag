@@ -0,0 +1,95 @@
+#version 450
+
+#define MAX_LIGHT_COUNT 255
+
+layout(location = 0) in vec3 frag_pos;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec2 tex_coord;
+layout(location = 3) in mat4 view;
+layout(location = 7) in vec4 current_clip_pos;
+layout(location = 8) in vec4 prev_clip_pos;
+
+layout(set = 0, binding = 1) uniform sampler2D base_color_tex;
+layout(set = 0, binding = 2) uniform sampler2D normal_tex;
+layout(set = 0, binding = 3) uniform sampler2D metal_rough_tex;
+layout(set = 0, binding = 4) uniform sampler2D ao_tex;
+layout(set = 0, binding = 6) uniform sampler2D emissive_tex;
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    uvec4 power;
+};
+
+layout(set = 0, binding = 5) uniform Data {
+    vec4 view_pos;
+    // The emissive factor of the material, multiplied by its emissive texture.
+    vec4 emissive;
+    // Fragments with a lower alpha are discarded. Zero for materials that aren't masked.
+    float alpha_cutoff;
+    // Diffuse irradiance of the environment, as 9 spherical harmonic coefficients.
+    vec4 irradiance[9];
+    PointLight lights[MAX_LIGHT_COUNT];
+} uniforms;
+
+layout(push_constant) uniform FragPushConstants {
+    layout(offset = 96) vec4 base_color;
+    float metalness;
+    float roughness;
+    float reflectance;
+    uint point_light_count;
+} push_constants;
+
+// The G-buffer. The locations of the emissive, velocity and normal and depth outputs match the
+// skybox shader, which writes the environment into the emissive attachment.
+layout(location = 0) out vec4 f_emissive;
+layout(location = 1) out vec2 f_velocity;
+layout(location = 2) out vec4 f_albedo;
+layout(location = 3) out vec4 f_normal_depth;
+// Metalness, roughness, ambient occlusion and reflectance.
+layout(location = 4) out vec4 f_material;
+layout(location = 5) out vec4 f_normal;
+
+vec3 CalculateNormal() {
+    vec3 tangentNormal = texture(normal_tex, tex_coord.xy).xyz * 2.0 - 1.0;
+
+	vec3 q1 = dFdx(frag_pos);
+	vec3 q2 = dFdy(frag_pos);
+	vec2 st1 = dFdx(tex_coord);
+	vec2 st2 = dFdy(tex_coord);
+
+	vec3 N = normalize(v_normal);
+	vec3 T = normalize(q1 * st2.t - q2 * st1.t);
+	vec3 B = -normalize(cross(N, T));
+	mat3 TBN = mat3(T, B, N);
+
+	return normalize(TBN * tangentNormal);
+}
+
+void main() {
+    vec4 base_color_alpha = texture(base_color_tex, tex_coord.xy);
+    vec3 base_color = base_color_alpha.rgb;
+    float opacity = base_color_alpha.a * push_constants.base_color.a;
+
+    if (opacity < uniforms.alpha_cutoff) {
+        discard;
+    }
+
+    float metalness = texture(metal_rough_tex, tex_coord.xy).b;
+    float roughness = texture(metal_rough_tex, tex_coord.xy).g;
+    float ao = texture(ao_tex, tex_coord.xy).r;
+    vec3 emissive = texture(emissive_tex, tex_coord.xy).rgb * uniforms.emissive.rgb;
+
+    vec3 N = CalculateNormal();
+
+    f_emissive = vec4(emissive, 1.0);
+    f_albedo = vec4(base_color, 1.0);
+    f_material = vec4(metalness, roughness, ao, clamp(push_constants.reflectance, 0.0, 1.0));
+    f_normal = vec4(N, 0.0);
+
+    vec4 view_space_pos = view * vec4(frag_pos, 1.0);
+    f_normal_depth = vec4(normalize(mat3(view) * N), -view_space_pos.z);
+
+    // Screen-space motion since the last frame, in texture coordinates.
+    f_velocity = (current_clip_pos.xy / current_clip_pos.w - prev_clip_pos.xy / prev_clip_pos.w) * 0.5;
+}
@@ -0,0 +1,73 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv_coord;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 proj;
+    mat4 view;
+    mat4 unjittered_proj;
+    mat4 prev_proj;
+    mat4 prev_view;
+} uniforms;
+
+// Transforms are the first three rows of the affine transform, as in the per-primitive path.
+struct Instance {
+    mat3x4 model;
+    mat3x4 prev_model;
+    // The draw the instance belongs to.
+    uint draw_index;
+};
+
+struct Draw {
+    // The transform of the primitive within its mesh, applied before the instance's.
+    mat3x4 model;
+    mat3x4 prev_model;
+    uint material_index;
+};
+
+// The instances of every draw, found with the first instance of each draw's command.
+layout(set = 0, binding = 1) readonly buffer Instances {
+    Instance instances[];
+};
+
+layout(set = 0, binding = 2) readonly buffer Draws {
+    Draw draws[];
+};
+
+layout(location = 0) out vec3 frag_pos;
+layout(location = 1) out vec3 v_normal;
+layout(location = 2) out vec2 tex_coord;
+layout(location = 3) out mat4 view;
+layout(location = 7) out vec4 current_clip_pos;
+layout(location = 8) out vec4 prev_clip_pos;
+layout(location = 9) flat out uint material_index;
+
+mat4 AffineFromRows(mat3x4 rows) {
+    return transpose(mat4(rows[0], rows[1], rows[2], vec4(0.0, 0.0, 0.0, 1.0)));
+}
+
+void main() {
+    Instance instance = instances[gl_InstanceIndex];
+    Draw draw = draws[instance.draw_index];
+
+    mat4 model = AffineFromRows(instance.model) * AffineFromRows(draw.model);
+    mat4 prev_model = AffineFromRows(instance.prev_model) * AffineFromRows(draw.prev_model);
+    mat4 modelview = uniforms.view * model;
+
+    gl_Position = uniforms.proj * modelview * vec4(position, 1.0);
+    gl_Position.x = -gl_Position.x;
+
+    // Unjittered clip space positions for this frame and the last, used to write motion vectors.
+    current_clip_pos = uniforms.unjittered_proj * modelview * vec4(position, 1.0);
+    current_clip_pos.x = -current_clip_pos.x;
+    prev_clip_pos = uniforms.prev_proj * uniforms.prev_view * prev_model * vec4(position, 1.0);
+    prev_clip_pos.x = -prev_clip_pos.x;
+
+    frag_pos = vec3(model * vec4(position, 1.0));
+    v_normal = transpose(inverse(mat3(model))) * normal;
+    tex_coord = uv_coord;
+    view = uniforms.view;
+    material_index = draw.material_index;
+}
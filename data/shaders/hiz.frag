@@ -0,0 +1,52 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+// The closest and furthest linear depth covered by the texel.
+layout(location = 0) out vec2 f_min_max_depth;
+
+// The previous level of the pyramid, or for the first level, the scene normals and depth.
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+layout(push_constant) uniform HiZPushConstants {
+    // Non-zero when building the first level from the scene depth.
+    uint first_level;
+} push_constants;
+
+// The background has no depth, and is treated as being infinitely far away.
+const float FAR_DEPTH = 1e6;
+
+vec2 MinMaxDepth(ivec2 texel) {
+    vec4 value = texelFetch(source, texel, 0);
+
+    if (push_constants.first_level != 0) {
+        float depth = value.w > 0.0 ? value.w : FAR_DEPTH;
+        return vec2(depth);
+    }
+
+    return value.xy;
+}
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+
+    if (push_constants.first_level != 0) {
+        f_min_max_depth = MinMaxDepth(texel);
+        return;
+    }
+
+    // Each texel covers up to 2x2 texels of the previous level. Sizes are rounded up, so the last
+    // row and column may cover fewer.
+    ivec2 source_size = textureSize(source, 0);
+    ivec2 origin = texel * 2;
+
+    vec2 result = vec2(FAR_DEPTH, 0.0);
+    for (int x = 0; x < 2; ++x) {
+        for (int y = 0; y < 2; ++y) {
+            vec2 depth = MinMaxDepth(min(origin + ivec2(x, y), source_size - 1));
+            result = vec2(min(result.x, depth.x), max(result.y, depth.y));
+        }
+    }
+
+    f_min_max_depth = result;
+}
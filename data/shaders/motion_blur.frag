@@ -0,0 +1,106 @@
+#version 450
+
+#define SAMPLE_COUNT 15
+
+// The depth range over which samples blend between foreground and background, in metres.
+#define SOFT_Z_EXTENT 0.1
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1) uniform sampler2D velocity;
+layout(set = 0, binding = 2) uniform sampler2D normal_depth;
+layout(set = 0, binding = 3) uniform sampler2D neighbour_max;
+
+layout(push_constant) uniform MotionBlurPushConstants {
+    // Converts a texture space motion vector into a blur vector in pixels.
+    vec2 velocity_scale;
+    // The longest blur vector allowed, in pixels.
+    float max_radius;
+    // Changes the sample offsets every frame, so TAA can average the noise out.
+    uint frame;
+} push_constants;
+
+// A reconstruction filter for motion blur, based on McGuire et al.
+// "A Reconstruction Filter for Plausible Motion Blur".
+
+float Depth(vec2 uv) {
+    // The background has no depth, and is treated as being infinitely far away.
+    float depth = texture(normal_depth, uv).w;
+    return depth > 0.0 ? depth : 1e6;
+}
+
+vec2 Velocity(vec2 uv) {
+    vec2 v = texture(velocity, uv).xy * push_constants.velocity_scale;
+    float speed = length(v);
+    return speed > push_constants.max_radius ? v * push_constants.max_radius / speed : v;
+}
+
+// One when the first depth is in front of the second, softened over a small range.
+float SoftDepthCompare(float depth_a, float depth_b) {
+    return clamp(1.0 - (depth_a - depth_b) / SOFT_Z_EXTENT, 0.0, 1.0);
+}
+
+float Cone(float distance, float speed) {
+    return clamp(1.0 - distance / max(speed, 1e-3), 0.0, 1.0);
+}
+
+float Cylinder(float distance, float speed) {
+    return 1.0 - smoothstep(0.95 * speed, 1.05 * speed, distance);
+}
+
+float InterleavedGradientNoise(vec2 pixel) {
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+void main() {
+    vec2 texel_size = 1.0 / vec2(textureSize(scene, 0));
+
+    vec2 max_velocity = texture(neighbour_max, tex_coord).xy;
+
+    vec3 center_color = texture(scene, tex_coord).rgb;
+
+    // Nothing nearby moves far enough to blur this pixel.
+    if (length(max_velocity) <= 0.5) {
+        f_color = vec4(center_color, 1.0);
+        return;
+    }
+
+    vec2 center_velocity = Velocity(tex_coord);
+    float center_speed = length(center_velocity);
+    float center_depth = Depth(tex_coord);
+
+    float weight = 1.0 / max(center_speed, 1.0);
+    vec3 sum = center_color * weight;
+
+    float jitter = InterleavedGradientNoise(gl_FragCoord.xy + 5.588238 * float(push_constants.frame % 64)) - 0.5;
+
+    // Sample along the longest motion in the neighbourhood, so that fast objects blur over
+    // the slower pixels around them.
+    for (int i = 0; i < SAMPLE_COUNT; ++i) {
+        float t = mix(-1.0, 1.0, (float(i) + jitter + 1.0) / float(SAMPLE_COUNT + 1));
+        vec2 offset = max_velocity * t;
+        float distance = length(offset);
+
+        vec2 sample_uv = tex_coord + offset * texel_size;
+        float sample_depth = Depth(sample_uv);
+        float sample_speed = length(Velocity(sample_uv));
+
+        float foreground = SoftDepthCompare(sample_depth, center_depth);
+        float background = SoftDepthCompare(center_depth, sample_depth);
+
+        // The sample is in front and blurs over the centre, or is behind and visible through the
+        // centre's blur, or both are blurred along each other.
+        float sample_weight =
+            foreground * Cone(distance, sample_speed) +
+            background * Cone(distance, center_speed) +
+            Cylinder(distance, sample_speed) * Cylinder(distance, center_speed) * 2.0;
+
+        sum += texture(scene, sample_uv).rgb * sample_weight;
+        weight += sample_weight;
+    }
+
+    f_color = vec4(sum / weight, 1.0);
+}
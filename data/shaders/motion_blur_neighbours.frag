@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+// The longest blur vector in the surrounding tiles, in pixels.
+layout(location = 0) out vec2 f_max_velocity;
+
+layout(set = 0, binding = 0) uniform sampler2D tile_max;
+
+void main() {
+    ivec2 size = textureSize(tile_max, 0);
+    ivec2 tile = ivec2(gl_FragCoord.xy);
+
+    vec2 max_velocity = vec2(0.0);
+
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            ivec2 neighbour = clamp(tile + ivec2(x, y), ivec2(0), size - 1);
+            vec2 v = texelFetch(tile_max, neighbour, 0).xy;
+
+            if (dot(v, v) > dot(max_velocity, max_velocity)) {
+                max_velocity = v;
+            }
+        }
+    }
+
+    f_max_velocity = max_velocity;
+}
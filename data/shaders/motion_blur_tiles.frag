@@ -0,0 +1,41 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+// The longest blur vector in the tile, in pixels.
+layout(location = 0) out vec2 f_max_velocity;
+
+layout(set = 0, binding = 0) uniform sampler2D velocity;
+
+layout(push_constant) uniform TilePushConstants {
+    // Converts a texture space motion vector into a blur vector in pixels.
+    vec2 velocity_scale;
+    // The longest blur vector allowed, in pixels.
+    float max_radius;
+    uint tile_size;
+} push_constants;
+
+void main() {
+    ivec2 size = textureSize(velocity, 0);
+    ivec2 origin = ivec2(gl_FragCoord.xy) * int(push_constants.tile_size);
+
+    vec2 max_velocity = vec2(0.0);
+
+    for (int x = 0; x < push_constants.tile_size; ++x) {
+        for (int y = 0; y < push_constants.tile_size; ++y) {
+            ivec2 texel = min(origin + ivec2(x, y), size - 1);
+            vec2 v = texelFetch(velocity, texel, 0).xy * push_constants.velocity_scale;
+
+            if (dot(v, v) > dot(max_velocity, max_velocity)) {
+                max_velocity = v;
+            }
+        }
+    }
+
+    float speed = length(max_velocity);
+    if (speed > push_constants.max_radius) {
+        max_velocity *= push_constants.max_radius / speed;
+    }
+
+    f_max_velocity = max_velocity;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D accum_tex;
+layout(set = 0, binding = 1) uniform sampler2D revealage_tex;
+
+void main() {
+    float revealage = texture(revealage_tex, tex_coord).r;
+
+    // Nothing transparent covers this pixel.
+    if (revealage >= 1.0) {
+        discard;
+    }
+
+    vec4 accum = texture(accum_tex, tex_coord);
+
+    // Prevent the sum overflowing with many overlapping, heavily weighted surfaces.
+    if (isinf(max(max(abs(accum.r), abs(accum.g)), abs(accum.b)))) {
+        accum.rgb = vec3(accum.a);
+    }
+
+    // The weighted average colour, blended over the scene by the combined coverage.
+    vec3 average_color = accum.rgb / max(accum.a, 1e-5);
+    f_color = vec4(average_color, 1.0 - revealage);
+}
@@ -6,11 +6,64 @@ layout(location = 0) in vec3 frag_pos;
 layout(location = 1) in vec3 v_normal;
 layout(location = 2) in vec2 tex_coord;
 layout(location = 3) in mat4 view;
+layout(location = 7) in vec4 current_clip_pos;
+layout(location = 8) in vec4 prev_clip_pos;
 
+#ifdef GPU_DRIVEN
+#define MAX_TEXTURES 128
+
+// Every draw's material is read from the materials by index, and its textures from the textures
+// by index. The index is the same for a whole draw, so it's dynamically uniform.
+layout(location = 9) flat in uint material_index;
+
+struct Material {
+    vec4 base_color;
+    // The emissive factor, multiplied by the emissive texture.
+    vec3 emissive;
+    uint emissive_tex;
+    float metalness;
+    float roughness;
+    float reflectance;
+    float alpha_cutoff;
+    uint base_color_tex;
+    uint normal_tex;
+    uint metal_rough_tex;
+    uint ao_tex;
+};
+
+layout(set = 0, binding = 3) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(set = 0, binding = 4) uniform sampler2D textures[MAX_TEXTURES];
+
+#define MATERIAL materials[material_index]
+#define BASE_COLOR_TEX textures[MATERIAL.base_color_tex]
+#define NORMAL_TEX textures[MATERIAL.normal_tex]
+#define METAL_ROUGH_TEX textures[MATERIAL.metal_rough_tex]
+#define AO_TEX textures[MATERIAL.ao_tex]
+#define EMISSIVE_TEX textures[MATERIAL.emissive_tex]
+#define BASE_COLOR MATERIAL.base_color
+#define REFLECTANCE MATERIAL.reflectance
+#define EMISSIVE MATERIAL.emissive
+#define ALPHA_CUTOFF MATERIAL.alpha_cutoff
+#else
 layout(set = 0, binding = 1) uniform sampler2D base_color_tex;
 layout(set = 0, binding = 2) uniform sampler2D normal_tex;
 layout(set = 0, binding = 3) uniform sampler2D metal_rough_tex;
 layout(set = 0, binding = 4) uniform sampler2D ao_tex;
+layout(set = 0, binding = 6) uniform sampler2D emissive_tex;
+
+#define BASE_COLOR_TEX base_color_tex
+#define NORMAL_TEX normal_tex
+#define METAL_ROUGH_TEX metal_rough_tex
+#define AO_TEX ao_tex
+#define EMISSIVE_TEX emissive_tex
+#define BASE_COLOR push_constants.base_color
+#define REFLECTANCE push_constants.reflectance
+#define EMISSIVE uniforms.emissive
+#define ALPHA_CUTOFF uniforms.alpha_cutoff
+#endif
 
 struct PointLight {
     vec4 position;
@@ -20,18 +73,40 @@ struct PointLight {
 
 layout(set = 0, binding = 5) uniform Data {
     vec4 view_pos;
+    // The emissive factor of the material, multiplied by its emissive texture. Unused by the
+    // GPU-driven path.
+    vec4 emissive;
+    // Fragments with a lower alpha are discarded. Zero for materials that aren't masked.
+    float alpha_cutoff;
+    // Diffuse irradiance of the environment, as 9 spherical harmonic coefficients.
+    vec4 irradiance[9];
     PointLight lights[MAX_LIGHT_COUNT];
 } uniforms;
 
+#ifndef GPU_DRIVEN
 layout(push_constant) uniform FragPushConstants {
-    layout(offset = 64) vec4 base_color;
+    layout(offset = 96) vec4 base_color;
     float metalness;
     float roughness;
     float reflectance;
     uint point_light_count;
 } push_constants;
-
+#endif
+
+#ifdef WEIGHTED_BLENDED
+// The weighted sum of the premultiplied colours and alphas, and the product of the transmittance of
+// every surface. See McGuire and Bavoil, "Weighted Blended Order-Independent Transparency".
+layout(location = 0) out vec4 f_accum;
+layout(location = 1) out float f_revealage;
+#else
 layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+// Ambient lighting is written separately, so it can be attenuated by the ambient occlusion pass.
+layout(location = 2) out vec4 f_ambient;
+layout(location = 3) out vec4 f_normal_depth;
+// The weight of the specular reflections in rgb, and the roughness in alpha.
+layout(location = 4) out vec4 f_specular;
+#endif
 
 const float PI = 3.1415926538;
 
@@ -120,8 +195,46 @@ float Fd_Burley(float NdotV, float NdotL, float LdotH, float alpha) {
     return light_scatter * view_scatter * energy_factor;
 }
 
+// Evaluates the irradiance of the environment for the given normal.
+// See Ramamoorthi and Hanrahan, "An Efficient Representation for Irradiance Environment Maps".
+vec3 Irradiance(vec3 N) {
+    const float c1 = 0.429043;
+    const float c2 = 0.511664;
+    const float c3 = 0.743125;
+    const float c4 = 0.886227;
+    const float c5 = 0.247708;
+
+    vec3 L[9];
+    for (int i = 0; i < 9; i++) {
+        L[i] = uniforms.irradiance[i].rgb;
+    }
+
+    vec3 irradiance =
+        c1 * L[8] * (N.x * N.x - N.y * N.y) +
+        c3 * L[6] * N.z * N.z +
+        c4 * L[0] -
+        c5 * L[6] +
+        2.0 * c1 * (L[4] * N.x * N.y + L[7] * N.x * N.z + L[5] * N.y * N.z) +
+        2.0 * c2 * (L[3] * N.x + L[1] * N.y + L[2] * N.z);
+
+    return max(irradiance, vec3(0.0));
+}
+
+// The environment BRDF, integrated over the specular lobe.
+// See Karis, "Physically Based Shading on Mobile".
+vec3 EnvironmentBRDF(vec3 specular_color, float roughness, float NdotV) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * NdotV)) * r.x + r.y;
+    vec2 AB = vec2(-1.04, 1.04) * a004 + r.zw;
+
+    return specular_color * AB.x + AB.y;
+}
+
 vec3 CalculateNormal() {
-    vec3 tangentNormal = texture(normal_tex, tex_coord.xy).xyz * 2.0 - 1.0;
+    vec3 tangentNormal = texture(NORMAL_TEX, tex_coord.xy).xyz * 2.0 - 1.0;
 
 	vec3 q1 = dFdx(frag_pos);
 	vec3 q2 = dFdy(frag_pos);
@@ -139,12 +252,20 @@ vec3 CalculateNormal() {
 void main() {
     vec3 result = vec3(0.0, 0.0, 0.0);
 
-    vec3 base_color = texture(base_color_tex, tex_coord.xy).rgb;
-    float metalness = texture(metal_rough_tex, tex_coord.xy).b;
-    float roughness = texture(metal_rough_tex, tex_coord.xy).g;
-    float ao = texture(ao_tex, tex_coord.xy).r;
+    vec4 base_color_alpha = texture(BASE_COLOR_TEX, tex_coord.xy);
+    vec3 base_color = base_color_alpha.rgb;
+    float opacity = base_color_alpha.a * BASE_COLOR.a;
+
+    if (opacity < ALPHA_CUTOFF) {
+        discard;
+    }
 
-    float reflectance_clamped = clamp(push_constants.reflectance, 0.0, 1.0);
+    float metalness = texture(METAL_ROUGH_TEX, tex_coord.xy).b;
+    float roughness = texture(METAL_ROUGH_TEX, tex_coord.xy).g;
+    float ao = texture(AO_TEX, tex_coord.xy).r;
+    vec3 emissive = texture(EMISSIVE_TEX, tex_coord.xy).rgb * EMISSIVE.rgb;
+
+    float reflectance_clamped = clamp(REFLECTANCE, 0.0, 1.0);
     float reflectance = 0.16 * reflectance_clamped * reflectance_clamped;
 
     // V: view vector
@@ -207,7 +328,42 @@ void main() {
         Lo += (diffuse + specular) * radiance;
     }
 
-    vec3 color = Lo * ao;
-
+    vec3 color = Lo * ao + emissive;
+
+    // Lambertian diffuse lighting from the environment.
+    vec3 ambient = Irradiance(N) / PI * base_color * (1.0 - metalness) * ao;
+
+#ifdef TRANSPARENT
+    // Transparent surfaces are blended over the finished scene, so only the colour is written, and
+    // it includes the ambient lighting and environment reflections added to opaque surfaces after
+    // the scene pass. The reflections are approximated with the irradiance.
+    vec3 R = reflect(-V, N);
+    vec3 reflection = Irradiance(R) / PI * EnvironmentBRDF(specular_color, roughness, clamp(dot(N, V), 0.0, 1.0)) * ao;
+    vec3 transparent_color = color + ambient + reflection;
+
+#ifdef WEIGHTED_BLENDED
+    // Weight nearer and more opaque surfaces more heavily, so they dominate the average.
+    float weight = clamp(pow(min(1.0, opacity * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - gl_FragCoord.z * 0.9, 3.0), 1e-2, 3e3);
+    f_accum = vec4(transparent_color * opacity, opacity) * weight;
+    f_revealage = opacity;
+#else
+    f_color = vec4(transparent_color, opacity);
+#endif
+#else
     f_color = vec4(color, 1.0);
+#endif
+
+#ifndef WEIGHTED_BLENDED
+    f_ambient = vec4(ambient, 1.0);
+
+    vec4 view_space_pos = view * vec4(frag_pos, 1.0);
+    f_normal_depth = vec4(normalize(mat3(view) * N), -view_space_pos.z);
+
+    // Reflections are added after the screen-space reflection pass.
+    float NdotV = clamp(dot(N, V), 0.0, 1.0);
+    f_specular = vec4(EnvironmentBRDF(specular_color, roughness, NdotV) * ao, roughness);
+
+    // Screen-space motion since the last frame, in texture coordinates.
+    f_velocity = (current_clip_pos.xy / current_clip_pos.w - prev_clip_pos.xy / prev_clip_pos.w) * 0.5;
+#endif
 }
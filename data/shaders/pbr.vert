@@ -4,28 +4,61 @@ layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 uv_coord;
 
+// The per-instance transforms this frame and last, as the first three rows of each.
+layout(location = 3) in vec4 instance_model_0;
+layout(location = 4) in vec4 instance_model_1;
+layout(location = 5) in vec4 instance_model_2;
+layout(location = 6) in vec4 instance_prev_model_0;
+layout(location = 7) in vec4 instance_prev_model_1;
+layout(location = 8) in vec4 instance_prev_model_2;
+
 layout(set = 0, binding = 0) uniform Data {
     mat4 proj;
     mat4 view;
+    mat4 unjittered_proj;
+    mat4 prev_proj;
+    mat4 prev_view;
 } uniforms;
 
+// The transform of the primitive within its mesh, applied before the instance's. Model matrices
+// are passed as the first three rows of the affine transform, to fit both the current and
+// previous frame's transform in the guaranteed push constant space.
 layout(push_constant) uniform VertPushConstants {
-    mat4 model;
+    mat3x4 model;
+    mat3x4 prev_model;
 } push_constants;
 
 layout(location = 0) out vec3 frag_pos;
 layout(location = 1) out vec3 v_normal;
 layout(location = 2) out vec2 tex_coord;
 layout(location = 3) out mat4 view;
+layout(location = 7) out vec4 current_clip_pos;
+layout(location = 8) out vec4 prev_clip_pos;
+
+mat4 AffineFromRows(mat3x4 rows) {
+    return transpose(mat4(rows[0], rows[1], rows[2], vec4(0.0, 0.0, 0.0, 1.0)));
+}
 
 void main() {
-    mat4 modelview = uniforms.view * push_constants.model;
+    mat4 instance = AffineFromRows(mat3x4(instance_model_0, instance_model_1, instance_model_2));
+    mat4 prev_instance =
+        AffineFromRows(mat3x4(instance_prev_model_0, instance_prev_model_1, instance_prev_model_2));
+
+    mat4 model = instance * AffineFromRows(push_constants.model);
+    mat4 prev_model = prev_instance * AffineFromRows(push_constants.prev_model);
+    mat4 modelview = uniforms.view * model;
 
     gl_Position = uniforms.proj * modelview * vec4(position, 1.0);
     gl_Position.x = -gl_Position.x;
 
-    frag_pos = vec3(push_constants.model * vec4(position, 1.0));
-    v_normal = transpose(inverse(mat3(push_constants.model))) * normal;
+    // Unjittered clip space positions for this frame and the last, used to write motion vectors.
+    current_clip_pos = uniforms.unjittered_proj * modelview * vec4(position, 1.0);
+    current_clip_pos.x = -current_clip_pos.x;
+    prev_clip_pos = uniforms.prev_proj * uniforms.prev_view * prev_model * vec4(position, 1.0);
+    prev_clip_pos.x = -prev_clip_pos.x;
+
+    frag_pos = vec3(model * vec4(position, 1.0));
+    v_normal = transpose(inverse(mat3(model))) * normal;
     tex_coord = uv_coord;
     view = uniforms.view;
 }
@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+void main() {
+    f_color = vec4(texture(scene, tex_coord).rgb, 1.0);
+}
@@ -1,8 +1,14 @@
 # version 450
 
 layout(location = 0) in vec3 local_pos;
+layout(location = 1) in vec4 current_clip_pos;
+layout(location = 2) in vec4 prev_clip_pos;
 
 layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+layout(location = 2) out vec4 f_ambient;
+layout(location = 3) out vec4 f_normal_depth;
+layout(location = 4) out vec4 f_specular;
 
 layout(set = 0, binding = 1) uniform samplerCube environment_map;
 
@@ -13,4 +19,10 @@ void main() {
     env_color = env_color / (env_color + vec3(1.0));
 
     f_color = vec4(pow(env_color, vec3(1.0 / 2.2)), 1.0);
+    f_velocity = (current_clip_pos.xy / current_clip_pos.w - prev_clip_pos.xy / prev_clip_pos.w) * 0.5;
+
+    // The background receives no ambient lighting, and has no depth for ambient occlusion.
+    f_ambient = vec4(0.0);
+    f_normal_depth = vec4(0.0);
+    f_specular = vec4(0.0);
 }
@@ -5,9 +5,14 @@ layout(location = 0) in vec3 position;
 layout(set = 0, binding = 0) uniform Data {
     mat4 proj;
     mat4 view;
+    mat4 unjittered_proj;
+    mat4 prev_proj;
+    mat4 prev_view;
 } uniforms;
 
 layout(location = 0) out vec3 local_pos;
+layout(location = 1) out vec4 current_clip_pos;
+layout(location = 2) out vec4 prev_clip_pos;
 
 void main() {
     local_pos = position;
@@ -16,4 +21,8 @@ void main() {
     vec4 clipped_pos = uniforms.proj * view_rotation * vec4(local_pos, 1.0);
 
     gl_Position = clipped_pos.xyww;
+
+    // The skybox only moves with the camera rotation.
+    current_clip_pos = uniforms.unjittered_proj * view_rotation * vec4(local_pos, 1.0);
+    prev_clip_pos = uniforms.prev_proj * mat4(mat3(uniforms.prev_view)) * vec4(local_pos, 1.0);
 }
@@ -0,0 +1,101 @@
+#version 450
+
+#define SAMPLE_COUNT 16
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out float f_occlusion;
+
+// View space normals in xyz, and linear view depth in w. A depth of zero marks the background.
+layout(set = 0, binding = 0) uniform sampler2D normal_depth;
+
+layout(push_constant) uniform SsaoPushConstants {
+    mat4 proj;
+    // The view space radius of the hemisphere sampled around each pixel.
+    float radius;
+    // The exponent applied to the result, darkening occluded areas further.
+    float intensity;
+    // Changes the sample pattern every frame, so TAA can average the noise out.
+    uint frame;
+} push_constants;
+
+const float PI = 3.1415926538;
+
+vec3 ViewPosition(vec2 uv, float depth) {
+    vec2 ndc = uv * 2.0 - 1.0;
+    return vec3(
+        -ndc.x * depth / push_constants.proj[0][0],
+        ndc.y * depth / push_constants.proj[1][1],
+        -depth
+    );
+}
+
+vec2 ProjectToUv(vec3 position) {
+    vec4 clip = push_constants.proj * vec4(position, 1.0);
+    clip.x = -clip.x;
+    return clip.xy / clip.w * 0.5 + 0.5;
+}
+
+float InterleavedGradientNoise(vec2 pixel) {
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+// A cosine-weighted point in the unit hemisphere around +z, spread over the sample count with a
+// golden angle spiral. Samples are scaled so more of them are taken close to the surface.
+vec3 HemisphereSample(int index, float rotation) {
+    float t = (float(index) + 0.5) / float(SAMPLE_COUNT);
+    float angle = float(index) * 2.39996323 + rotation * 2.0 * PI;
+    float r = sqrt(t);
+
+    vec3 direction = vec3(r * cos(angle), r * sin(angle), sqrt(1.0 - t));
+    float scale = mix(0.1, 1.0, t * t);
+
+    return direction * scale;
+}
+
+void main() {
+    vec4 center = texture(normal_depth, tex_coord);
+
+    if (center.w <= 0.0) {
+        f_occlusion = 1.0;
+        return;
+    }
+
+    vec3 position = ViewPosition(tex_coord, center.w);
+    vec3 normal = normalize(center.xyz);
+
+    float noise = InterleavedGradientNoise(gl_FragCoord.xy + 5.588238 * float(push_constants.frame % 64));
+
+    // Build a basis around the normal, rotated per pixel by the noise.
+    vec3 random = vec3(cos(noise * 2.0 * PI), sin(noise * 2.0 * PI), 0.0);
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    if (any(isnan(tangent))) {
+        tangent = normalize(cross(normal, vec3(0.0, 1.0, 0.0)));
+    }
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float bias = 0.02 * push_constants.radius;
+    float occlusion = 0.0;
+
+    for (int i = 0; i < SAMPLE_COUNT; ++i) {
+        vec3 sample_position = position + tbn * HemisphereSample(i, noise) * push_constants.radius;
+        vec2 sample_uv = ProjectToUv(sample_position);
+
+        if (any(lessThan(sample_uv, vec2(0.0))) || any(greaterThan(sample_uv, vec2(1.0)))) {
+            continue;
+        }
+
+        float sample_depth = texture(normal_depth, sample_uv).w;
+        if (sample_depth <= 0.0) {
+            continue;
+        }
+
+        // Fade out occluders far outside the radius, so silhouettes don't darken the background.
+        float scene_z = -sample_depth;
+        float range_check = smoothstep(0.0, 1.0, push_constants.radius / abs(position.z - scene_z));
+        occlusion += (scene_z >= sample_position.z + bias ? 1.0 : 0.0) * range_check;
+    }
+
+    float visibility = 1.0 - occlusion / float(SAMPLE_COUNT);
+    f_occlusion = pow(visibility, push_constants.intensity);
+}
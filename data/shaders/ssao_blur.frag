@@ -0,0 +1,39 @@
+#version 450
+
+#define BLUR_RADIUS 2
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out float f_occlusion;
+
+layout(set = 0, binding = 0) uniform sampler2D occlusion;
+layout(set = 0, binding = 1) uniform sampler2D normal_depth;
+
+void main() {
+    vec2 texel_size = 1.0 / vec2(textureSize(occlusion, 0));
+    float center_depth = texture(normal_depth, tex_coord).w;
+
+    if (center_depth <= 0.0) {
+        f_occlusion = 1.0;
+        return;
+    }
+
+    float total = 0.0;
+    float total_weight = 0.0;
+
+    // Average the noisy occlusion over the neighbourhood, ignoring pixels at a different depth
+    // so occlusion doesn't bleed across edges.
+    for (int x = -BLUR_RADIUS; x <= BLUR_RADIUS; ++x) {
+        for (int y = -BLUR_RADIUS; y <= BLUR_RADIUS; ++y) {
+            vec2 uv = tex_coord + vec2(x, y) * texel_size;
+            float depth = texture(normal_depth, uv).w;
+
+            float weight = max(0.0, 1.0 - abs(center_depth - depth) / (0.1 * center_depth));
+
+            total += texture(occlusion, uv).r * weight;
+            total_weight += weight;
+        }
+    }
+
+    f_occlusion = total / total_weight;
+}
@@ -0,0 +1,151 @@
+#version 450
+
+#define HIZ_LEVELS 8
+#define MAX_STEPS 96
+
+layout(location = 0) in vec2 tex_coord;
+
+// The reflected radiance in rgb, and how confident the trace is of it in alpha.
+layout(location = 0) out vec4 f_reflection;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1) uniform sampler2D normal_depth;
+layout(set = 0, binding = 2) uniform sampler2D specular;
+// The levels of the depth pyramid, holding the closest depth in x.
+layout(set = 0, binding = 3) uniform sampler2D hiz0;
+layout(set = 0, binding = 4) uniform sampler2D hiz1;
+layout(set = 0, binding = 5) uniform sampler2D hiz2;
+layout(set = 0, binding = 6) uniform sampler2D hiz3;
+layout(set = 0, binding = 7) uniform sampler2D hiz4;
+layout(set = 0, binding = 8) uniform sampler2D hiz5;
+layout(set = 0, binding = 9) uniform sampler2D hiz6;
+layout(set = 0, binding = 10) uniform sampler2D hiz7;
+
+layout(push_constant) uniform SsrPushConstants {
+    mat4 proj;
+    // The furthest distance a reflected ray is traced, in metres.
+    float max_distance;
+    // How far behind a surface a ray can be and still count as hitting it, in metres.
+    float thickness;
+} push_constants;
+
+// Rough surfaces reflect the blurred environment, so aren't worth tracing.
+const float MAX_ROUGHNESS = 0.8;
+
+const float NEAR_PLANE = 0.1;
+
+float ClosestDepth(int level, ivec2 cell) {
+    switch (level) {
+        case 0: return texelFetch(hiz0, cell, 0).x;
+        case 1: return texelFetch(hiz1, cell, 0).x;
+        case 2: return texelFetch(hiz2, cell, 0).x;
+        case 3: return texelFetch(hiz3, cell, 0).x;
+        case 4: return texelFetch(hiz4, cell, 0).x;
+        case 5: return texelFetch(hiz5, cell, 0).x;
+        case 6: return texelFetch(hiz6, cell, 0).x;
+        default: return texelFetch(hiz7, cell, 0).x;
+    }
+}
+
+vec3 ViewPosition(vec2 uv, float depth) {
+    vec2 ndc = uv * 2.0 - 1.0;
+    return vec3(
+        -ndc.x * depth / push_constants.proj[0][0],
+        ndc.y * depth / push_constants.proj[1][1],
+        -depth
+    );
+}
+
+vec2 ProjectToPixel(vec3 position, vec2 size) {
+    vec4 clip = push_constants.proj * vec4(position, 1.0);
+    clip.x = -clip.x;
+    return (clip.xy / clip.w * 0.5 + 0.5) * size;
+}
+
+void main() {
+    vec4 material = texture(specular, tex_coord);
+    vec4 center = texture(normal_depth, tex_coord);
+    float roughness = material.a;
+
+    if (center.w <= 0.0 || roughness > MAX_ROUGHNESS || material.rgb == vec3(0.0)) {
+        f_reflection = vec4(0.0);
+        return;
+    }
+
+    vec2 size = vec2(textureSize(normal_depth, 0));
+
+    vec3 origin = ViewPosition(tex_coord, center.w);
+    vec3 normal = normalize(center.xyz);
+    vec3 direction = reflect(normalize(origin), normal);
+
+    // Stop the ray before it passes behind the camera.
+    float ray_length = push_constants.max_distance;
+    if (origin.z + direction.z * ray_length > -NEAR_PLANE) {
+        ray_length = (-NEAR_PLANE - origin.z) / direction.z;
+    }
+    vec3 end = origin + direction * ray_length;
+
+    // March in screen space, interpolating the inverse depth, which is linear in screen space.
+    vec2 start_pixel = ProjectToPixel(origin, size);
+    vec2 end_pixel = ProjectToPixel(end, size);
+    float start_inv_depth = 1.0 / -origin.z;
+    float end_inv_depth = 1.0 / -end.z;
+
+    vec2 delta = end_pixel - start_pixel;
+    delta = mix(delta, vec2(1e-5), lessThan(abs(delta), vec2(1e-5)));
+
+    // Clip the ray to the screen.
+    vec2 screen_exit = (mix(vec2(0.0), size, greaterThan(delta, vec2(0.0))) - start_pixel) / delta;
+    float t_max = min(1.0, min(screen_exit.x, screen_exit.y));
+
+    // Start a pixel away, so the ray doesn't hit the surface it starts on.
+    float t = 1.5 / max(abs(delta.x), abs(delta.y));
+    int level = 0;
+    bool hit = false;
+
+    for (int i = 0; i < MAX_STEPS && t < t_max; ++i) {
+        vec2 pixel = start_pixel + delta * t;
+        ivec2 cell = ivec2(pixel) >> level;
+
+        // Find where the ray leaves the current cell.
+        float cell_size = float(1 << level);
+        vec2 cell_min = vec2(cell) * cell_size;
+        vec2 cell_bound = mix(cell_min, cell_min + cell_size, greaterThan(delta, vec2(0.0)));
+        vec2 cell_exit = (cell_bound - start_pixel) / delta;
+        float t_exit = min(min(cell_exit.x, cell_exit.y) + 1e-4, t_max);
+
+        float entry_depth = 1.0 / mix(start_inv_depth, end_inv_depth, t);
+        float exit_depth = 1.0 / mix(start_inv_depth, end_inv_depth, t_exit);
+        float closest = ClosestDepth(level, cell);
+
+        if (max(entry_depth, exit_depth) < closest) {
+            // The ray passes in front of everything in the cell, so skip it and try a larger one.
+            t = t_exit;
+            level = min(level + 1, HIZ_LEVELS - 1);
+        } else if (level > 0) {
+            level -= 1;
+        } else if (min(entry_depth, exit_depth) - closest < push_constants.thickness) {
+            hit = true;
+            break;
+        } else {
+            // The ray passes behind the surface.
+            t = t_exit;
+        }
+    }
+
+    if (!hit) {
+        f_reflection = vec4(0.0);
+        return;
+    }
+
+    vec2 hit_uv = (start_pixel + delta * t) / size;
+
+    // Fade out towards the edge of the screen, the end of the ray, and at high roughness, so the
+    // environment fallback blends in smoothly.
+    vec2 edge_distance = min(hit_uv, 1.0 - hit_uv);
+    float edge_fade = clamp(min(edge_distance.x, edge_distance.y) * 10.0, 0.0, 1.0);
+    float distance_fade = 1.0 - clamp(t * 2.0 - 1.0, 0.0, 1.0);
+    float roughness_fade = 1.0 - smoothstep(0.5 * MAX_ROUGHNESS, MAX_ROUGHNESS, roughness);
+
+    f_reflection = vec4(texture(scene, hit_uv).rgb, edge_fade * distance_fade * roughness_fade);
+}
@@ -0,0 +1,113 @@
+#version 450
+
+#define BLUR_SAMPLES 12
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D reflection;
+layout(set = 0, binding = 1) uniform sampler2D normal_depth;
+layout(set = 0, binding = 2) uniform sampler2D specular;
+
+layout(set = 1, binding = 0) uniform samplerCube environment_map;
+
+layout(push_constant) uniform CompositePushConstants {
+    mat4 view;
+    // The x and y scale of the projection, for reconstructing view space positions.
+    vec2 projection_scale;
+    // Zero when the screen-space reflections are disabled, and the reflection image is not written.
+    uint reflections_enabled;
+    // The blur radius of the reflections at full roughness, in pixels.
+    float max_blur_radius;
+} push_constants;
+
+const float PI = 3.1415926538;
+const float GOLDEN_ANGLE = 2.39996323;
+
+vec3 ViewPosition(vec2 uv, float depth) {
+    vec2 ndc = uv * 2.0 - 1.0;
+    return vec3(
+        -ndc.x * depth / push_constants.projection_scale.x,
+        ndc.y * depth / push_constants.projection_scale.y,
+        -depth
+    );
+}
+
+// Averages the environment over a cone around the reflection, widening with roughness.
+vec3 Environment(vec3 direction, float roughness) {
+    vec3 color = texture(environment_map, direction).rgb;
+
+    float spread = roughness * roughness;
+    if (spread < 0.01) {
+        return color;
+    }
+
+    vec3 up = abs(direction.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, direction));
+    vec3 bitangent = cross(direction, tangent);
+
+    for (int i = 0; i < 8; ++i) {
+        float angle = float(i) * PI / 4.0;
+        vec3 offset = (cos(angle) * tangent + sin(angle) * bitangent) * spread;
+        color += texture(environment_map, normalize(direction + offset)).rgb;
+    }
+
+    return color / 9.0;
+}
+
+// Blurs the traced reflections by roughness, ignoring pixels at a different depth.
+vec4 BlurredReflection(float roughness, float center_depth) {
+    vec2 texel_size = 1.0 / vec2(textureSize(reflection, 0));
+    float radius = roughness * push_constants.max_blur_radius;
+
+    vec4 center = texture(reflection, tex_coord);
+    vec3 color = center.rgb * center.a;
+    float confidence = center.a;
+    float weight = 1.0;
+
+    if (radius >= 1.0) {
+        for (int i = 0; i < BLUR_SAMPLES; ++i) {
+            float t = (float(i) + 0.5) / float(BLUR_SAMPLES);
+            float angle = float(i) * GOLDEN_ANGLE;
+            vec2 uv = tex_coord + sqrt(t) * radius * vec2(cos(angle), sin(angle)) * texel_size;
+
+            float depth = texture(normal_depth, uv).w;
+            float depth_weight = max(0.0, 1.0 - abs(center_depth - depth) / (0.1 * center_depth));
+
+            vec4 s = texture(reflection, uv);
+            color += s.rgb * s.a * depth_weight;
+            confidence += s.a * depth_weight;
+            weight += depth_weight;
+        }
+    }
+
+    return vec4(confidence > 0.0 ? color / confidence : vec3(0.0), confidence / weight);
+}
+
+// Adds the specular reflections to the scene, blended additively. Where the screen-space trace
+// missed, the environment is reflected instead.
+void main() {
+    vec4 material = texture(specular, tex_coord);
+    vec4 center = texture(normal_depth, tex_coord);
+
+    if (center.w <= 0.0 || material.rgb == vec3(0.0)) {
+        f_color = vec4(0.0);
+        return;
+    }
+
+    float roughness = material.a;
+
+    vec3 position = ViewPosition(tex_coord, center.w);
+    vec3 view_direction = reflect(normalize(position), normalize(center.xyz));
+    vec3 world_direction = transpose(mat3(push_constants.view)) * view_direction;
+
+    vec3 color = Environment(world_direction, roughness);
+
+    if (push_constants.reflections_enabled != 0) {
+        vec4 traced = BlurredReflection(roughness, center.w);
+        color = mix(color, traced.rgb, traced.a);
+    }
+
+    f_color = vec4(color * material.rgb, 0.0);
+}
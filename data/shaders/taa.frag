@@ -0,0 +1,104 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1) uniform sampler2D velocity;
+layout(set = 0, binding = 2) uniform sampler2D history;
+
+layout(push_constant) uniform TaaPushConstants {
+    // The fraction of the history kept each frame.
+    float feedback;
+    // Non-zero when the history doesn't contain a previous frame, e.g. after a resize.
+    uint reset;
+} push_constants;
+
+vec3 RgbToYCoCg(vec3 color) {
+    return vec3(
+        dot(color, vec3(0.25, 0.5, 0.25)),
+        dot(color, vec3(0.5, 0.0, -0.5)),
+        dot(color, vec3(-0.25, 0.5, -0.25))
+    );
+}
+
+vec3 YCoCgToRgb(vec3 color) {
+    return vec3(
+        color.x + color.y - color.z,
+        color.x + color.z,
+        color.x - color.y - color.z
+    );
+}
+
+// Compress HDR values before blending, so single bright samples don't dominate the history.
+vec3 Tonemap(vec3 color) {
+    return color / (1.0 + max(color.r, max(color.g, color.b)));
+}
+
+vec3 InverseTonemap(vec3 color) {
+    return color / max(1.0 - max(color.r, max(color.g, color.b)), 1e-4);
+}
+
+vec3 SampleScene(vec2 uv) {
+    return RgbToYCoCg(Tonemap(texture(scene, uv).rgb));
+}
+
+// Clips the history towards the centre of the neighbourhood bounding box.
+// This avoids the colour shifts caused by clamping each channel separately.
+vec3 ClipToAabb(vec3 color, vec3 aabb_min, vec3 aabb_max) {
+    vec3 center = 0.5 * (aabb_max + aabb_min);
+    vec3 extents = 0.5 * (aabb_max - aabb_min) + 1e-5;
+
+    vec3 offset = color - center;
+    vec3 units = abs(offset / extents);
+    float max_unit = max(units.x, max(units.y, units.z));
+
+    return max_unit > 1.0 ? center + offset / max_unit : color;
+}
+
+void main() {
+    vec3 current = SampleScene(tex_coord);
+
+    if (push_constants.reset != 0) {
+        f_color = vec4(texture(scene, tex_coord).rgb, 1.0);
+        return;
+    }
+
+    vec2 texel_size = 1.0 / vec2(textureSize(scene, 0));
+
+    // Gather the colour bounds of the 3x3 neighbourhood, and use the longest motion vector in it
+    // so the edges of moving objects reproject with the object rather than the background.
+    vec3 neighbourhood_min = current;
+    vec3 neighbourhood_max = current;
+    vec2 motion = vec2(0.0);
+
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            vec2 uv = tex_coord + vec2(x, y) * texel_size;
+
+            vec3 neighbour = SampleScene(uv);
+            neighbourhood_min = min(neighbourhood_min, neighbour);
+            neighbourhood_max = max(neighbourhood_max, neighbour);
+
+            vec2 neighbour_motion = texture(velocity, uv).xy;
+            if (dot(neighbour_motion, neighbour_motion) > dot(motion, motion)) {
+                motion = neighbour_motion;
+            }
+        }
+    }
+
+    vec2 history_uv = tex_coord - motion;
+
+    // Disocclusion at the edge of the screen, there is no history to use.
+    if (any(lessThan(history_uv, vec2(0.0))) || any(greaterThan(history_uv, vec2(1.0)))) {
+        f_color = vec4(texture(scene, tex_coord).rgb, 1.0);
+        return;
+    }
+
+    vec3 previous = RgbToYCoCg(Tonemap(texture(history, history_uv).rgb));
+    previous = ClipToAabb(previous, neighbourhood_min, neighbourhood_max);
+
+    vec3 result = mix(current, previous, push_constants.feedback);
+    f_color = vec4(InverseTonemap(YCoCgToRgb(result)), 1.0);
+}